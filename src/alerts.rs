@@ -0,0 +1,441 @@
+use crate::app_options;
+use crate::miner_handle::MinerHandle;
+use crate::stats::{self, StatsSnapshot};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Time between evaluations of the rules
+const EVALUATE_INTERVAL: Duration = Duration::from_secs(10);
+/// The rejected ratio is taken over the shares of this window
+const RATIO_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Fewer shares in RATIO_WINDOW don't say much about the rejected ratio
+const RATIO_MIN_SHARES: u64 = 10;
+
+#[derive(
+    serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub const ALL: [Severity; 3] = [Severity::Info, Severity::Warning, Severity::Critical];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::Info => "Info",
+            Severity::Warning => "Warning",
+            Severity::Critical => "Critical",
+        }
+    }
+}
+
+/// What an alert rule watches for
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "condition", rename_all = "snake_case")]
+pub enum Condition {
+    /// The total hashrate stayed below the threshold for the duration
+    HashrateBelow {
+        mhs: f64,
+        minutes: u32,
+    },
+    /// The percentage of rejected shares over the last hour
+    RejectedRatioAbove {
+        percent: f64,
+    },
+    NoAcceptedShare {
+        minutes: u32,
+    },
+    /// Only reported when ethminer runs with --HWMON
+    GpuTemperatureAbove {
+        gpu: usize,
+        celsius: u32,
+    },
+}
+
+impl Condition {
+    /// The conditions with default thresholds, to choose from in the rules editor
+    pub fn defaults() -> [Condition; 4] {
+        [
+            Condition::HashrateBelow {
+                mhs: 10.0,
+                minutes: 10,
+            },
+            Condition::RejectedRatioAbove { percent: 10.0 },
+            Condition::NoAcceptedShare { minutes: 30 },
+            Condition::GpuTemperatureAbove {
+                gpu: 0,
+                celsius: 80,
+            },
+        ]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Condition::HashrateBelow { .. } => "Hashrate below",
+            Condition::RejectedRatioAbove { .. } => "Rejected shares above",
+            Condition::NoAcceptedShare { .. } => "No accepted share",
+            Condition::GpuTemperatureAbove { .. } => "GPU temperature above",
+        }
+    }
+
+    /// How long the condition has to hold before the rule fires
+    fn duration(&self) -> Duration {
+        match self {
+            Condition::HashrateBelow { minutes, .. } => Duration::from_secs(*minutes as u64 * 60),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct AlertRule {
+    #[serde(flatten)]
+    pub condition: Condition,
+    pub severity: Severity,
+    /// Minimum time between two alerts of the rule
+    pub cooldown_minutes: u32,
+}
+
+impl Default for AlertRule {
+    fn default() -> Self {
+        AlertRule {
+            condition: Condition::defaults()[0].clone(),
+            severity: Severity::Warning,
+            cooldown_minutes: 60,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alert {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Clone, Default)]
+struct RuleState {
+    /// When the condition started to hold
+    since: Option<Instant>,
+    fired: Option<Instant>,
+}
+
+/// Evaluates the rules against the statistics over time
+#[derive(Default)]
+pub struct Evaluator {
+    rules: Vec<AlertRule>,
+    states: Vec<RuleState>,
+    /// Accepted and rejected share counts of the last RATIO_WINDOW
+    shares: VecDeque<(Instant, u64, u64)>,
+}
+
+impl Evaluator {
+    /// Returns the alerts of the rules that fire now
+    pub fn evaluate(
+        &mut self,
+        rules: &[AlertRule],
+        stats: &StatsSnapshot,
+        running: bool,
+        now: Instant,
+    ) -> Vec<Alert> {
+        if rules != self.rules {
+            self.rules = rules.to_vec();
+            self.states = vec![RuleState::default(); rules.len()];
+        }
+        let rejected_ratio = self.rejected_ratio(stats, now);
+        let mut alerts = Vec::new();
+        for (rule, state) in self.rules.iter().zip(&mut self.states) {
+            // A stopped miner isn't mining badly
+            let message = match running {
+                true => check(&rule.condition, stats, rejected_ratio),
+                false => None,
+            };
+            let message = match message {
+                Some(message) => message,
+                None => {
+                    state.since = None;
+                    continue;
+                }
+            };
+            let since = *state.since.get_or_insert(now);
+            if now.duration_since(since) < rule.condition.duration() {
+                continue;
+            }
+            let cooldown = Duration::from_secs(rule.cooldown_minutes as u64 * 60);
+            if state
+                .fired
+                .is_some_and(|fired| now.duration_since(fired) < cooldown)
+            {
+                continue;
+            }
+            state.fired = Some(now);
+            alerts.push(Alert {
+                severity: rule.severity,
+                message,
+            });
+        }
+        alerts
+    }
+
+    /// The fraction of the shares of the last RATIO_WINDOW that were rejected
+    fn rejected_ratio(&mut self, stats: &StatsSnapshot, now: Instant) -> Option<f64> {
+        // The statistics were reset
+        if self.shares.back().is_some_and(|(_, accepted, rejected)| {
+            *accepted > stats.accepted || *rejected > stats.rejected
+        }) {
+            self.shares.clear();
+        }
+        self.shares.push_back((now, stats.accepted, stats.rejected));
+        while self
+            .shares
+            .front()
+            .is_some_and(|(t, _, _)| now.duration_since(*t) > RATIO_WINDOW)
+        {
+            self.shares.pop_front();
+        }
+        let (_, accepted, rejected) = self.shares.front()?;
+        let (accepted, rejected) = (stats.accepted - accepted, stats.rejected - rejected);
+        if accepted + rejected < RATIO_MIN_SHARES {
+            return None;
+        }
+        stats::accepted_ratio(accepted, rejected).map(|ratio| 1.0 - ratio)
+    }
+}
+
+/// The message of the alert if the condition holds
+fn check(
+    condition: &Condition,
+    stats: &StatsSnapshot,
+    rejected_ratio: Option<f64>,
+) -> Option<String> {
+    match *condition {
+        Condition::HashrateBelow { mhs, minutes } => {
+            let threshold = mhs * 1e6;
+            (stats.current_hashrate < threshold).then(|| {
+                format!(
+                    "Hashrate {} has been below {} for {minutes} minutes",
+                    stats::format_hashrate(stats.current_hashrate),
+                    stats::format_hashrate(threshold)
+                )
+            })
+        }
+        Condition::RejectedRatioAbove { percent } => {
+            let ratio = rejected_ratio? * 100.0;
+            (ratio > percent)
+                .then(|| format!("{ratio:.1}% of the shares of the last hour were rejected"))
+        }
+        Condition::NoAcceptedShare { minutes } => {
+            let secs = stats.last_accepted_secs.unwrap_or(stats.uptime_secs);
+            (secs >= minutes as u64 * 60)
+                .then(|| format!("No share was accepted for {} minutes", secs / 60))
+        }
+        Condition::GpuTemperatureAbove { gpu, celsius } => {
+            let temperature = stats.gpus.iter().find(|g| g.index == gpu)?.temperature?;
+            (temperature > celsius)
+                .then(|| format!("GPU {gpu} is at {temperature}°C, above {celsius}°C"))
+        }
+    }
+}
+
+/// Evaluates the alert rules of the options, see app_options::follow
+pub fn start(miner: &MinerHandle) {
+    let mut evaluator = Evaluator::default();
+    app_options::follow(
+        miner,
+        Duration::ZERO,
+        EVALUATE_INTERVAL,
+        move |miner, options| {
+            let running = miner.state().running;
+            let alerts = evaluator.evaluate(
+                &options.alert_rules,
+                &miner.stats(),
+                running,
+                Instant::now(),
+            );
+            async move {
+                for alert in alerts {
+                    miner.raise_alert(alert).await;
+                }
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output_parser::GpuStatus;
+
+    fn rule(condition: Condition, cooldown_minutes: u32) -> AlertRule {
+        AlertRule {
+            condition,
+            severity: Severity::Warning,
+            cooldown_minutes,
+        }
+    }
+
+    fn minutes(start: Instant, minutes: u64) -> Instant {
+        start + Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn test_hashrate_below() {
+        let rules = [rule(
+            Condition::HashrateBelow {
+                mhs: 20.0,
+                minutes: 10,
+            },
+            30,
+        )];
+        let mut evaluator = Evaluator::default();
+        let start = Instant::now();
+        let mut stats = StatsSnapshot {
+            current_hashrate: 15e6,
+            ..Default::default()
+        };
+        assert!(evaluator.evaluate(&rules, &stats, true, start).is_empty());
+        assert!(evaluator
+            .evaluate(&rules, &stats, true, minutes(start, 9))
+            .is_empty());
+        let alerts = evaluator.evaluate(&rules, &stats, true, minutes(start, 10));
+        assert_eq!(
+            alerts,
+            [Alert {
+                severity: Severity::Warning,
+                message: "Hashrate 15.00 MH/s has been below 20.00 MH/s for 10 minutes".to_string()
+            }]
+        );
+        // Cooling down
+        assert!(evaluator
+            .evaluate(&rules, &stats, true, minutes(start, 30))
+            .is_empty());
+        assert_eq!(
+            evaluator
+                .evaluate(&rules, &stats, true, minutes(start, 40))
+                .len(),
+            1
+        );
+
+        // Recovering restarts the duration
+        stats.current_hashrate = 25e6;
+        assert!(evaluator
+            .evaluate(&rules, &stats, true, minutes(start, 80))
+            .is_empty());
+        stats.current_hashrate = 15e6;
+        assert!(evaluator
+            .evaluate(&rules, &stats, true, minutes(start, 81))
+            .is_empty());
+        assert!(evaluator
+            .evaluate(&rules, &stats, false, minutes(start, 95))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rejected_ratio_above() {
+        let rules = [rule(Condition::RejectedRatioAbove { percent: 10.0 }, 60)];
+        let mut evaluator = Evaluator::default();
+        let start = Instant::now();
+        let mut stats = StatsSnapshot {
+            accepted: 1000,
+            rejected: 200,
+            ..Default::default()
+        };
+        // Only the shares of the last hour count
+        assert!(evaluator.evaluate(&rules, &stats, true, start).is_empty());
+        // Too few shares to tell
+        stats.accepted += 5;
+        stats.rejected += 2;
+        assert!(evaluator
+            .evaluate(&rules, &stats, true, minutes(start, 10))
+            .is_empty());
+        stats.accepted += 16;
+        stats.rejected += 1;
+        assert_eq!(
+            evaluator.evaluate(&rules, &stats, true, minutes(start, 20)),
+            [Alert {
+                severity: Severity::Warning,
+                message: "12.5% of the shares of the last hour were rejected".to_string()
+            }]
+        );
+        // The rejected shares are older than an hour now
+        stats.accepted += 20;
+        stats.rejected += 1;
+        assert!(evaluator
+            .evaluate(&rules, &stats, true, minutes(start, 90))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_no_accepted_share() {
+        let rules = [rule(Condition::NoAcceptedShare { minutes: 30 }, 60)];
+        let mut evaluator = Evaluator::default();
+        let now = Instant::now();
+        let mut stats = StatsSnapshot {
+            uptime_secs: 20 * 60,
+            ..Default::default()
+        };
+        assert!(evaluator.evaluate(&rules, &stats, true, now).is_empty());
+        stats.uptime_secs = 45 * 60;
+        stats.last_accepted_secs = Some(5 * 60);
+        assert!(evaluator.evaluate(&rules, &stats, true, now).is_empty());
+        stats.last_accepted_secs = Some(31 * 60);
+        assert_eq!(
+            evaluator.evaluate(&rules, &stats, true, now)[0].message,
+            "No share was accepted for 31 minutes"
+        );
+    }
+
+    #[test]
+    fn test_gpu_temperature_above() {
+        let rules = [AlertRule {
+            condition: Condition::GpuTemperatureAbove {
+                gpu: 1,
+                celsius: 80,
+            },
+            severity: Severity::Critical,
+            cooldown_minutes: 0,
+        }];
+        let mut evaluator = Evaluator::default();
+        let now = Instant::now();
+        let gpu = |index, temperature| GpuStatus {
+            index,
+            hashrate: 30e6,
+            temperature,
+            fan: None,
+        };
+        let mut stats = StatsSnapshot {
+            gpus: vec![gpu(0, Some(90)), gpu(1, Some(70))],
+            ..Default::default()
+        };
+        assert!(evaluator.evaluate(&rules, &stats, true, now).is_empty());
+        stats.gpus[1].temperature = Some(85);
+        assert_eq!(
+            evaluator.evaluate(&rules, &stats, true, now),
+            [Alert {
+                severity: Severity::Critical,
+                message: "GPU 1 is at 85°C, above 80°C".to_string()
+            }]
+        );
+        // Without --HWMON there are no temperatures
+        stats.gpus[1].temperature = None;
+        assert!(evaluator.evaluate(&rules, &stats, true, now).is_empty());
+    }
+
+    #[test]
+    fn test_rules_serialization() {
+        let rules = vec![
+            AlertRule::default(),
+            rule(Condition::RejectedRatioAbove { percent: 5.0 }, 15),
+        ];
+        let json = serde_json::to_string(&rules).unwrap();
+        assert!(json.contains(r#""condition":"rejected_ratio_above","percent":5.0"#));
+        assert_eq!(
+            serde_json::from_str::<Vec<AlertRule>>(&json).unwrap(),
+            rules
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use eframe::egui::Color32;
+use std::ops::Range;
+
+/// A colour set by an ANSI SGR escape sequence
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AnsiColor {
+    /// Index into the 256 colour xterm palette, where 0-7 are the standard
+    /// colours and 8-15 their bright variants
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// The 16 standard terminal colours, tuned to be readable on a dark background
+const BASIC_PALETTE: [(u8, u8, u8); 16] = [
+    (40, 40, 40),
+    (205, 60, 60),
+    (80, 190, 80),
+    (215, 175, 60),
+    (80, 130, 230),
+    (190, 90, 200),
+    (60, 180, 190),
+    (200, 200, 200),
+    (120, 120, 120),
+    (255, 100, 100),
+    (120, 240, 120),
+    (250, 220, 100),
+    (120, 170, 255),
+    (230, 130, 240),
+    (100, 225, 235),
+    (255, 255, 255),
+];
+
+impl AnsiColor {
+    /// Converts to an egui colour. Bold text uses the bright variant of the
+    /// standard colours, like most terminals do.
+    pub fn to_color32(self, bold: bool) -> Color32 {
+        match self {
+            AnsiColor::Indexed(i) => {
+                let i = if bold && i < 8 { i + 8 } else { i };
+                let (r, g, b) = match i {
+                    0..=15 => BASIC_PALETTE[i as usize],
+                    // 6x6x6 colour cube
+                    16..=231 => {
+                        let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+                        let i = i - 16;
+                        (level(i / 36), level((i / 6) % 6), level(i % 6))
+                    }
+                    // Grayscale ramp
+                    232..=255 => {
+                        let v = 8 + (i - 232) * 10;
+                        (v, v, v)
+                    }
+                };
+                Color32::from_rgb(r, g, b)
+            }
+            AnsiColor::Rgb(r, g, b) => Color32::from_rgb(r, g, b),
+        }
+    }
+
+    /// The SGR parameters setting the colour, with base 30 for the foreground and 40 for
+    /// the background
+    fn sgr(self, base: u8) -> String {
+        match self {
+            AnsiColor::Indexed(i @ 0..=7) => (base + i).to_string(),
+            AnsiColor::Indexed(i @ 8..=15) => (base + 60 + i - 8).to_string(),
+            AnsiColor::Indexed(i) => format!("{};5;{i}", base + 8),
+            AnsiColor::Rgb(r, g, b) => format!("{};2;{r};{g};{b}", base + 8),
+        }
+    }
+}
+
+/// Text attributes, as set by ANSI SGR escape sequences
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct AnsiStyle {
+    pub fg: Option<AnsiColor>,
+    pub bg: Option<AnsiColor>,
+    pub bold: bool,
+    pub underline: bool,
+}
+
+impl AnsiStyle {
+    /// Applies the parameters of a single SGR sequence, "ESC [ params m"
+    fn apply_sgr(&mut self, params: &str) {
+        let codes: Vec<u16> = params
+            .split([';', ':'])
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                0 => *self = AnsiStyle::default(),
+                1 => self.bold = true,
+                4 => self.underline = true,
+                22 => self.bold = false,
+                24 => self.underline = false,
+                c @ 30..=37 => self.fg = Some(AnsiColor::Indexed((c - 30) as u8)),
+                39 => self.fg = None,
+                c @ 40..=47 => self.bg = Some(AnsiColor::Indexed((c - 40) as u8)),
+                49 => self.bg = None,
+                c @ 90..=97 => self.fg = Some(AnsiColor::Indexed((c - 90 + 8) as u8)),
+                c @ 100..=107 => self.bg = Some(AnsiColor::Indexed((c - 100 + 8) as u8)),
+                c @ (38 | 48) => {
+                    // Extended colours: 38;5;n or 38;2;r;g;b
+                    let (color, used) = match codes.get(i + 1) {
+                        Some(5) => (
+                            codes.get(i + 2).map(|n| AnsiColor::Indexed(*n as u8)),
+                            2,
+                        ),
+                        Some(2) => match (codes.get(i + 2), codes.get(i + 3), codes.get(i + 4)) {
+                            (Some(r), Some(g), Some(b)) => {
+                                (Some(AnsiColor::Rgb(*r as u8, *g as u8, *b as u8)), 4)
+                            }
+                            _ => (None, codes.len()),
+                        },
+                        _ => (None, codes.len()),
+                    };
+                    if c == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                    i += used;
+                }
+                // Dim, italics, blinking etc. are not rendered
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    /// The SGR sequence that sets the style, starting from the default style
+    fn sgr(&self) -> String {
+        let mut params = Vec::new();
+        if self.bold {
+            params.push("1".to_string());
+        }
+        if self.underline {
+            params.push("4".to_string());
+        }
+        params.extend(self.fg.map(|fg| fg.sgr(30)));
+        params.extend(self.bg.map(|bg| bg.sgr(40)));
+        format!("\x1b[{}m", params.join(";"))
+    }
+}
+
+/// A run of text with a single style, as a byte range of StyledLine::text
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StyledSpan {
+    pub range: Range<usize>,
+    pub style: AnsiStyle,
+}
+
+/// A line of miner output with the escape codes removed.
+/// The plain text is kept for searching and exporting,
+/// and the spans store how each part of it was styled.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct StyledLine {
+    pub text: String,
+    /// Only styled runs of text are stored, unstyled text has no span
+    pub spans: Vec<StyledSpan>,
+}
+
+impl StyledLine {
+    /// A line without any styling, used for messages from the controller
+    pub fn plain(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            spans: Vec::new(),
+        }
+    }
+
+    /// Parses a line containing ANSI escape sequences.
+    /// SGR sequences are turned into spans, any other escape sequence is dropped.
+    pub fn parse(line: &str) -> Self {
+        let mut out = StyledLine::default();
+        let mut style = AnsiStyle::default();
+        let mut span_start = 0;
+        let mut chars = line.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                out.text.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('[') => {
+                    // Control Sequence: parameter bytes, then a single final byte
+                    let mut params = String::new();
+                    let mut final_byte = None;
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            final_byte = Some(c);
+                            break;
+                        }
+                        params.push(c);
+                    }
+                    if final_byte == Some('m') {
+                        let mut new_style = style;
+                        new_style.apply_sgr(&params);
+                        if new_style != style {
+                            out.push_span(span_start..out.text.len(), style);
+                            span_start = out.text.len();
+                            style = new_style;
+                        }
+                    }
+                }
+                // Two character escape sequences, e.g. "ESC ( B"
+                Some('(') | Some(')') => {
+                    chars.next();
+                }
+                _ => {}
+            }
+        }
+        out.push_span(span_start..out.text.len(), style);
+        out
+    }
+
+    fn push_span(&mut self, range: Range<usize>, style: AnsiStyle) {
+        if !range.is_empty() && style != AnsiStyle::default() {
+            self.spans.push(StyledSpan { range, style });
+        }
+    }
+
+    /// The line with escape sequences for its styles, which parses back to the same line
+    pub fn to_ansi(&self) -> String {
+        let mut out = String::with_capacity(self.text.len());
+        let mut end = 0;
+        for span in &self.spans {
+            out.push_str(&self.text[end..span.range.start]);
+            out.push_str(&span.style.sgr());
+            out.push_str(&self.text[span.range.clone()]);
+            out.push_str("\x1b[0m");
+            end = span.range.end;
+        }
+        out.push_str(&self.text[end..]);
+        out
+    }
+
+    /// Returns the style of the text at the byte offset
+    pub fn style_at(&self, offset: usize) -> AnsiStyle {
+        self.spans
+            .iter()
+            .find(|s| s.range.contains(&offset))
+            .map(|s| s.style)
+            .unwrap_or_default()
+    }
+
+    /// Byte offsets at which the style changes, including the start and end of the text
+    pub fn boundaries(&self) -> Vec<usize> {
+        let mut out = vec![0, self.text.len()];
+        for span in &self.spans {
+            out.push(span.range.start);
+            out.push(span.range.end);
+        }
+        out.sort_unstable();
+        out.dedup();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fg(i: u8) -> AnsiStyle {
+        AnsiStyle {
+            fg: Some(AnsiColor::Indexed(i)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plain_line() {
+        let line = StyledLine::parse(" i 17:29:36 ethminer Selected pool eu1.ethermine.org:4444");
+        assert_eq!(
+            line.text,
+            " i 17:29:36 ethminer Selected pool eu1.ethermine.org:4444"
+        );
+        assert!(line.spans.is_empty());
+    }
+
+    #[test]
+    fn test_channel_tags() {
+        // Ethminer colours the channel tag of each line, and resets after it
+        let line = StyledLine::parse("\x1b[34m i\x1b[0m 17:29:36 \x1b[35methminer\x1b[0m Epoch : 401");
+        assert_eq!(line.text, " i 17:29:36 ethminer Epoch : 401");
+        assert_eq!(
+            line.spans,
+            vec![
+                StyledSpan {
+                    range: 0..2,
+                    style: fg(4)
+                },
+                StyledSpan {
+                    range: 12..20,
+                    style: fg(5)
+                },
+            ]
+        );
+        assert_eq!(line.style_at(1), fg(4));
+        assert_eq!(line.style_at(5), AnsiStyle::default());
+        assert_eq!(line.boundaries(), vec![0, 2, 12, 20, 32]);
+    }
+
+    #[test]
+    fn test_accepted_and_rejected_shares() {
+        let accepted =
+            StyledLine::parse(" i 17:30:06 ethminer \x1b[1;32m**Accepted\x1b[0m  48 ms. eu1.ethermine.org:4444");
+        assert_eq!(
+            accepted.text,
+            " i 17:30:06 ethminer **Accepted  48 ms. eu1.ethermine.org:4444"
+        );
+        let style = accepted.style_at(21);
+        assert_eq!(style.fg, Some(AnsiColor::Indexed(2)));
+        assert!(style.bold);
+        assert_eq!(accepted.spans[0].range, 21..31);
+
+        let rejected =
+            StyledLine::parse(" X 17:30:07 ethminer \x1b[31m**Rejected\x1b[0m  48 ms. eu1.ethermine.org:4444");
+        assert_eq!(rejected.style_at(21), fg(1));
+        assert!(!rejected.style_at(21).bold);
+    }
+
+    #[test]
+    fn test_high_intensity_and_underline() {
+        // "0;9xm" high intensity colours, "4;3xm" underlined colours
+        let line = StyledLine::parse("\x1b[0;92mfast\x1b[4;33mlink\x1b[24mtext\x1b[39mdone");
+        assert_eq!(line.text, "fastlinktextdone");
+        assert_eq!(line.style_at(0), fg(10));
+        let link = line.style_at(4);
+        assert_eq!(link.fg, Some(AnsiColor::Indexed(3)));
+        assert!(link.underline);
+        assert_eq!(line.style_at(8), fg(3));
+        assert_eq!(line.style_at(12), AnsiStyle::default());
+        assert_eq!(line.spans.len(), 3);
+    }
+
+    #[test]
+    fn test_extended_colours_and_backgrounds() {
+        let line = StyledLine::parse("\x1b[38;5;208morange\x1b[48;2;10;20;30mrgb\x1b[49;39mx");
+        assert_eq!(line.style_at(0), fg(208));
+        assert_eq!(line.style_at(6).bg, Some(AnsiColor::Rgb(10, 20, 30)));
+        assert_eq!(line.style_at(6).fg, Some(AnsiColor::Indexed(208)));
+        assert_eq!(line.style_at(9), AnsiStyle::default());
+    }
+
+    #[test]
+    fn test_other_sequences_are_dropped() {
+        // Erase line, cursor movement and charset selection carry no text
+        let line = StyledLine::parse("\x1b[2K\x1b[1Gprogress\x1b(B 50%\x1b[");
+        assert_eq!(line.text, "progress 50%");
+        assert!(line.spans.is_empty());
+    }
+
+    #[test]
+    fn test_to_ansi() {
+        let accepted = "\x1b[1;32m**Accepted\x1b[0m  48 ms. eu1.ethermine.org:4444";
+        assert_eq!(StyledLine::parse(accepted).to_ansi(), accepted);
+        for raw in [
+            "\x1b[34m i\x1b[0m 17:29:36 \x1b[35methminer\x1b[0m Epoch : 401",
+            "\x1b[0;92mfast\x1b[4;33mlink\x1b[24mtext\x1b[39mdone",
+            "\x1b[38;5;208morange\x1b[48;2;10;20;30mrgb\x1b[49;39mx",
+            "no styles",
+        ] {
+            let line = StyledLine::parse(raw);
+            assert_eq!(StyledLine::parse(&line.to_ansi()), line, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn test_palette() {
+        assert_eq!(AnsiColor::Indexed(2).to_color32(true), AnsiColor::Indexed(10).to_color32(false));
+        assert_eq!(AnsiColor::Indexed(16).to_color32(false), Color32::from_rgb(0, 0, 0));
+        assert_eq!(AnsiColor::Indexed(231).to_color32(false), Color32::from_rgb(255, 255, 255));
+        assert_eq!(AnsiColor::Indexed(232).to_color32(false), Color32::from_rgb(8, 8, 8));
+    }
+}
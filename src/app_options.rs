@@ -0,0 +1,266 @@
+use crate::alerts::AlertRule;
+use crate::idle::IdleOptions;
+use crate::miner_handle::MinerHandle;
+use crate::process_watch::ProcessWatch;
+use crate::profiles::config_dir;
+use crate::schedule::Schedule;
+use crate::tariff::Tariff;
+use crate::webhooks::Webhook;
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::time::{Duration, Instant};
+
+/// Options of the app itself, as opposed to the settings of a profile.
+/// Stored as json in the config dir, see AppOptions::path
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct AppOptions {
+    /// Closing the window leaves the miner running in the tray
+    pub close_to_tray: bool,
+    pub notifications: NotificationOptions,
+    pub webhooks: Vec<Webhook>,
+    pub alert_rules: Vec<AlertRule>,
+    pub schedule: Schedule,
+    pub tariff: Tariff,
+    pub idle: IdleOptions,
+    pub process_watch: ProcessWatch,
+}
+
+/// The events of the miner that are notified on the desktop
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct NotificationOptions {
+    pub crashed: bool,
+    pub pool_lost: bool,
+    /// When a GPU stops mining as it reached the temperature given with --tstop
+    pub thermal_stop: bool,
+    pub errors: bool,
+    pub alerts: bool,
+}
+
+impl Default for NotificationOptions {
+    fn default() -> Self {
+        NotificationOptions {
+            crashed: true,
+            pool_lost: true,
+            thermal_stop: true,
+            errors: true,
+            alerts: true,
+        }
+    }
+}
+
+impl AppOptions {
+    /// The location of the options file, "$XDG_CONFIG_HOME/ethminer-gui/options.json"
+    pub fn path() -> PathBuf {
+        config_dir().join("options.json")
+    }
+
+    /// Loads the options file, returns the default if it doesn't exist yet
+    pub fn load() -> Result<AppOptions, String> {
+        AppOptions::load_from(&AppOptions::path())
+    }
+
+    pub fn load_from(path: &Path) -> Result<AppOptions, String> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("Could not parse {}: {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(AppOptions::default()),
+            Err(e) => Err(format!("Could not read {}: {e}", path.display())),
+        }
+    }
+
+    /// When the options file was last written, None if it doesn't exist
+    pub fn modified() -> Option<SystemTime> {
+        std::fs::metadata(AppOptions::path())
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        self.save_to(&AppOptions::path())
+    }
+
+    /// Writes to a temporary file first, so the tasks reading the options never see a half
+    /// written file
+    pub fn save_to(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+}
+
+/// Reads the options again and again for a task following them. While the file can't be
+/// read, e.g. as it was broken by hand, the last options read are kept, so the policies
+/// of the task aren't turned off by the defaults.
+pub struct OptionsReader {
+    path: PathBuf,
+    options: AppOptions,
+    /// The last error, so it is only logged once
+    error: Option<String>,
+}
+
+impl Default for OptionsReader {
+    fn default() -> Self {
+        OptionsReader::at(AppOptions::path())
+    }
+}
+
+impl OptionsReader {
+    pub fn at(path: PathBuf) -> OptionsReader {
+        OptionsReader {
+            path,
+            options: AppOptions::default(),
+            error: None,
+        }
+    }
+
+    /// The options as they are now, or as they last were if they can't be read
+    pub fn read(&mut self) -> &AppOptions {
+        match AppOptions::load_from(&self.path) {
+            Ok(options) => {
+                self.options = options;
+                self.error = None;
+            }
+            Err(e) => {
+                if self.error.as_ref() != Some(&e) {
+                    println!("{e}, keeping the options last read");
+                    self.error = Some(e);
+                }
+            }
+        }
+        &self.options
+    }
+}
+
+/// Runs a task following the options, such as a policy pausing the miner, for a miner
+/// running in this process. After the delay, and then every period, the task is given the
+/// miner and the options as they are then, and the future it returns acts on them.
+///
+/// The options are read again each time, as the window editing them may run in another
+/// process. An app attached to another instance leaves the tasks to that instance, which
+/// runs the miner.
+pub fn follow<F, Fut>(miner: &MinerHandle, delay: Duration, period: Duration, mut task: F)
+where
+    F: FnMut(MinerHandle, &AppOptions) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    follow_events(miner, move |miner, mut reader| async move {
+        let mut interval = tokio::time::interval_at(Instant::now() + delay, period);
+        loop {
+            interval.tick().await;
+            task(miner.clone(), reader.read()).await;
+        }
+    });
+}
+
+/// Like follow, for a task that waits for events of its own instead of a period. The task
+/// is given a reader of the options, to read them for each event.
+pub fn follow_events<F, Fut>(miner: &MinerHandle, task: F)
+where
+    F: FnOnce(MinerHandle, OptionsReader) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    if miner.is_remote() {
+        return;
+    }
+    tokio::spawn(task(miner.clone(), OptionsReader::default()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::Severity;
+    use crate::process_watch::{MatchKind, WatchedProcess};
+    use crate::webhooks::PayloadFormat;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("ethminer-gui-options-{}", std::process::id()));
+        let path = dir.join("options.json");
+        assert_eq!(AppOptions::load_from(&path).unwrap(), AppOptions::default());
+
+        let options = AppOptions {
+            close_to_tray: true,
+            notifications: NotificationOptions {
+                pool_lost: false,
+                ..Default::default()
+            },
+            webhooks: vec![Webhook {
+                url: "https://hooks.slack.com/services/T0/B0/X".to_string(),
+                format: PayloadFormat::Slack,
+                ..Default::default()
+            }],
+            alert_rules: vec![AlertRule {
+                severity: Severity::Critical,
+                ..Default::default()
+            }],
+            schedule: Schedule {
+                enabled: true,
+                ..Default::default()
+            },
+            tariff: Tariff {
+                watts: 850.0,
+                pause_above_price: true,
+                ..Default::default()
+            },
+            idle: IdleOptions {
+                enabled: true,
+                idle_minutes: 5,
+                ..Default::default()
+            },
+            process_watch: ProcessWatch {
+                enabled: true,
+                processes: vec![WatchedProcess {
+                    pattern: "blender".to_string(),
+                    kind: MatchKind::Name,
+                }],
+                resume_delay: 120,
+            },
+        };
+        options.save_to(&path).unwrap();
+        assert_eq!(AppOptions::load_from(&path).unwrap(), options);
+
+        // Options added later take their defaults
+        std::fs::write(&path, "{\"notifications\": {\"errors\": false}}").unwrap();
+        let options = AppOptions::load_from(&path).unwrap();
+        assert!(!options.close_to_tray);
+        assert_eq!(
+            options.notifications,
+            NotificationOptions {
+                errors: false,
+                ..Default::default()
+            }
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reader_keeps_last_options() {
+        let dir = std::env::temp_dir().join(format!("ethminer-gui-reader-{}", std::process::id()));
+        let path = dir.join("options.json");
+        let mut reader = OptionsReader::at(path.clone());
+        assert_eq!(reader.read(), &AppOptions::default());
+
+        let options = AppOptions {
+            close_to_tray: true,
+            ..Default::default()
+        };
+        options.save_to(&path).unwrap();
+        assert_eq!(reader.read(), &options);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        // A file cut short while written by hand
+        std::fs::write(&path, "{\"close_to_tray\": tr").unwrap();
+        assert_eq!(reader.read(), &options);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
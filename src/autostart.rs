@@ -0,0 +1,256 @@
+use crate::profiles::xdg_dir;
+
+use std::path::{Path, PathBuf};
+
+/// Options of the XDG autostart entry, which launches the app when logging in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AutostartOptions {
+    /// Starts in the tray instead of opening the window, see --minimized
+    pub minimized: bool,
+    /// Starts mining with the active profile, see --start-mining
+    pub start_mining: bool,
+}
+
+/// The autostart entry as found on disk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AutostartStatus {
+    Disabled,
+    Enabled(AutostartOptions),
+    /// The entry launches a binary other than this one, e.g. one that was moved or deleted
+    Stale {
+        exec: PathBuf,
+        options: AutostartOptions,
+    },
+}
+
+/// The location of the autostart entry, "$XDG_CONFIG_HOME/autostart/ethminer-gui.desktop"
+pub fn desktop_path() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+        .join("autostart")
+        .join("ethminer-gui.desktop")
+}
+
+/// Reads the entry at path, comparing the binary it launches with exe
+pub fn status(path: &Path, exe: &Path) -> AutostartStatus {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return AutostartStatus::Disabled,
+    };
+    // Entries can be disabled without removing them, e.g. by the settings of the desktop
+    if contents
+        .lines()
+        .any(|l| l.trim() == "Hidden=true" || l.trim() == "X-GNOME-Autostart-enabled=false")
+    {
+        return AutostartStatus::Disabled;
+    }
+    let args = match contents
+        .lines()
+        .find_map(|l| l.strip_prefix("Exec="))
+        .and_then(parse_exec)
+    {
+        Some(args) if !args.is_empty() => args,
+        _ => return AutostartStatus::Disabled,
+    };
+    let options = AutostartOptions {
+        minimized: args.iter().any(|a| a == "--minimized"),
+        start_mining: args.iter().any(|a| a == "--start-mining"),
+    };
+    let exec = PathBuf::from(&args[0]);
+    if exec == exe && exec.exists() {
+        AutostartStatus::Enabled(options)
+    } else {
+        AutostartStatus::Stale { exec, options }
+    }
+}
+
+/// Writes the entry, replacing any existing entry
+pub fn enable(path: &Path, exe: &Path, options: &AutostartOptions) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+    }
+    std::fs::write(path, render(exe, options))
+        .map_err(|e| format!("Could not write {}: {e}", path.display()))
+}
+
+/// Removes the entry, if there is one
+pub fn disable(path: &Path) -> Result<(), String> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(format!("Could not remove {}: {e}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Renders the desktop entry launching exe with the options
+pub fn render(exe: &Path, options: &AutostartOptions) -> String {
+    let mut exec = vec![quote_exec_arg(&exe.to_string_lossy())];
+    if options.minimized {
+        exec.push("--minimized".to_string());
+    }
+    if options.start_mining {
+        exec.push("--start-mining".to_string());
+    }
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=ethminer-gui\n\
+         Comment=Mine ether using a gui application\n\
+         Exec={}\n\
+         Icon=ethminer-gui\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-enabled=true\n",
+        exec.join(" ")
+    )
+}
+
+/// Characters that need an argument of Exec to be quoted, according to the desktop entry spec
+const RESERVED: &[char] = &[
+    ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(', ')',
+    '`',
+];
+
+/// Quotes an argument of the Exec key. Backslashes are escaped twice,
+/// as the quoting rules apply after the escapes of the string value itself.
+/// Percent signs are doubled, as they would start a field code such as %f.
+fn quote_exec_arg(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    if !arg.contains(RESERVED) {
+        return arg;
+    }
+    let mut out = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' => {
+                out.push_str("\\\\");
+                out.push(c);
+            }
+            '\\' => out.push_str("\\\\\\\\"),
+            // A line break would end the value
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Splits the value of an Exec key into its arguments, reversing quote_exec_arg
+fn parse_exec(exec: &str) -> Option<Vec<String>> {
+    // Undoes the escapes of the string value first
+    let mut value = String::new();
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            's' => value.push(' '),
+            'n' => value.push('\n'),
+            't' => value.push('\t'),
+            'r' => value.push('\r'),
+            c => value.push(c),
+        }
+    }
+
+    let mut args = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ' ').is_some() {}
+        let first = match chars.next() {
+            Some(c) => c,
+            None => return Some(args),
+        };
+        let mut arg = String::new();
+        if first == '"' {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => arg.push(chars.next()?),
+                    c => arg.push(c),
+                }
+            }
+        } else {
+            arg.push(first);
+            while let Some(c) = chars.next_if(|c| *c != ' ') {
+                arg.push(c);
+            }
+        }
+        args.push(arg.replace("%%", "%"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_quoting() {
+        for arg in [
+            "/usr/bin/ethminer-gui",
+            "/home/a b/$x \"y\" `z` \\w",
+            "/opt/100%/ethminer-gui",
+            "/opt/two\nlines/ethminer-gui",
+        ] {
+            let quoted = quote_exec_arg(arg);
+            assert_eq!(parse_exec(&quoted).unwrap(), [arg]);
+        }
+        assert_eq!(
+            quote_exec_arg("/opt/100%/ethminer-gui"),
+            "/opt/100%%/ethminer-gui"
+        );
+        assert_eq!(quote_exec_arg("a\nb"), "\"a\\nb\"");
+        assert_eq!(
+            quote_exec_arg("/opt/my miner/ethminer-gui"),
+            "\"/opt/my miner/ethminer-gui\""
+        );
+        assert_eq!(
+            parse_exec("\"/opt/my miner/gui\" --minimized").unwrap(),
+            ["/opt/my miner/gui", "--minimized"]
+        );
+        assert!(parse_exec("\"unterminated").is_none());
+    }
+
+    #[test]
+    fn test_enable_and_detect_stale_entry() {
+        let dir =
+            std::env::temp_dir().join(format!("ethminer-gui-autostart-{}", std::process::id()));
+        let path = dir.join("autostart").join("ethminer-gui.desktop");
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(status(&path, &exe), AutostartStatus::Disabled);
+
+        let options = AutostartOptions {
+            minimized: true,
+            start_mining: false,
+        };
+        enable(&path, &exe, &options).unwrap();
+        assert_eq!(
+            status(&path, &exe),
+            AutostartStatus::Enabled(options.clone())
+        );
+
+        // The binary was moved since the entry was written
+        let moved = dir.join("ethminer-gui");
+        assert_eq!(
+            status(&path, &moved),
+            AutostartStatus::Stale {
+                exec: exe.clone(),
+                options: options.clone()
+            }
+        );
+        // The binary the entry points at no longer exists
+        enable(&path, &moved, &options).unwrap();
+        assert!(matches!(
+            status(&path, &moved),
+            AutostartStatus::Stale { .. }
+        ));
+
+        disable(&path).unwrap();
+        disable(&path).unwrap();
+        assert_eq!(status(&path, &exe), AutostartStatus::Disabled);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: ethminer-gui [OPTIONS]
+       ethminer-gui ctl <COMMAND> [OPTIONS]
+
+Options:
+  --headless          Run the miner without a window, until SIGINT or SIGTERM
+  --tui               Show a terminal interface instead of the window, e.g. over SSH
+  --minimized         Start in the tray without opening the window
+  --start-mining      Start mining with the active profile, unless the miner is already running
+  --profile <NAME>    Profile to mine with, defaults to the active profile.
+                      Outside of headless mode it becomes the active profile
+  --log-dir <DIR>     Directory for the headless mode logs,
+                      defaults to $XDG_STATE_HOME/ethminer-gui
+  -h, --help          Print this help
+
+Commands of ctl, which controls the running instance through its control socket:
+  status [--json]             Print whether the miner is running, and its hashrate and shares
+  start [--profile <NAME>]    Start the miner, with the active profile if none is given
+  stop                        Stop the miner
+  restart                     Restart the miner with the profile it was started with
+  tail [-f] [-n <LINES>]      Print the last lines of miner output, -f keeps printing new lines
+  stats [--json]              Print the session and lifetime statistics
+  profiles list [--json]      Print the profiles, marking the active profile with *
+  profiles use <NAME>         Make the profile active, restarting the miner with it if it is running";
+
+/// Command line arguments of the app
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Args {
+    pub headless: bool,
+    pub tui: bool,
+    pub minimized: bool,
+    pub start_mining: bool,
+    pub profile: Option<String>,
+    pub log_dir: Option<PathBuf>,
+    pub help: bool,
+    /// Set when started as "ethminer-gui ctl ..."
+    pub ctl: Option<CtlArgs>,
+}
+
+/// Arguments of the ctl subcommand
+#[derive(Debug, PartialEq, Eq)]
+pub struct CtlArgs {
+    pub command: CtlCommand,
+    /// Print json instead of text meant for humans
+    pub json: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CtlCommand {
+    Status,
+    Start { profile: Option<String> },
+    Stop,
+    Restart,
+    Tail { follow: bool, lines: usize },
+    Stats,
+    ProfilesList,
+    ProfilesUse { profile: String },
+}
+
+/// Number of lines printed by tail when -n isn't given
+const DEFAULT_TAIL_LINES: usize = 10;
+
+/// Splits "--flag=value" into the flag and the value
+fn split_flag(arg: String) -> (String, Option<String>) {
+    match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => {
+            (flag.to_string(), Some(value.to_string()))
+        }
+        _ => (arg, None),
+    }
+}
+
+impl Args {
+    /// Parses the arguments, not including the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut out = Args::default();
+        let mut args = args.into_iter().peekable();
+        if args.peek().map(String::as_str) == Some("ctl") {
+            args.next();
+            out.ctl = Some(CtlArgs::parse(args)?);
+            return Ok(out);
+        }
+        while let Some(arg) = args.next() {
+            // Accepts both "--flag value" and "--flag=value"
+            let (flag, inline_value) = split_flag(arg);
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(format!("{flag} requires a value"))
+            };
+            match flag.as_str() {
+                "--headless" => out.headless = true,
+                "--tui" => out.tui = true,
+                "--minimized" => out.minimized = true,
+                "--start-mining" => out.start_mining = true,
+                "--profile" => out.profile = Some(value()?),
+                "--log-dir" => out.log_dir = Some(PathBuf::from(value()?)),
+                "-h" | "--help" => out.help = true,
+                _ => return Err(format!("Unknown argument \"{flag}\"")),
+            }
+        }
+        if out.headless && out.tui {
+            return Err("--headless and --tui can't be used together".to_string());
+        }
+        if out.minimized && (out.headless || out.tui) {
+            return Err("--minimized can't be used with --headless or --tui".to_string());
+        }
+        if !out.headless && out.log_dir.is_some() {
+            return Err("--log-dir can only be used with --headless".to_string());
+        }
+        Ok(out)
+    }
+}
+
+impl CtlArgs {
+    /// Parses the arguments following "ctl"
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<CtlArgs, String> {
+        let mut words = Vec::new();
+        let (mut json, mut follow, mut profile, mut lines) = (false, false, None, None);
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = split_flag(arg);
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(format!("{flag} requires a value"))
+            };
+            match flag.as_str() {
+                "--json" => json = true,
+                "-f" | "--follow" => follow = true,
+                "--profile" => profile = Some(value()?),
+                "-n" | "--lines" => {
+                    let value = value()?;
+                    let count = value
+                        .parse()
+                        .map_err(|_| format!("Invalid number of lines \"{value}\""))?;
+                    lines = Some(count);
+                }
+                _ if flag.starts_with('-') => return Err(format!("Unknown argument \"{flag}\"")),
+                _ => words.push(flag),
+            }
+        }
+
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let command = match words[..] {
+            ["status"] => CtlCommand::Status,
+            ["start"] => CtlCommand::Start {
+                profile: profile.take(),
+            },
+            ["stop"] => CtlCommand::Stop,
+            ["restart"] => CtlCommand::Restart,
+            ["tail"] => CtlCommand::Tail {
+                follow,
+                lines: lines.take().unwrap_or(DEFAULT_TAIL_LINES),
+            },
+            ["stats"] => CtlCommand::Stats,
+            ["profiles", "list"] => CtlCommand::ProfilesList,
+            ["profiles", "use", profile] => CtlCommand::ProfilesUse {
+                profile: profile.to_string(),
+            },
+            [] => return Err("ctl requires a command".to_string()),
+            _ => return Err(format!("Unknown command \"{}\"", words.join(" "))),
+        };
+
+        // Options are only accepted by the commands they apply to
+        let json_command = matches!(
+            command,
+            CtlCommand::Status | CtlCommand::Stats | CtlCommand::ProfilesList
+        );
+        let tail_command = matches!(command, CtlCommand::Tail { .. });
+        if profile.is_some()
+            || lines.is_some()
+            || (json && !json_command)
+            || (follow && !tail_command)
+        {
+            return Err(format!("Invalid option for \"{}\"", words.join(" ")));
+        }
+        Ok(CtlArgs { command, json })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+        let args = parse(&[
+            "--headless",
+            "--profile",
+            "backup",
+            "--log-dir=/var/log/miner",
+        ])
+        .unwrap();
+        assert!(args.headless);
+        assert_eq!(args.profile.as_deref(), Some("backup"));
+        assert_eq!(args.log_dir, Some(PathBuf::from("/var/log/miner")));
+        assert!(parse(&["-h"]).unwrap().help);
+        assert!(parse(&["--tui"]).unwrap().tui);
+        let args = parse(&["--minimized", "--start-mining"]).unwrap();
+        assert!(args.minimized && args.start_mining);
+        // Launching again passes the profile to the running instance
+        assert_eq!(
+            parse(&["--profile", "backup"]).unwrap().profile.as_deref(),
+            Some("backup")
+        );
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        assert!(parse(&["--headless", "--profile"]).is_err());
+        assert!(parse(&["--fullscreen"]).is_err());
+        assert!(parse(&["--log-dir", "/tmp"]).is_err());
+        assert!(parse(&["--headless", "--tui"]).is_err());
+        assert!(parse(&["--tui", "--minimized"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_ctl_args() {
+        let ctl = |args: &[&str]| parse(args).map(|a| a.ctl.unwrap());
+        assert_eq!(
+            ctl(&["ctl", "status", "--json"]).unwrap(),
+            CtlArgs {
+                command: CtlCommand::Status,
+                json: true
+            }
+        );
+        assert_eq!(
+            ctl(&["ctl", "start", "--profile=backup"]).unwrap().command,
+            CtlCommand::Start {
+                profile: Some("backup".to_string())
+            }
+        );
+        assert_eq!(
+            ctl(&["ctl", "tail", "-f"]).unwrap().command,
+            CtlCommand::Tail {
+                follow: true,
+                lines: DEFAULT_TAIL_LINES
+            }
+        );
+        assert_eq!(
+            ctl(&["ctl", "profiles", "use", "backup"]).unwrap().command,
+            CtlCommand::ProfilesUse {
+                profile: "backup".to_string()
+            }
+        );
+
+        assert!(ctl(&["ctl"]).is_err());
+        assert!(ctl(&["ctl", "pause"]).is_err());
+        assert!(ctl(&["ctl", "stop", "--json"]).is_err());
+        assert!(ctl(&["ctl", "status", "--profile", "backup"]).is_err());
+        assert!(ctl(&["ctl", "tail", "-n", "many"]).is_err());
+        assert!(ctl(&["ctl", "profiles", "use"]).is_err());
+    }
+}
@@ -0,0 +1,451 @@
+use crate::ansi::StyledLine;
+use crate::cli::{CtlArgs, CtlCommand};
+use crate::ipc::{socket_path, Client, Command, Event, Reply, Topic};
+use crate::miner_controller::MinerState;
+use crate::profiles::{activate_profile, Profiles};
+use crate::stats::{self, StatsSnapshot};
+
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::timeout;
+
+/// How long start, restart and stop wait for the miner to change state.
+/// Longer than the time the controller gives the miner to exit when stopping it
+const STATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs "ethminer-gui ctl", returning the exit code of the process
+pub async fn run_ctl(args: CtlArgs) -> i32 {
+    let stdout = std::io::stdout();
+    match run(args, &socket_path(), &Profiles::path(), &mut stdout.lock()).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{e}");
+            1
+        }
+    }
+}
+
+/// Runs the command against the instance serving the socket, writing the output to out
+async fn run(
+    args: CtlArgs,
+    socket: &Path,
+    profiles_path: &Path,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    match args.command {
+        // The profiles are read from the file, so they can be listed without a running instance
+        CtlCommand::ProfilesList => {
+            let profiles = Profiles::load_from(profiles_path)?;
+            if args.json {
+                let json = serde_json::to_string_pretty(&profiles).map_err(|e| e.to_string())?;
+                return write_out(out, &json);
+            }
+            for (name, settings) in &profiles.profiles {
+                let active = if *name == profiles.active { "*" } else { " " };
+                let pools: Vec<String> = settings
+                    .url
+                    .iter()
+                    .map(|url| format!("{}:{}", url.pool, url.port))
+                    .collect();
+                write_out(
+                    out,
+                    &format!(
+                        "{active} {name}\t{}\t{}",
+                        settings.bin_path,
+                        pools.join(", ")
+                    ),
+                )?;
+            }
+            Ok(())
+        }
+        CtlCommand::ProfilesUse { profile } => match Client::connect(socket).await {
+            // The running instance restarts the miner if it is mining with another profile
+            Ok(client) => {
+                let command = Command::SwitchProfile { profile };
+                if get_state(&client).await?.running {
+                    start(&client, command, out).await
+                } else {
+                    client.request(command).await?;
+                    write_out(out, "Switched the active profile")
+                }
+            }
+            Err(_) => {
+                activate_profile(profiles_path, &profile)?;
+                write_out(out, "Switched the active profile")
+            }
+        },
+        command => {
+            let client = Client::connect(socket).await.map_err(|e| {
+                format!("{e}\nIs ethminer-gui running? It can be started with \"ethminer-gui --headless\"")
+            })?;
+            run_with_client(command, args.json, &client, out).await
+        }
+    }
+}
+
+/// Runs the commands that need a running instance
+async fn run_with_client(
+    command: CtlCommand,
+    json: bool,
+    client: &Client,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    match command {
+        CtlCommand::Status => {
+            let state = get_state(client).await?;
+            let stats = get_stats(client).await?;
+            if json {
+                let status = serde_json::json!({ "state": state, "stats": stats });
+                return write_out(out, &status.to_string());
+            }
+            write_out(out, &format_status(&state, &stats))
+        }
+        CtlCommand::Stats => {
+            let stats = get_stats(client).await?;
+            if json {
+                let json = serde_json::to_string(&stats).map_err(|e| e.to_string())?;
+                return write_out(out, &json);
+            }
+            write_out(out, &format_stats(&stats))
+        }
+        CtlCommand::Start { profile } => start(client, Command::Start { profile }, out).await,
+        CtlCommand::Restart => start(client, Command::Restart, out).await,
+        CtlCommand::Stop => {
+            let mut states = subscribe_state(client).await?;
+            client.request(Command::Stop).await?;
+            wait_for_state(&mut states, |s| !s.running).await?;
+            write_out(out, "Stopped the miner")
+        }
+        CtlCommand::Tail {
+            follow: false,
+            lines,
+        } => match client.request(Command::GetLines { count: lines }).await? {
+            Reply::Lines { lines } => lines.iter().try_for_each(|l| write_out(out, l)),
+            reply => Err(unexpected(reply)),
+        },
+        CtlCommand::Tail {
+            follow: true,
+            lines,
+        } => {
+            let mut events = client.events().ok_or_else(closed)?;
+            client
+                .request(Command::Subscribe {
+                    topics: vec![Topic::Lines],
+                    backlog: lines,
+                })
+                .await?;
+            loop {
+                match events.recv().await {
+                    // Redrawn lines are left out, as they can't be redrawn in a pipe
+                    Ok(Event::Line {
+                        text,
+                        replace: false,
+                    }) => {
+                        // Without the escape sequences, which pipes and log files don't want
+                        if write_out(out, &StyledLine::parse(&text).text).is_err() {
+                            // The reader has gone away, e.g. "ctl tail -f | head"
+                            return Ok(());
+                        }
+                        let _ = out.flush();
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Err(closed()),
+                }
+            }
+        }
+        CtlCommand::ProfilesList | CtlCommand::ProfilesUse { .. } => {
+            unreachable!("Profile commands don't need a running instance")
+        }
+    }
+}
+
+/// Sends a request that (re)starts the miner, and waits for a new miner process to be running
+async fn start(client: &Client, command: Command, out: &mut impl Write) -> Result<(), String> {
+    let previous_pid = get_state(client).await?.pid;
+    let mut states = subscribe_state(client).await?;
+    client.request(command).await?;
+    let state = wait_for_state(&mut states, |s| s.running && s.pid != previous_pid).await?;
+    write_out(
+        out,
+        &format!(
+            "Started the miner with profile \"{}\", pid {}",
+            state.profile.unwrap_or_default(),
+            state.pid.unwrap_or_default()
+        ),
+    )
+}
+
+async fn get_state(client: &Client) -> Result<MinerState, String> {
+    match client.request(Command::GetState).await? {
+        Reply::State(state) => Ok(state),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+async fn get_stats(client: &Client) -> Result<StatsSnapshot, String> {
+    match client.request(Command::GetStats).await? {
+        Reply::Stats(stats) => Ok(stats),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+async fn subscribe_state(client: &Client) -> Result<broadcast::Receiver<Event>, String> {
+    let events = client.events().ok_or_else(closed)?;
+    client
+        .request(Command::Subscribe {
+            topics: vec![Topic::State],
+            backlog: 0,
+        })
+        .await?;
+    Ok(events)
+}
+
+/// Waits for a state matching the predicate, up to STATE_TIMEOUT
+async fn wait_for_state(
+    events: &mut broadcast::Receiver<Event>,
+    predicate: impl Fn(&MinerState) -> bool,
+) -> Result<MinerState, String> {
+    let wait = async {
+        loop {
+            match events.recv().await {
+                Ok(Event::State(state)) if predicate(&state) => return Ok(state),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return Err(closed()),
+            }
+        }
+    };
+    timeout(STATE_TIMEOUT, wait).await.unwrap_or_else(|_| {
+        Err("Timed out waiting for the miner, see the log for details".to_string())
+    })
+}
+
+fn format_status(state: &MinerState, stats: &StatsSnapshot) -> String {
+    let miner = match state.pid {
+        Some(pid) if state.running => format!("running, pid {pid}"),
+        _ => "stopped".to_string(),
+    };
+    format!(
+        "Miner:    {miner}\n\
+         Profile:  {}\n\
+         Uptime:   {}\n\
+         Hashrate: {}\n\
+         Shares:   {} accepted, {} rejected, {} stale",
+        state.profile.as_deref().unwrap_or("-"),
+        stats::format_duration(stats.uptime()),
+        stats::format_hashrate(stats.current_hashrate),
+        stats.accepted,
+        stats.rejected,
+        stats.stale
+    )
+}
+
+/// Formats the statistics as a table, like the statistics panel of the GUI
+fn format_stats(stats: &StatsSnapshot) -> String {
+    let lifetime = &stats.lifetime;
+    let lifetime_uptime = Duration::from_secs(lifetime.mining_secs);
+    let ratio = |accepted, rejected| match stats::accepted_ratio(accepted, rejected) {
+        Some(r) => format!("{:.1}%", r * 100.0),
+        None => "-".to_string(),
+    };
+    let shares = |accepted, rejected, stale| format!("{accepted} / {rejected} / {stale}");
+    let last_accepted = match stats.last_accepted_secs {
+        Some(secs) => format!("{} ago", stats::format_duration(Duration::from_secs(secs))),
+        None => "Never".to_string(),
+    };
+
+    let mut rows = vec![
+        [
+            "".to_string(),
+            "Session".to_string(),
+            "Lifetime".to_string(),
+        ],
+        [
+            "Uptime".to_string(),
+            stats::format_duration(stats.uptime()),
+            stats::format_duration(lifetime_uptime),
+        ],
+        [
+            "Hashrate".to_string(),
+            stats::format_hashrate(stats.current_hashrate),
+            String::new(),
+        ],
+        [
+            "Average Hashrate".to_string(),
+            stats::format_hashrate(stats.average_hashrate),
+            String::new(),
+        ],
+        [
+            "Peak Hashrate".to_string(),
+            stats::format_hashrate(stats.peak_hashrate),
+            stats::format_hashrate(lifetime.peak_hashrate),
+        ],
+        [
+            "Accepted / Rejected / Stale".to_string(),
+            shares(stats.accepted, stats.rejected, stats.stale),
+            shares(lifetime.accepted, lifetime.rejected, lifetime.stale),
+        ],
+        [
+            "Accepted Ratio".to_string(),
+            ratio(stats.accepted, stats.rejected),
+            ratio(lifetime.accepted, lifetime.rejected),
+        ],
+        [
+            "Shares per Hour".to_string(),
+            format!(
+                "{:.2}",
+                stats::shares_per_hour(stats.accepted, stats.uptime())
+            ),
+            format!(
+                "{:.2}",
+                stats::shares_per_hour(lifetime.accepted, lifetime_uptime)
+            ),
+        ],
+        [
+            "Last Accepted Share".to_string(),
+            last_accepted,
+            String::new(),
+        ],
+        [
+            "Reconnects".to_string(),
+            stats.reconnects.to_string(),
+            lifetime.reconnects.to_string(),
+        ],
+        [
+            "Restarts".to_string(),
+            stats.restarts.to_string(),
+            lifetime.restarts.to_string(),
+        ],
+        [
+            "Stall Restarts".to_string(),
+            stats.stall_restarts.to_string(),
+            lifetime.stall_restarts.to_string(),
+        ],
+    ];
+    for gpu in &stats.gpus {
+        let mut status = stats::format_hashrate(gpu.hashrate);
+        if let Some(temperature) = gpu.temperature {
+            status += &format!(", {temperature}C");
+        }
+        if let Some(fan) = gpu.fan {
+            status += &format!(", fan {fan}%");
+        }
+        rows.push([format!("GPU {}", gpu.index), status, String::new()]);
+    }
+
+    let width = |column: usize| rows.iter().map(|r| r[column].len()).max().unwrap_or(0);
+    let (first, second) = (width(0), width(1));
+    rows.iter()
+        .map(|[label, session, lifetime]| {
+            format!("{label:first$}  {session:second$}  {lifetime}")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn write_out(out: &mut impl Write, text: &str) -> Result<(), String> {
+    writeln!(out, "{text}").map_err(|e| format!("Could not write the output: {e}"))
+}
+
+fn closed() -> String {
+    "The connection to the controller was closed".to_string()
+}
+
+fn unexpected(reply: Reply) -> String {
+    format!("Unexpected reply from the controller: {reply:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::Server;
+    use crate::miner_controller::MinerController;
+    use crate::miner_settings::tests::fake_miner_settings;
+    use crate::profiles::DEFAULT_PROFILE;
+
+    async fn ctl_output(
+        args: &[&str],
+        socket: &Path,
+        profiles_path: &Path,
+    ) -> Result<String, String> {
+        let args = CtlArgs::parse(args.iter().map(|a| a.to_string()))?;
+        let mut out = Vec::new();
+        run(args, socket, profiles_path, &mut out).await?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ctl_commands() {
+        let dir = std::env::temp_dir().join(format!("ethminer-gui-ctl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("control.sock");
+        let profiles_path = dir.join("profiles.json");
+        let mut profiles = Profiles::default();
+        profiles.set_active_settings(fake_miner_settings(
+            &dir,
+            "echo ' m 17:29:50 ethminer 0:00 A0 20.00 Mh - cu0 20.00'\nexec sleep 30",
+        ));
+        profiles.profiles.insert(
+            "backup".to_string(),
+            profiles.profiles[DEFAULT_PROFILE].clone(),
+        );
+        profiles.save_to(&profiles_path).unwrap();
+        let ctl = |args: &'static [&'static str]| ctl_output(args, &socket, &profiles_path);
+
+        // Without a running instance, only the profiles can be used
+        assert!(ctl(&["status"]).await.is_err());
+        let list = ctl(&["profiles", "list"]).await.unwrap();
+        assert!(list.starts_with("  backup\t"));
+        assert!(list.contains("\n* default\t"));
+        ctl(&["profiles", "use", "backup"]).await.unwrap();
+        assert_eq!(
+            Profiles::load_from(&profiles_path).unwrap().active,
+            "backup"
+        );
+        assert!(ctl(&["profiles", "use", "missing"]).await.is_err());
+
+        Server::new(MinerController::new(), profiles_path.clone())
+            .listen(&socket)
+            .await
+            .unwrap();
+        let started = ctl(&["start", "--profile", "default"]).await.unwrap();
+        assert!(started.starts_with("Started the miner with profile \"default\""));
+        assert!(ctl(&["start", "--profile", "missing"]).await.is_err());
+
+        let mut tail = String::new();
+        for _ in 0..100 {
+            tail = ctl(&["tail", "-n", "1"]).await.unwrap();
+            if tail.contains("20.00 Mh") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(tail.lines().count(), 1);
+        assert!(tail.contains("20.00 Mh"));
+
+        let status = ctl(&["status"]).await.unwrap();
+        assert!(status.contains("Miner:    running"));
+        assert!(status.contains("Hashrate: 20.00 MH/s"));
+        let json: serde_json::Value =
+            serde_json::from_str(&ctl(&["status", "--json"]).await.unwrap()).unwrap();
+        assert_eq!(json["state"]["running"], true);
+        assert_eq!(json["stats"]["current_hashrate"], 20e6);
+        let table = ctl(&["stats"]).await.unwrap();
+        assert!(table.contains("GPU 0"));
+
+        // Switching the profile restarts the miner with it
+        let switched = ctl(&["profiles", "use", "backup"]).await.unwrap();
+        assert!(switched.starts_with("Started the miner with profile \"backup\""));
+        ctl(&["restart"]).await.unwrap();
+        assert_eq!(ctl(&["stop"]).await.unwrap(), "Stopped the miner\n");
+        assert!(ctl(&["status"])
+            .await
+            .unwrap()
+            .contains("Miner:    stopped"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,389 @@
+use crate::ansi::StyledLine;
+use crate::miner_controller::MinerState;
+use crate::output_reader::LineEvent;
+
+use regex::Regex;
+use std::sync::OnceLock;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// A known failure of the miner, with what usually fixes it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub problem: &'static str,
+    pub fix: &'static str,
+}
+
+impl std::fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\nSuggested fix: {}", self.problem, self.fix)
+    }
+}
+
+/// What a rule recognises
+enum Trigger {
+    /// A line of output matching the pattern, seen the number of times without a line
+    /// matching the reset pattern in between
+    Output {
+        pattern: &'static str,
+        times: u32,
+        reset: Option<&'static str>,
+    },
+    /// The miner was killed by the signal
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Signal(i32),
+    /// The miner exited by itself with the code
+    ExitCode(i32),
+}
+
+struct Rule {
+    trigger: Trigger,
+    diagnosis: Diagnosis,
+}
+
+const DAG_TOO_LARGE: Diagnosis = Diagnosis {
+    problem: "The GPU doesn't have enough memory for the DAG of the current epoch",
+    fix: "The DAG grows with every epoch, so this card can't mine Ethash anymore. Mine with \
+          GPUs that have more memory",
+};
+
+const RULES: &[Rule] = &[
+    Rule {
+        trigger: Trigger::Output {
+            pattern: r"(?i)CUDA error.*out of memory",
+            times: 1,
+            reset: None,
+        },
+        diagnosis: DAG_TOO_LARGE,
+    },
+    Rule {
+        trigger: Trigger::Output {
+            pattern: r"Insufficient device memory",
+            times: 1,
+            reset: None,
+        },
+        diagnosis: DAG_TOO_LARGE,
+    },
+    Rule {
+        trigger: Trigger::Output {
+            pattern: r"No OpenCL platforms found",
+            times: 1,
+            reset: None,
+        },
+        diagnosis: Diagnosis {
+            problem: "The miner found no OpenCL driver",
+            fix: "Mine NVIDIA cards with the Cuda device type, or install the OpenCL driver of \
+                  the AMD card (ROCm or AMDGPU-PRO)",
+        },
+    },
+    Rule {
+        trigger: Trigger::Output {
+            pattern: r"(?i)no CUDA driver found|no CUDA-capable device|CUDA driver version is insufficient",
+            times: 1,
+            reset: None,
+        },
+        diagnosis: Diagnosis {
+            problem: "The miner found no usable CUDA driver",
+            fix: "Mine AMD cards with the OpenCl device type, or install or update the NVIDIA \
+                  driver",
+        },
+    },
+    Rule {
+        trigger: Trigger::Output {
+            pattern: r"Could not resolve host|Host not found",
+            times: 1,
+            reset: None,
+        },
+        diagnosis: Diagnosis {
+            problem: "The address of the pool could not be found",
+            fix: "Check the spelling of the pool address in the profile, and that the rig can \
+                  resolve host names",
+        },
+    },
+    Rule {
+        trigger: Trigger::Output {
+            pattern: r"No connection\. Suspend mining|Connection refused|Connection timed out",
+            times: 5,
+            reset: Some(r"Established connection"),
+        },
+        diagnosis: Diagnosis {
+            problem: "The miner keeps failing to connect to the pool",
+            fix: "Check the pool port in the profile, and that no firewall blocks outgoing \
+                  connections to it",
+        },
+    },
+    Rule {
+        trigger: Trigger::Signal(9),
+        diagnosis: Diagnosis {
+            problem: "The miner was killed, most likely because the system ran out of memory",
+            fix: "Ethminer needs system memory for the DAG of each GPU. Close other programs, \
+                  or add memory or swap",
+        },
+    },
+    Rule {
+        trigger: Trigger::Signal(11),
+        diagnosis: Diagnosis {
+            problem: "The miner crashed with a segmentation fault",
+            fix: "This usually comes from an unstable overclock or GPU driver. Lower the \
+                  overclock, or update the driver",
+        },
+    },
+    // Ethminer exits with 1 on invalid arguments, and with 2 on errors while starting,
+    // such as "No usable mining devices found"
+    Rule {
+        trigger: Trigger::ExitCode(1),
+        diagnosis: Diagnosis {
+            problem: "The miner rejected the arguments it was started with",
+            fix: "Check the settings of the profile, such as the pool URL, against \
+                  ethminer --help",
+        },
+    },
+    Rule {
+        trigger: Trigger::ExitCode(2),
+        diagnosis: Diagnosis {
+            problem: "The miner stopped on an error, most often as it found no usable mining \
+                      devices",
+            fix: "Check that the GPU driver is installed and sees the GPUs, e.g. with ethminer \
+                  --list-devices, and that the device type of the profile matches them",
+        },
+    },
+];
+
+/// The patterns of the output rules and their resets, in the order of RULES
+fn patterns() -> &'static [(Option<Regex>, Option<Regex>)] {
+    static PATTERNS: OnceLock<Vec<(Option<Regex>, Option<Regex>)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        RULES
+            .iter()
+            .map(|rule| match rule.trigger {
+                Trigger::Output { pattern, reset, .. } => (
+                    Some(Regex::new(pattern).unwrap()),
+                    reset.map(|reset| Regex::new(reset).unwrap()),
+                ),
+                Trigger::Signal(_) | Trigger::ExitCode(_) => (None, None),
+            })
+            .collect()
+    })
+}
+
+/// Recognises the known failures in the output of one run of the miner, each once
+#[derive(Default)]
+pub struct Diagnoser {
+    /// Matches of each rule since it was last reset
+    counts: Vec<u32>,
+    /// Diagnoses already given this run
+    given: Vec<Diagnosis>,
+}
+
+impl Diagnoser {
+    pub fn line(&mut self, line: &str) -> Option<Diagnosis> {
+        self.counts.resize(RULES.len(), 0);
+        let mut found = None;
+        for ((rule, (pattern, reset)), count) in RULES.iter().zip(patterns()).zip(&mut self.counts)
+        {
+            let times = match rule.trigger {
+                Trigger::Output { times, .. } => times,
+                Trigger::Signal(_) | Trigger::ExitCode(_) => continue,
+            };
+            if reset.as_ref().is_some_and(|reset| reset.is_match(line)) {
+                *count = 0;
+            }
+            if pattern
+                .as_ref()
+                .is_some_and(|pattern| pattern.is_match(line))
+            {
+                *count += 1;
+                if *count >= times && found.is_none() {
+                    found = Some(rule.diagnosis);
+                }
+            }
+        }
+        let diagnosis = found.filter(|d| !self.given.contains(d))?;
+        self.given.push(diagnosis);
+        Some(diagnosis)
+    }
+}
+
+/// Recognises a known failure from how the miner exited
+pub fn diagnose_exit(status: &std::process::ExitStatus) -> Option<Diagnosis> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return RULES.iter().find_map(|rule| match rule.trigger {
+                Trigger::Signal(s) if s == signal => Some(rule.diagnosis),
+                _ => None,
+            });
+        }
+    }
+    let code = status.code()?;
+    RULES.iter().find_map(|rule| match rule.trigger {
+        Trigger::ExitCode(c) if c == code => Some(rule.diagnosis),
+        _ => None,
+    })
+}
+
+/// Raises the diagnoses of the miner output on error_tx, starting over with each run of
+/// the miner, until the controller is dropped
+pub async fn watch_output(
+    mut lines: broadcast::Receiver<LineEvent>,
+    mut states: broadcast::Receiver<MinerState>,
+    error_tx: broadcast::Sender<String>,
+) {
+    let mut diagnoser = Diagnoser::default();
+    loop {
+        tokio::select! {
+            line = lines.recv() => match line {
+                Ok(LineEvent::Push(line)) => {
+                    if let Some(diagnosis) = diagnoser.line(&StyledLine::parse(&line).text) {
+                        println!("Diagnosed: {}", diagnosis.problem);
+                        // Nobody might be subscribed
+                        let _ = error_tx.send(diagnosis.to_string());
+                    }
+                }
+                Ok(LineEvent::Replace(_)) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            state = states.recv() => match state {
+                Ok(state) if state.running => diagnoser = Diagnoser::default(),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnose(log: &str) -> Vec<Diagnosis> {
+        let mut diagnoser = Diagnoser::default();
+        log.lines()
+            .filter_map(|line| diagnoser.line(line))
+            .collect()
+    }
+
+    #[test]
+    fn test_cuda_out_of_memory() {
+        let log = "\
+ i 11:02:14 ethminer Epoch : 430 Difficulty : 4.00 Gh
+cu 11:02:15 cuda-0   Generating DAG + Light : 4.43 GB
+cu 11:02:15 cuda-0   CUDA error in func ethash_generate_dag at line 82 calling cudaMalloc(&dag, dagSize) failed with error: out of memory
+ i 11:02:16 ethminer Job: 9f2c61a8… block 14300312 eu1.ethermine.org:4444
+cu 11:02:18 cuda-0   CUDA error in func ethash_generate_dag at line 82 calling cudaMalloc(&dag, dagSize) failed with error: out of memory";
+        assert_eq!(diagnose(log), [DAG_TOO_LARGE]);
+    }
+
+    #[test]
+    fn test_insufficient_device_memory() {
+        let log = "\
+cl 09:14:51 cl-0     Using PciId : 0000:01:00.0 Ellesmere OpenCL 2.0 AMD-APP (3240.7) Memory : 4.00 GB (4294967296 B)
+cl 09:14:51 cl-0     Error: Insufficient device memory
+ X 09:14:51 cl-0     Mining suspended ...";
+        assert_eq!(diagnose(log), [DAG_TOO_LARGE]);
+    }
+
+    #[test]
+    fn test_no_opencl() {
+        let log = "\
+ i 20:41:03 ethminer ethminer 0.18.0
+ i 20:41:03 ethminer Build: linux/release/gnu
+Error: No OpenCL platforms found
+No usable mining devices found";
+        let diagnoses = diagnose(log);
+        assert_eq!(diagnoses.len(), 1);
+        assert_eq!(diagnoses[0].problem, "The miner found no OpenCL driver");
+    }
+
+    #[test]
+    fn test_no_cuda() {
+        let log = "\
+ i 20:41:03 ethminer ethminer 0.18.0
+CUDA error: no CUDA-capable device is detected
+No usable mining devices found";
+        let diagnoses = diagnose(log);
+        assert_eq!(diagnoses.len(), 1);
+        assert_eq!(
+            diagnoses[0].problem,
+            "The miner found no usable CUDA driver"
+        );
+    }
+
+    #[test]
+    fn test_bad_pool_host() {
+        let log = "\
+ i 13:20:11 ethminer Selected pool eu1.ethermine.rog:4444
+ X 13:20:11 ethminer Could not resolve host eu1.ethermine.rog, Host not found (authoritative)
+ X 13:20:11 ethminer No connection. Suspend mining ...";
+        let diagnoses = diagnose(log);
+        assert_eq!(diagnoses.len(), 1);
+        assert_eq!(
+            diagnoses[0].problem,
+            "The address of the pool could not be found"
+        );
+    }
+
+    #[test]
+    fn test_pool_unreachable() {
+        let attempt = "\
+ i 13:20:11 ethminer Selected pool eu1.ethermine.org:14444
+ X 13:20:41 ethminer Error  172.65.207.106:14444 [ Connection timed out ]
+ X 13:20:41 ethminer No connection. Suspend mining ...
+";
+        // Losing the pool now and then is no reason for a diagnosis
+        let connected = " i 13:21:02 ethminer Established connection to eu1.ethermine.org [172.65.207.106:4444]\n";
+        assert!(diagnose(&[attempt, connected, attempt].concat()).is_empty());
+
+        let diagnoses = diagnose(&attempt.repeat(3));
+        assert_eq!(diagnoses.len(), 1);
+        assert_eq!(
+            diagnoses[0].problem,
+            "The miner keeps failing to connect to the pool"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_signals() {
+        use std::os::unix::process::ExitStatusExt;
+        // Raw wait statuses, as reported when the kernel kills the miner
+        let killed = std::process::ExitStatus::from_raw(9);
+        assert_eq!(
+            diagnose_exit(&killed).unwrap().problem,
+            "The miner was killed, most likely because the system ran out of memory"
+        );
+        let segfault = std::process::ExitStatus::from_raw(11);
+        assert_eq!(
+            diagnose_exit(&segfault).unwrap().problem,
+            "The miner crashed with a segmentation fault"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_codes() {
+        // Raw wait statuses hold the exit code in the second byte
+        use std::os::unix::process::ExitStatusExt;
+        let exited = |code: i32| std::process::ExitStatus::from_raw(code << 8);
+        assert_eq!(
+            diagnose_exit(&exited(1)).unwrap().problem,
+            "The miner rejected the arguments it was started with"
+        );
+        assert_eq!(
+            diagnose_exit(&exited(2)).unwrap().problem,
+            "The miner stopped on an error, most often as it found no usable mining devices"
+        );
+        assert_eq!(diagnose_exit(&exited(0)), None);
+        assert_eq!(diagnose_exit(&exited(3)), None);
+    }
+
+    #[test]
+    fn test_diagnosis_message() {
+        assert_eq!(
+            DAG_TOO_LARGE.to_string(),
+            "The GPU doesn't have enough memory for the DAG of the current epoch\n\
+             Suggested fix: The DAG grows with every epoch, so this card can't mine Ethash \
+             anymore. Mine with GPUs that have more memory"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Number of blocks of an epoch, the DAG changes with every epoch
+pub const EPOCH_LENGTH: u64 = 30_000;
+/// Average time between blocks, to forecast when an epoch begins
+const BLOCK_TIME_SECS: f64 = 13.2;
+
+const DATASET_BYTES_INIT: u64 = 1 << 30;
+const DATASET_BYTES_GROWTH: u64 = 1 << 23;
+const CACHE_BYTES_INIT: u64 = 1 << 24;
+const CACHE_BYTES_GROWTH: u64 = 1 << 17;
+const MIX_BYTES: u64 = 128;
+const HASH_BYTES: u64 = 64;
+
+pub fn epoch_of_block(block: u64) -> u64 {
+    block / EPOCH_LENGTH
+}
+
+/// Size of the light cache the DAG is generated from, in bytes
+pub fn cache_size(epoch: u64) -> u64 {
+    let mut size = CACHE_BYTES_INIT + CACHE_BYTES_GROWTH * epoch - HASH_BYTES;
+    while !is_prime(size / HASH_BYTES) {
+        size -= 2 * HASH_BYTES;
+    }
+    size
+}
+
+/// Size of the DAG, in bytes
+pub fn dag_size(epoch: u64) -> u64 {
+    let mut size = DATASET_BYTES_INIT + DATASET_BYTES_GROWTH * epoch - MIX_BYTES;
+    while !is_prime(size / MIX_BYTES) {
+        size -= 2 * MIX_BYTES;
+    }
+    size
+}
+
+/// Memory a GPU needs to mine the epoch, as ethminer keeps both the DAG and the cache on it
+pub fn required_memory(epoch: u64) -> u64 {
+    dag_size(epoch) + cache_size(epoch)
+}
+
+/// The first epoch from the given one on that needs more memory than the GPU has
+pub fn first_epoch_exceeding(memory: u64, from: u64) -> u64 {
+    // The sizes only grow with the epoch, and the DAG alone grows by 8 MB per epoch, so the
+    // DAG of the high epoch is larger than the memory. Searched in halves, as each size
+    // takes a search for a prime.
+    let (mut low, mut high) = (from, from.max(memory / DATASET_BYTES_GROWTH + 1));
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if required_memory(mid) <= memory {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+/// Forecasts the unix time the epoch begins, from the block the chain is at now
+pub fn epoch_start(epoch: u64, block: u64, now_secs: u64) -> u64 {
+    let blocks = (epoch * EPOCH_LENGTH).saturating_sub(block);
+    now_secs + (blocks as f64 * BLOCK_TIME_SECS) as u64
+}
+
+fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    let mut i = 2;
+    while i * i <= n {
+        if n.is_multiple_of(i) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Formats a size in bytes like ethminer does, in binary units, e.g. "4.36 GB"
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, units[unit])
+}
+
+/// A mining device, as listed by ethminer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Device {
+    pub index: usize,
+    pub pci_id: String,
+    pub name: String,
+    /// Total memory in bytes
+    pub memory: u64,
+}
+
+fn device_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // "  0 01:00.0   Gpu  GeForce GTX 1060 6GB          Yes  6.1   Yes        6.00 GB ..."
+    // The name ends at the column gap, the total memory is the first size after it
+    RE.get_or_init(|| {
+        Regex::new(r"^\s*(\d+)\s+(\S+)\s+(?:Gpu|Cpu|Acc)\s+(.+?)\s{2,}.*?(\d+(?:\.\d+)?) (GB|MB)\b")
+            .unwrap()
+    })
+}
+
+/// Reads the table of devices printed by "ethminer --list-devices"
+pub fn parse_devices(output: &str) -> Vec<Device> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let caps = device_regex().captures(line)?;
+            let unit = match &caps[5] {
+                "GB" => 1u64 << 30,
+                _ => 1 << 20,
+            };
+            Some(Device {
+                index: caps[1].parse().ok()?,
+                pci_id: caps[2].to_string(),
+                name: caps[3].to_string(),
+                memory: (caps[4].parse::<f64>().ok()? * unit as f64) as u64,
+            })
+        })
+        .collect()
+}
+
+/// Asks ethminer for the mining devices of the rig
+pub async fn list_devices(bin_path: &str) -> Result<Vec<Device>, String> {
+    let output = tokio::process::Command::new(bin_path)
+        .arg("--list-devices")
+        .output()
+        .await
+        .map_err(|e| format!("Could not run {bin_path}: {e}"))?;
+    let devices = parse_devices(&String::from_utf8_lossy(&output.stdout));
+    if devices.is_empty() {
+        return Err(format!("{bin_path} listed no devices"));
+    }
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sizes() {
+        // From the published Ethash size tables
+        let table = [
+            (0, 1073739904, 16776896),
+            (1, 1082130304, 16907456),
+            (2, 1090514816, 17039296),
+            (100, 1912601216, 29882816),
+            (384, 4294962304, 67108672),
+            (512, 5368706944, 83886016),
+        ];
+        for (epoch, dag, cache) in table {
+            assert_eq!(dag_size(epoch), dag, "epoch {epoch}");
+            assert_eq!(cache_size(epoch), cache, "epoch {epoch}");
+        }
+        assert_eq!(epoch_of_block(14_300_312), 476);
+    }
+
+    #[test]
+    fn test_memory_fit() {
+        let gb = 1u64 << 30;
+        assert_eq!(first_epoch_exceeding(4 * gb, 0), 377);
+        assert_eq!(first_epoch_exceeding(6 * gb, 377), 629);
+        assert!(required_memory(376) <= 4 * gb);
+        assert!(required_memory(377) > 4 * gb);
+        // Already too small
+        assert_eq!(first_epoch_exceeding(2 * gb, 400), 400);
+        let last = first_epoch_exceeding(24 * gb, 476);
+        assert!(required_memory(last - 1) <= 24 * gb);
+        assert!(required_memory(last) > 24 * gb);
+    }
+
+    #[test]
+    fn test_epoch_start() {
+        let now = 1_700_000_000;
+        assert_eq!(epoch_start(477, 14_300_312, now), now + 9688 * 132 / 10);
+        assert_eq!(epoch_start(476, 14_300_312, now), now);
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(dag_size(0)), "1024.00 MB");
+        assert_eq!(format_bytes(required_memory(430)), "4.43 GB");
+        assert_eq!(format_bytes(512), "512.00 B");
+    }
+
+    #[test]
+    fn test_parse_devices() {
+        let output = "\
+ethminer 0.18.0
+Build: linux/release/gnu
+
+ Id Pci Id    Type Name                          CUDA SM   CL    Total Memory  Cl Max Alloc Cl Max W.Grp
+--- --------- ---- ----------------------------- ---- ----- ----- ------------- ------------ ------------
+  0 01:00.0   Gpu  GeForce GTX 1060 6GB          Yes  6.1   Yes        5.93 GB      1.48 GB      1024 B
+  1 03:00.0   Gpu  Ellesmere                     No         Yes        4.00 GB      3.40 GB       256 B
+";
+        assert_eq!(
+            parse_devices(output),
+            [
+                Device {
+                    index: 0,
+                    pci_id: "01:00.0".to_string(),
+                    name: "GeForce GTX 1060 6GB".to_string(),
+                    memory: (5.93 * (1u64 << 30) as f64) as u64,
+                },
+                Device {
+                    index: 1,
+                    pci_id: "03:00.0".to_string(),
+                    name: "Ellesmere".to_string(),
+                    memory: 4 << 30,
+                },
+            ]
+        );
+        assert!(parse_devices("No usable mining devices found").is_empty());
+    }
+}
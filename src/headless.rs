@@ -0,0 +1,233 @@
+use crate::ansi::StyledLine;
+use crate::cli::Args;
+use crate::launch;
+use crate::miner_controller::{MinerController, MinerEvent};
+use crate::miner_handle::{start_tasks, MinerHandle};
+use crate::output_reader::LineEvent;
+use crate::profiles::{load_profile, state_dir, Profiles};
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Log files larger than this are rotated when headless mode starts
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Runs the miner without a window until SIGINT or SIGTERM is received.
+/// The miner output is logged to miner.log, and the events of headless
+/// mode itself to ethminer-gui.log. Returns the exit code of the process.
+pub async fn run_headless(args: Args) -> i32 {
+    // Taken before opening the logs, which would rotate the logs of the running instance
+    #[cfg(unix)]
+    let _lock = match crate::instance::InstanceLock::acquire(&crate::instance::lock_path()) {
+        Ok(Some(lock)) => Some(lock),
+        Ok(None) => {
+            eprintln!(
+                "Another instance is running the miner, control it with \"ethminer-gui ctl\""
+            );
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("Not using the instance lock: {e}");
+            None
+        }
+    };
+
+    let log_dir = args.log_dir.clone().unwrap_or_else(state_dir);
+    let mut log = match Log::open(&log_dir) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Could not open the logs in {}: {e}", log_dir.display());
+            return 1;
+        }
+    };
+
+    let (name, settings) = match load_profile(&Profiles::path(), args.profile.as_deref()) {
+        Ok((name, settings)) => {
+            log.event(&format!("Mining with profile \"{name}\""));
+            (name, settings)
+        }
+        Err(e) => {
+            log.event(&e);
+            return 1;
+        }
+    };
+
+    let controller = MinerController::new();
+    #[cfg(unix)]
+    {
+        let socket = crate::ipc::socket_path();
+        let server = crate::ipc::Server::new(controller.clone(), Profiles::path());
+        if let Err(e) = server.listen(&socket).await {
+            log.event(&format!("Could not serve the control socket: {e}"));
+            return 1;
+        }
+        log.event(&format!(
+            "Serving the control socket at {}",
+            socket.display()
+        ));
+    }
+    let (mut lines_rx, mut error_rx, mut events_rx) = {
+        let mc = controller.lock().await;
+        (
+            mc.lines_tx.subscribe(),
+            mc.error_tx.subscribe(),
+            mc.events_tx.subscribe(),
+        )
+    };
+    let miner = MinerHandle::local(controller.clone()).await;
+    // Headless mode is asked to mine, whether the previous session crashed or not
+    start_tasks(&miner).await;
+    MinerController::run_profile(controller.clone(), name, settings);
+
+    let mut signals = match Signals::new() {
+        Ok(signals) => signals,
+        Err(e) => {
+            log.event(&format!("Could not listen for signals: {e}"));
+            return 1;
+        }
+    };
+
+    let code = loop {
+        tokio::select! {
+            line = lines_rx.recv() => match line {
+                Ok(event) => log.miner(&event),
+                Err(RecvError::Lagged(n)) => log.event(&format!("{n} lines of miner output were not logged")),
+                Err(RecvError::Closed) => {}
+            },
+            error = error_rx.recv() => {
+                if let Ok(error) = error {
+                    log.event(&error);
+                }
+            }
+            event = events_rx.recv() => {
+                if let Ok(MinerEvent::SpawnFailed) = event {
+                    // Nothing will be mined without a miner
+                    break 1;
+                }
+            }
+            signal = signals.recv() => {
+                log.event(&format!("Received {signal}, stopping the miner"));
+                break 0;
+            }
+        }
+    };
+
+    controller.lock().await.shutdown().await;
+    launch::record_clean_exit();
+    // Logs the last messages of the controller
+    while let Ok(event) = lines_rx.try_recv() {
+        log.miner(&event);
+    }
+    while let Ok(error) = error_rx.try_recv() {
+        log.event(&error);
+    }
+    #[cfg(unix)]
+    let _ = std::fs::remove_file(crate::ipc::socket_path());
+    log.event("Stopped");
+    code
+}
+
+/// The signals that stop headless mode, and the tray of --minimized
+pub struct Signals {
+    #[cfg(unix)]
+    sigterm: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    pub fn new() -> std::io::Result<Signals> {
+        Ok(Signals {
+            #[cfg(unix)]
+            sigterm: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?,
+        })
+    }
+
+    /// Waits for a signal, returning its name
+    pub async fn recv(&mut self) -> &'static str {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = self.sigterm.recv() => "SIGTERM",
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = tokio::signal::ctrl_c().await;
+            "Ctrl-C"
+        }
+    }
+}
+
+/// The log files of headless mode
+struct Log {
+    miner: File,
+    events: File,
+}
+
+impl Log {
+    fn open(dir: &Path) -> std::io::Result<Log> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Log {
+            miner: open_log(&dir.join("miner.log"))?,
+            events: open_log(&dir.join("ethminer-gui.log"))?,
+        })
+    }
+
+    /// Logs a line of miner output, which already contains a timestamp.
+    /// Redrawn lines are progress output, and are left out of the log.
+    fn miner(&mut self, event: &LineEvent) {
+        let line = match event {
+            LineEvent::Push(line) => StyledLine::parse(line).text,
+            LineEvent::Replace(_) => return,
+        };
+        if let Err(e) = writeln!(self.miner, "{line}") {
+            eprintln!("Could not write to the miner log: {e}");
+        }
+    }
+
+    /// Logs an event of headless mode, also printing it
+    fn event(&mut self, message: &str) {
+        println!("{message}");
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        if let Err(e) = writeln!(self.events, "[{secs}] {message}") {
+            eprintln!("Could not write to the log: {e}");
+        }
+    }
+}
+
+/// Opens a log file for appending, moving it to "<name>.1" first if it has grown too large
+fn open_log(path: &Path) -> std::io::Result<File> {
+    if std::fs::metadata(path).is_ok_and(|m| m.len() > MAX_LOG_SIZE) {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        std::fs::rename(path, rotated)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_rotation() {
+        let dir = std::env::temp_dir().join(format!("ethminer-gui-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("miner.log");
+
+        std::fs::write(&path, vec![b'x'; MAX_LOG_SIZE as usize + 1]).unwrap();
+        let mut file = open_log(&path).unwrap();
+        writeln!(file, "fresh").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fresh\n");
+        assert!(dir.join("miner.log.1").exists());
+
+        // Small logs are appended to
+        drop(file);
+        writeln!(open_log(&path).unwrap(), "more").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fresh\nmore\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
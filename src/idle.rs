@@ -0,0 +1,437 @@
+use crate::app_options;
+use crate::miner_handle::MinerHandle;
+use crate::schedule::{Clock, SystemClock};
+
+use std::time::Duration;
+
+/// Name of the pause held while the computer is in use, see MinerController::pause
+const POLICY: &str = "idle";
+
+/// Time between checks of the idle time
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Number of checks in a row that must see input before the user counts as back,
+/// so brushing against the mouse doesn't stop mining
+const ACTIVE_POLLS: u32 = 2;
+
+/// Where the time since the last input is read from
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleSourceKind {
+    /// The X11 screensaver extension if there is an X display, logind otherwise
+    #[default]
+    Auto,
+    X11,
+    /// The IdleHint of the session, which the desktop sets after its own idle delay
+    Logind,
+}
+
+impl IdleSourceKind {
+    pub const ALL: [IdleSourceKind; 3] = [
+        IdleSourceKind::Auto,
+        IdleSourceKind::X11,
+        IdleSourceKind::Logind,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            IdleSourceKind::Auto => "Automatic",
+            IdleSourceKind::X11 => "X11 screensaver",
+            IdleSourceKind::Logind => "logind",
+        }
+    }
+}
+
+/// Mining only while nobody uses the computer
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct IdleOptions {
+    pub enabled: bool,
+    /// Minutes without input after which the miner is started
+    pub idle_minutes: u32,
+    pub source: IdleSourceKind,
+}
+
+impl Default for IdleOptions {
+    fn default() -> Self {
+        IdleOptions {
+            enabled: false,
+            idle_minutes: 10,
+            source: IdleSourceKind::Auto,
+        }
+    }
+}
+
+/// Tells how long the computer has gone without input
+pub trait IdleSource: Send {
+    fn idle_time(&mut self) -> Result<Duration, String>;
+}
+
+/// Opens the source of the kind
+#[cfg(target_os = "linux")]
+pub fn open_source(kind: IdleSourceKind) -> Result<Box<dyn IdleSource>, String> {
+    match kind {
+        IdleSourceKind::X11 => Ok(Box::new(x11::X11Source::open()?)),
+        IdleSourceKind::Logind => Ok(Box::new(logind::LogindSource::open()?)),
+        IdleSourceKind::Auto => match x11::X11Source::open() {
+            Ok(source) => Ok(Box::new(source)),
+            Err(x11_error) => match logind::LogindSource::open() {
+                Ok(source) => Ok(Box::new(source)),
+                Err(logind_error) => Err(format!("{x11_error}, and {logind_error}")),
+            },
+        },
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open_source(_kind: IdleSourceKind) -> Result<Box<dyn IdleSource>, String> {
+    Err("Idle detection is only supported on linux".to_string())
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use super::IdleSource;
+
+    use std::time::Duration;
+    use x11_dl::xlib::{Display, Xlib};
+    use x11_dl::xss::{XScreenSaverInfo, Xss};
+
+    /// Reads the idle time from the screensaver extension of the X server
+    pub struct X11Source {
+        xlib: Xlib,
+        xss: Xss,
+        display: *mut Display,
+        info: *mut XScreenSaverInfo,
+    }
+
+    impl X11Source {
+        pub fn open() -> Result<X11Source, String> {
+            let xlib = Xlib::open().map_err(|e| format!("Could not load libX11: {e}"))?;
+            let xss = Xss::open().map_err(|e| format!("Could not load libXss: {e}"))?;
+            // Safe, as the display and info are checked before use, and freed on drop
+            unsafe {
+                let display = (xlib.XOpenDisplay)(std::ptr::null());
+                if display.is_null() {
+                    return Err("Could not open the X display".to_string());
+                }
+                let info = (xss.XScreenSaverAllocInfo)();
+                if info.is_null() {
+                    (xlib.XCloseDisplay)(display);
+                    return Err("Could not allocate the X screensaver info".to_string());
+                }
+                Ok(X11Source {
+                    xlib,
+                    xss,
+                    display,
+                    info,
+                })
+            }
+        }
+    }
+
+    // Safe, as the display is only used by one thread at a time, through &mut self
+    unsafe impl Send for X11Source {}
+
+    impl IdleSource for X11Source {
+        fn idle_time(&mut self) -> Result<Duration, String> {
+            // Safe, as the display and info are valid until drop
+            unsafe {
+                let root = (self.xlib.XDefaultRootWindow)(self.display);
+                if (self.xss.XScreenSaverQueryInfo)(self.display, root, self.info) == 0 {
+                    return Err("The X server has no screensaver extension".to_string());
+                }
+                // A c_ulong, which is only 32 bits on some targets
+                #[allow(clippy::useless_conversion)]
+                Ok(Duration::from_millis(u64::from((*self.info).idle)))
+            }
+        }
+    }
+
+    impl Drop for X11Source {
+        fn drop(&mut self) {
+            // Safe, as both were allocated by open and aren't used anymore
+            unsafe {
+                (self.xlib.XFree)(self.info.cast());
+                (self.xlib.XCloseDisplay)(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod logind {
+    use super::IdleSource;
+
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use zbus::dbus_proxy;
+    use zbus::CacheProperties;
+
+    /// The login session of the user, see
+    /// https://www.freedesktop.org/software/systemd/man/org.freedesktop.login1.html
+    #[dbus_proxy(
+        interface = "org.freedesktop.login1.Session",
+        default_service = "org.freedesktop.login1",
+        default_path = "/org/freedesktop/login1/session/auto",
+        gen_async = false
+    )]
+    trait Session {
+        #[dbus_proxy(property)]
+        fn idle_hint(&self) -> zbus::Result<bool>;
+
+        /// Microseconds since the unix epoch
+        #[dbus_proxy(property)]
+        fn idle_since_hint(&self) -> zbus::Result<u64>;
+    }
+
+    /// Reads the idle hint of the session from logind. The desktop only sets it once it
+    /// considers itself idle, so short idle times aren't seen.
+    pub struct LogindSource {
+        proxy: SessionProxy<'static>,
+    }
+
+    impl LogindSource {
+        pub fn open() -> Result<LogindSource, String> {
+            let open = || {
+                let connection = zbus::blocking::Connection::system()?;
+                SessionProxy::builder(&connection)
+                    // Read each time, as the changes are only signalled by some versions
+                    .cache_properties(CacheProperties::No)
+                    .build()
+            };
+            let proxy = open().map_err(|e| format!("Could not reach logind: {e}"))?;
+            Ok(LogindSource { proxy })
+        }
+    }
+
+    impl IdleSource for LogindSource {
+        fn idle_time(&mut self) -> Result<Duration, String> {
+            let read = || -> zbus::Result<Duration> {
+                if !self.proxy.idle_hint()? {
+                    return Ok(Duration::ZERO);
+                }
+                let since = Duration::from_micros(self.proxy.idle_since_hint()?);
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                Ok(now.saturating_sub(since))
+            };
+            read().map_err(|e| format!("Could not read the idle hint from logind: {e}"))
+        }
+    }
+}
+
+/// Whether the user left the computer or is using it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Activity {
+    Idle,
+    Active,
+}
+
+/// Tells from the idle time when the user leaves and comes back. Leaving takes the idle
+/// delay without input, coming back takes input over ACTIVE_POLLS checks, so a single touch
+/// of the mouse doesn't make the miner flap.
+#[derive(Default)]
+pub struct IdleTracker {
+    activity: Option<Activity>,
+    /// Checks in a row that saw input while idle
+    active_polls: u32,
+}
+
+impl IdleTracker {
+    /// Reads the idle time from the source, returns the activity if it changed
+    pub fn poll(
+        &mut self,
+        source: &mut dyn IdleSource,
+        idle_after: Duration,
+    ) -> Result<Option<Activity>, String> {
+        let idle_time = source.idle_time()?;
+        let activity = match self.activity {
+            Some(Activity::Idle) => {
+                if idle_time < POLL_INTERVAL {
+                    self.active_polls += 1;
+                } else {
+                    self.active_polls = 0;
+                }
+                if self.active_polls >= ACTIVE_POLLS {
+                    Activity::Active
+                } else {
+                    Activity::Idle
+                }
+            }
+            Some(Activity::Active) | None if idle_time >= idle_after => Activity::Idle,
+            Some(Activity::Active) | None => Activity::Active,
+        };
+        if self.activity == Some(activity) {
+            return Ok(None);
+        }
+        self.activity = Some(activity);
+        self.active_polls = 0;
+        Ok(Some(activity))
+    }
+}
+
+/// Reads the idle time from the source of the options, opening it again when the options
+/// change or it fails
+#[derive(Default)]
+struct Detector {
+    source: Option<(IdleSourceKind, Box<dyn IdleSource>)>,
+    /// The kind that could not be opened, so the error is only logged once
+    failed: Option<IdleSourceKind>,
+    tracker: IdleTracker,
+}
+
+impl Detector {
+    /// Returns the activity if it changed
+    fn poll(&mut self, idle: &IdleOptions) -> Option<Activity> {
+        let source = match &mut self.source {
+            Some((kind, source)) if *kind == idle.source => source,
+            _ => match open_source(idle.source) {
+                Ok(opened) => {
+                    println!("Detecting idle time with {}", idle.source.label());
+                    self.failed = None;
+                    &mut self.source.insert((idle.source, opened)).1
+                }
+                Err(e) => {
+                    if self.failed != Some(idle.source) {
+                        println!("Could not detect the idle time: {e}");
+                        self.failed = Some(idle.source);
+                    }
+                    return None;
+                }
+            },
+        };
+        let idle_after = Duration::from_secs(idle.idle_minutes as u64 * 60);
+        match self.tracker.poll(source.as_mut(), idle_after) {
+            Ok(activity) => activity,
+            Err(e) => {
+                println!("{e}");
+                self.source = None;
+                None
+            }
+        }
+    }
+}
+
+/// Starts the miner once the computer is idle, and pauses it while it is in use, as set in
+/// the options, see app_options::follow
+pub fn start(miner: &MinerHandle) {
+    let mut detector = Detector::default();
+    app_options::follow(
+        miner,
+        POLL_INTERVAL,
+        POLL_INTERVAL,
+        move |miner, options| {
+            let enabled = options.idle.enabled;
+            let activity = if enabled {
+                // The sources block on the X server or D-Bus
+                tokio::task::block_in_place(|| detector.poll(&options.idle))
+            } else {
+                detector = Detector::default();
+                None
+            };
+            let schedule = &options.schedule;
+            let scheduled = !schedule.enabled || schedule.wants_mining(SystemClock.now());
+            async move {
+                match activity {
+                    None if !enabled => {
+                        miner.unpause(POLICY).await;
+                    }
+                    Some(Activity::Active) => {
+                        miner
+                            .pause(POLICY, "the computer is in use".to_string())
+                            .await
+                    }
+                    Some(Activity::Idle) => {
+                        let resumed = miner.unpause(POLICY).await;
+                        if !resumed && !miner.state().running && scheduled {
+                            miner
+                                .push_message(
+                                    "***** Starting the miner, as the computer is idle *****",
+                                )
+                                .await;
+                            miner.restart();
+                        }
+                    }
+                    None => {}
+                }
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gives the idle times it was made with, one per check
+    struct FakeSource(std::vec::IntoIter<Duration>);
+
+    impl IdleSource for FakeSource {
+        fn idle_time(&mut self) -> Result<Duration, String> {
+            self.0
+                .next()
+                .ok_or_else(|| "No more idle times".to_string())
+        }
+    }
+
+    /// The changes of activity over checks of the idle times, in seconds
+    fn activities(idle_secs: &[u64]) -> Vec<Option<Activity>> {
+        let mut source = FakeSource(
+            idle_secs
+                .iter()
+                .map(|&secs| Duration::from_secs(secs))
+                .collect::<Vec<_>>()
+                .into_iter(),
+        );
+        let mut tracker = IdleTracker::default();
+        idle_secs
+            .iter()
+            .map(|_| {
+                tracker
+                    .poll(&mut source, Duration::from_secs(10 * 60))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_leaving_and_coming_back() {
+        use Activity::*;
+        assert_eq!(
+            activities(&[0, 300, 599, 600, 605, 610, 0, 2]),
+            [
+                Some(Active),
+                None,
+                None,
+                Some(Idle),
+                None,
+                None,
+                None,
+                Some(Active)
+            ]
+        );
+        // Already idle when the app starts
+        assert_eq!(activities(&[3600, 3605]), [Some(Idle), None]);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        use Activity::*;
+        // Single touches of the mouse while idle are ignored
+        assert_eq!(
+            activities(&[600, 1, 30, 2, 40, 0]),
+            [Some(Idle), None, None, None, None, None]
+        );
+        // And input that stops for a moment is not leaving
+        assert_eq!(
+            activities(&[0, 20, 0, 120]),
+            [Some(Active), None, None, None]
+        );
+    }
+
+    #[test]
+    fn test_source_errors() {
+        let mut tracker = IdleTracker::default();
+        let mut source = FakeSource(Vec::new().into_iter());
+        assert!(tracker.poll(&mut source, Duration::from_secs(600)).is_err());
+    }
+}
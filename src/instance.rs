@@ -0,0 +1,101 @@
+use crate::miner_controller::MinerController;
+use crate::profiles::{activate_profile, load_profile};
+
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[cfg(unix)]
+use std::fs::File;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Applies the arguments of a launch to the controller, whether the launch is the one running
+/// the controller or a second launch forwarding them. The profile becomes the active profile,
+/// restarting the miner with it if it is running, and mining is started if asked to.
+pub async fn apply_launch(
+    controller: Arc<Mutex<MinerController>>,
+    profiles_path: &Path,
+    profile: Option<&str>,
+    start_mining: bool,
+) -> Result<(), String> {
+    let running = controller.lock().await.state().running;
+    if let Some(profile) = profile {
+        let settings = activate_profile(profiles_path, profile)?;
+        if running || start_mining {
+            MinerController::run_profile(controller, profile.to_string(), settings);
+        }
+    } else if start_mining && running {
+        println!("The miner is already running");
+    } else if start_mining {
+        let (name, settings) = load_profile(profiles_path, None)?;
+        MinerController::run_profile(controller, name, settings);
+    }
+    Ok(())
+}
+
+/// The location of the instance lock, next to the control socket
+#[cfg(unix)]
+pub fn lock_path() -> PathBuf {
+    crate::ipc::socket_path().with_extension("lock")
+}
+
+/// Lock held by the instance running the miner, so launching the app again doesn't start
+/// a second controller that spawns ethminer on the same GPUs. The OS releases the lock
+/// when the process exits, also when it crashes, so a stale lock file doesn't matter.
+#[cfg(unix)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+#[cfg(unix)]
+impl InstanceLock {
+    /// Takes the lock, returns None if another instance holds it
+    pub fn acquire(path: &Path) -> Result<Option<InstanceLock>, String> {
+        use std::io::Write;
+        use std::os::unix::io::AsRawFd;
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            // Truncating before taking the lock would clear the pid of the instance holding it
+            .truncate(false)
+            .open(path)
+            .map_err(|e| format!("Could not open {}: {e}", path.display()))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(format!("Could not lock {}: {e}", path.display()));
+        }
+        // The pid is only written for people looking at the file
+        let _ = file
+            .set_len(0)
+            .and_then(|_| write!(file, "{}", std::process::id()));
+        Ok(Some(InstanceLock { _file: file }))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_lock() {
+        let path =
+            std::env::temp_dir().join(format!("ethminer-gui-lock-{}.lock", std::process::id()));
+        let lock = InstanceLock::acquire(&path).unwrap();
+        assert!(lock.is_some());
+        // Locks of separately opened files exclude each other, also within a process
+        assert!(InstanceLock::acquire(&path).unwrap().is_none());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+
+        drop(lock);
+        assert!(InstanceLock::acquire(&path).unwrap().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,776 @@
+use crate::ansi::StyledLine;
+use crate::instance::apply_launch;
+use crate::miner_controller::{MinerController, MinerState};
+use crate::output_buffer::{OutputBuffer, OutputSnapshot};
+use crate::output_reader::LineEvent;
+use crate::profiles::{activate_profile, load_profile};
+use crate::stats::StatsSnapshot;
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration};
+
+/// Version of the control socket protocol.
+/// The server greets every client with its version, and clients
+/// declare theirs with a hello request, see Command::Hello
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The location of the control socket, "$XDG_RUNTIME_DIR/ethminer-gui.sock"
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir).join("ethminer-gui.sock"),
+        // The temp dir is shared between users, so the socket is made unique per user
+        _ => std::env::temp_dir().join(format!("ethminer-gui-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+/// A request from a client, sent as a single line of json.
+/// e.g. {"id": 1, "cmd": "start", "profile": "backup"}
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct Request {
+    /// Echoed in the response, so clients can match responses to requests
+    pub id: u64,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    /// Declares the protocol version of the client, fails if it isn't supported
+    Hello {
+        version: u32,
+    },
+    /// Starts the miner with the profile, or the active profile if none is given
+    Start {
+        #[serde(default)]
+        profile: Option<String>,
+    },
+    Stop,
+    /// Restarts the miner with the profile it was last started with
+    Restart,
+    /// Makes the profile the active profile, restarting the miner with it if it is running
+    SwitchProfile {
+        profile: String,
+    },
+    GetState,
+    GetStats,
+    ResetStats,
+    /// Returns the most recent lines of output, without the escape sequences
+    GetLines {
+        count: usize,
+    },
+    /// Applies the arguments of launching the app again, see instance::apply_launch.
+    /// The reply tells whether a window was asked to show itself.
+    Launch {
+        #[serde(default)]
+        profile: Option<String>,
+        #[serde(default)]
+        start_mining: bool,
+        #[serde(default)]
+        show_window: bool,
+    },
+    /// Starts sending events of the topics to the client
+    Subscribe {
+        topics: Vec<Topic>,
+        /// Number of already buffered lines to send before the new lines
+        #[serde(default)]
+        backlog: usize,
+    },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    /// Every line of miner output
+    Lines,
+    /// The statistics, once a second
+    Stats,
+    /// The state, whenever the miner is started or stopped
+    State,
+    /// Requests to show the window, subscribed to by the windows attached to the controller
+    Window,
+}
+
+/// A message from the server, sent as a single line of json
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Sent once when a client connects
+    Hello {
+        version: u32,
+    },
+    Response {
+        id: u64,
+        result: Reply,
+    },
+    Event(Event),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum Reply {
+    Ok,
+    Error { message: String },
+    State(MinerState),
+    Stats(StatsSnapshot),
+    Lines { lines: Vec<String> },
+    Launched { window_shown: bool },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A line of output, with its ANSI escape sequences.
+    /// If replace is set, the line redraws the previous line.
+    Line {
+        text: String,
+        #[serde(default)]
+        replace: bool,
+    },
+    State(MinerState),
+    Stats(StatsSnapshot),
+    ShowWindow,
+}
+
+/// Serves the control socket of a MinerController
+pub struct Server {
+    controller: Arc<Mutex<MinerController>>,
+    /// Profiles file that start requests load their settings from
+    profiles_path: PathBuf,
+}
+
+impl Server {
+    pub fn new(controller: Arc<Mutex<MinerController>>, profiles_path: PathBuf) -> Arc<Server> {
+        Arc::new(Server {
+            controller,
+            profiles_path,
+        })
+    }
+
+    /// Binds the socket, and starts serving it in a new task.
+    /// Fails with AddrInUse if another instance is serving the socket already.
+    pub async fn listen(self: Arc<Self>, path: &Path) -> io::Result<()> {
+        let listener = bind(path).await?;
+        tokio::spawn(self.serve(listener));
+        Ok(())
+    }
+
+    async fn serve(self: Arc<Self>, listener: UnixListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(self.clone().handle_connection(stream));
+                }
+                Err(e) => {
+                    println!("Error accepting a control socket connection: {e}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    }
+
+    async fn handle_connection(self: Arc<Self>, stream: UnixStream) {
+        let (read, write) = stream.into_split();
+        let (out_tx, out_rx) = mpsc::channel(256);
+        tokio::spawn(write_messages(write, out_rx));
+        let _ = out_tx
+            .send(ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+            })
+            .await;
+
+        let mut subscriptions = Vec::new();
+        let mut lines = BufReader::new(read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (id, result) = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => (
+                    request.id,
+                    self.handle(request.command, &out_tx, &mut subscriptions)
+                        .await,
+                ),
+                Err(e) => (0, error(format!("Invalid request: {e}"))),
+            };
+            if out_tx
+                .send(ServerMessage::Response { id, result })
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        for subscription in subscriptions {
+            subscription.abort();
+        }
+    }
+
+    async fn handle(
+        &self,
+        command: Command,
+        out_tx: &mpsc::Sender<ServerMessage>,
+        subscriptions: &mut Vec<JoinHandle<()>>,
+    ) -> Reply {
+        match command {
+            Command::Hello { version } if version == PROTOCOL_VERSION => Reply::Ok,
+            Command::Hello { version } => error(format!(
+                "Protocol version {version} is not supported, the controller uses version {PROTOCOL_VERSION}"
+            )),
+            Command::Start { profile } => self.start(profile.as_deref()),
+            Command::Stop => {
+                MinerController::kill_child_miner(self.controller.clone());
+                Reply::Ok
+            }
+            Command::Restart => {
+                let profile = self.controller.lock().await.profile.clone();
+                self.start(profile.as_deref())
+            }
+            Command::SwitchProfile { profile } => {
+                match activate_profile(&self.profiles_path, &profile) {
+                    Ok(settings) => {
+                        let controller = self.controller.clone();
+                        if controller.lock().await.state().running {
+                            MinerController::run_profile(controller, profile, settings);
+                        }
+                        Reply::Ok
+                    }
+                    Err(e) => error(e),
+                }
+            }
+            Command::GetState => Reply::State(self.controller.lock().await.state()),
+            Command::GetStats => {
+                let stats = self.controller.lock().await.stats.clone();
+                let snapshot = stats.lock().unwrap().snapshot();
+                Reply::Stats(snapshot)
+            }
+            Command::ResetStats => {
+                let stats = self.controller.lock().await.stats.clone();
+                stats.lock().unwrap().reset();
+                Reply::Ok
+            }
+            Command::GetLines { count } => {
+                let buffer = self.controller.lock().await.buffer.clone();
+                let lines = last_lines(&buffer.lock().unwrap(), count)
+                    .iter()
+                    .map(|l| l.text.clone())
+                    .collect();
+                Reply::Lines { lines }
+            }
+            Command::Launch {
+                profile,
+                start_mining,
+                show_window,
+            } => {
+                let launched = apply_launch(
+                    self.controller.clone(),
+                    &self.profiles_path,
+                    profile.as_deref(),
+                    start_mining,
+                )
+                .await;
+                match launched {
+                    Ok(()) => {
+                        // Sending fails if there is no window to receive it
+                        let window_shown = show_window
+                            && self.controller.lock().await.show_window_tx.send(()).is_ok();
+                        Reply::Launched { window_shown }
+                    }
+                    Err(e) => error(e),
+                }
+            }
+            Command::Subscribe { topics, backlog } => {
+                for topic in topics {
+                    let task = self.subscribe(topic, backlog, out_tx.clone()).await;
+                    subscriptions.push(task);
+                }
+                Reply::Ok
+            }
+        }
+    }
+
+    fn start(&self, profile: Option<&str>) -> Reply {
+        match load_profile(&self.profiles_path, profile) {
+            Ok((name, settings)) => {
+                MinerController::run_profile(self.controller.clone(), name, settings);
+                Reply::Ok
+            }
+            Err(e) => error(e),
+        }
+    }
+
+    /// Starts a task forwarding the events of the topic to the client
+    async fn subscribe(
+        &self,
+        topic: Topic,
+        backlog: usize,
+        out_tx: mpsc::Sender<ServerMessage>,
+    ) -> JoinHandle<()> {
+        let mc = self.controller.lock().await;
+        match topic {
+            Topic::Lines => {
+                // Subscribing while holding the buffer lock, as lines are sent while
+                // holding it, so the backlog and the new lines don't overlap
+                let (mut rx, backlog) = {
+                    let buffer = mc.buffer.lock().unwrap();
+                    (mc.lines_tx.subscribe(), last_lines(&buffer, backlog))
+                };
+                // With the escape sequences of their colours, like the lines sent as they come
+                let backlog: Vec<String> = backlog.iter().map(|l| l.to_ansi()).collect();
+                tokio::spawn(async move {
+                    for text in backlog {
+                        let event = Event::Line {
+                            text,
+                            replace: false,
+                        };
+                        if out_tx.send(ServerMessage::Event(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                    // After lines were dropped, the line the client has last isn't the one
+                    // a redrawn line replaces
+                    let mut lagged = false;
+                    loop {
+                        let received = rx.recv().await;
+                        let dropped = matches!(received, Err(RecvError::Lagged(_)));
+                        let event = match received {
+                            Ok(LineEvent::Push(text)) => Event::Line {
+                                text,
+                                replace: false,
+                            },
+                            Ok(LineEvent::Replace(text)) => Event::Line {
+                                text,
+                                replace: !lagged,
+                            },
+                            Err(RecvError::Lagged(n)) => Event::Line {
+                                text: format!("***** {n} lines of output were dropped *****"),
+                                replace: false,
+                            },
+                            Err(RecvError::Closed) => return,
+                        };
+                        lagged = dropped;
+                        if out_tx.send(ServerMessage::Event(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                })
+            }
+            Topic::Stats => {
+                let stats = mc.stats.clone();
+                tokio::spawn(async move {
+                    let mut ticks = interval(Duration::from_secs(1));
+                    loop {
+                        ticks.tick().await;
+                        let snapshot = stats.lock().unwrap().snapshot();
+                        let event = ServerMessage::Event(Event::Stats(snapshot));
+                        if out_tx.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                })
+            }
+            Topic::State => {
+                let mut rx = mc.state_tx.subscribe();
+                let state = mc.state();
+                tokio::spawn(async move {
+                    let mut state = Ok(state);
+                    loop {
+                        match state {
+                            Ok(state) => {
+                                let event = ServerMessage::Event(Event::State(state));
+                                if out_tx.send(event).await.is_err() {
+                                    return;
+                                }
+                            }
+                            Err(RecvError::Lagged(_)) => {}
+                            Err(RecvError::Closed) => return,
+                        }
+                        state = rx.recv().await;
+                    }
+                })
+            }
+            Topic::Window => {
+                let mut rx = mc.show_window_tx.subscribe();
+                tokio::spawn(async move {
+                    loop {
+                        match rx.recv().await {
+                            Ok(()) => {
+                                let event = ServerMessage::Event(Event::ShowWindow);
+                                if out_tx.send(event).await.is_err() {
+                                    return;
+                                }
+                            }
+                            Err(RecvError::Lagged(_)) => {}
+                            Err(RecvError::Closed) => return,
+                        }
+                    }
+                })
+            }
+        }
+    }
+}
+
+fn error(message: String) -> Reply {
+    Reply::Error { message }
+}
+
+/// The last lines in the buffer
+fn last_lines(buffer: &OutputBuffer, count: usize) -> Vec<Arc<StyledLine>> {
+    let mut snapshot = OutputSnapshot::default();
+    buffer.update_snapshot(&mut snapshot);
+    let skip = snapshot.lines.len().saturating_sub(count);
+    snapshot.lines.iter().skip(skip).cloned().collect()
+}
+
+/// Binds the socket, replacing the socket of an instance that didn't exit cleanly
+async fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is used by another instance", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Writes the messages to the client, one per line, until the client disconnects
+async fn write_messages(mut write: OwnedWriteHalf, mut out_rx: mpsc::Receiver<ServerMessage>) {
+    while let Some(message) = out_rx.recv().await {
+        let mut json = serde_json::to_string(&message).expect("Could not serialize message");
+        json.push('\n');
+        if write.write_all(json.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Requests waiting for a response, None once the connection has closed
+type Pending = Arc<std::sync::Mutex<Option<HashMap<u64, oneshot::Sender<Reply>>>>>;
+
+/// Client of the control socket
+pub struct Client {
+    writer: Mutex<OwnedWriteHalf>,
+    pending: Pending,
+    /// Sends the events of the subscribed topics, None once the connection has closed
+    events: Arc<std::sync::Mutex<Option<broadcast::Sender<Event>>>>,
+    next_id: AtomicU64,
+}
+
+impl Client {
+    /// Connects to the control socket, checking that the protocol version matches
+    pub async fn connect(path: &Path) -> Result<Client, String> {
+        let stream = UnixStream::connect(path)
+            .await
+            .map_err(|e| format!("Could not connect to {}: {e}", path.display()))?;
+        let (read, write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        // The server introduces itself first
+        let hello = lines.next_line().await.ok().flatten().unwrap_or_default();
+        match serde_json::from_str(&hello) {
+            Ok(ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+            }) => {}
+            Ok(ServerMessage::Hello { version }) => {
+                return Err(format!(
+                    "The controller uses protocol version {version}, expected {PROTOCOL_VERSION}"
+                ))
+            }
+            _ => return Err(format!("Unexpected greeting from the controller: {hello}")),
+        }
+
+        let (events_tx, _) = broadcast::channel(1024);
+        let client = Client {
+            writer: Mutex::new(write),
+            pending: Arc::new(std::sync::Mutex::new(Some(HashMap::new()))),
+            events: Arc::new(std::sync::Mutex::new(Some(events_tx))),
+            next_id: AtomicU64::new(1),
+        };
+        tokio::spawn(read_messages(
+            lines,
+            client.pending.clone(),
+            client.events.clone(),
+        ));
+        client
+            .request(Command::Hello {
+                version: PROTOCOL_VERSION,
+            })
+            .await?;
+        Ok(client)
+    }
+
+    /// Sends the request and waits for its response. Error replies are returned as Err
+    pub async fn request(&self, command: Command) -> Result<Reply, String> {
+        let closed = || "The connection to the controller was closed".to_string();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or_else(closed)?
+            .insert(id, tx);
+
+        let mut json =
+            serde_json::to_string(&Request { id, command }).map_err(|e| e.to_string())?;
+        json.push('\n');
+        self.writer
+            .lock()
+            .await
+            .write_all(json.as_bytes())
+            .await
+            .map_err(|e| format!("Could not send the request to the controller: {e}"))?;
+
+        match rx.await {
+            Ok(Reply::Error { message }) => Err(message),
+            Ok(reply) => Ok(reply),
+            Err(_) => Err(closed()),
+        }
+    }
+
+    /// Receives the events of the subscribed topics.
+    /// Subscribe before sending the subscribe request, so no events are missed.
+    /// The receiver is closed when the connection closes.
+    pub fn events(&self) -> Option<broadcast::Receiver<Event>> {
+        self.events
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|tx| tx.subscribe())
+    }
+}
+
+/// Passes responses to the requests waiting for them, and events to the subscribers
+async fn read_messages(
+    mut lines: Lines<BufReader<OwnedReadHalf>>,
+    pending: Pending,
+    events: Arc<std::sync::Mutex<Option<broadcast::Sender<Event>>>>,
+) {
+    while let Ok(Some(line)) = lines.next_line().await {
+        match serde_json::from_str::<ServerMessage>(&line) {
+            Ok(ServerMessage::Response { id, result }) => {
+                let tx = pending.lock().unwrap().as_mut().and_then(|p| p.remove(&id));
+                if let Some(tx) = tx {
+                    let _ = tx.send(result);
+                }
+            }
+            Ok(ServerMessage::Event(event)) => {
+                if let Some(tx) = events.lock().unwrap().as_ref() {
+                    let _ = tx.send(event);
+                }
+            }
+            Ok(ServerMessage::Hello { .. }) => {}
+            Err(e) => println!("Invalid message from the controller: {e}"),
+        }
+    }
+    // Dropping the senders fails the waiting requests, and closes the event receivers
+    pending.lock().unwrap().take();
+    events.lock().unwrap().take();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner_settings::tests::fake_miner_settings;
+    use crate::profiles::Profiles;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_protocol_format() {
+        let request: Request =
+            serde_json::from_str(r#"{"id": 3, "cmd": "start", "profile": "backup"}"#).unwrap();
+        assert_eq!(
+            request,
+            Request {
+                id: 3,
+                command: Command::Start {
+                    profile: Some("backup".to_string())
+                }
+            }
+        );
+        let request: Request = serde_json::from_str(r#"{"id": 4, "cmd": "start"}"#).unwrap();
+        assert_eq!(request.command, Command::Start { profile: None });
+        let request: Request =
+            serde_json::from_str(r#"{"id": 5, "cmd": "subscribe", "topics": ["lines", "state"]}"#)
+                .unwrap();
+        assert_eq!(
+            request.command,
+            Command::Subscribe {
+                topics: vec![Topic::Lines, Topic::State],
+                backlog: 0
+            }
+        );
+
+        let event = ServerMessage::Event(Event::Line {
+            text: "DAG 50%".to_string(),
+            replace: true,
+        });
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"event","event":"line","text":"DAG 50%","replace":true}"#
+        );
+        assert_eq!(serde_json::from_str::<ServerMessage>(&json).unwrap(), event);
+
+        let response = ServerMessage::Response {
+            id: 7,
+            result: Reply::State(MinerState {
+                running: true,
+                profile: Some("default".to_string()),
+                pid: Some(42),
+                paused: Vec::new(),
+            }),
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ServerMessage>(&json).unwrap(),
+            response
+        );
+    }
+
+    /// Waits for the next event matching the predicate
+    async fn wait_for(
+        events: &mut broadcast::Receiver<Event>,
+        predicate: impl Fn(&Event) -> bool,
+    ) -> Event {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("Timed out waiting for an event")
+    }
+
+    #[tokio::test]
+    async fn test_control_socket() {
+        let dir = std::env::temp_dir().join(format!("ethminer-gui-ipc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("control.sock");
+        let profiles_path = dir.join("profiles.json");
+        let mut profiles = Profiles::default();
+        profiles.set_active_settings(fake_miner_settings(
+            &dir,
+            "printf '\\033[32m m\\033[0m 17:29:50 ethminer 0:00 A0 20.00 Mh - cu0 20.00\\n'\nexec sleep 30",
+        ));
+        profiles.save_to(&profiles_path).unwrap();
+
+        // A socket left behind by an instance that didn't exit cleanly is replaced
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        let controller = MinerController::new();
+        Server::new(controller.clone(), profiles_path.clone())
+            .listen(&socket)
+            .await
+            .unwrap();
+        let second = Server::new(MinerController::new(), profiles_path).listen(&socket);
+        assert_eq!(second.await.unwrap_err().kind(), io::ErrorKind::AddrInUse);
+
+        let client = Client::connect(&socket).await.unwrap();
+        let mut events = client.events().unwrap();
+        client
+            .request(Command::Subscribe {
+                topics: vec![Topic::Lines, Topic::State],
+                backlog: 0,
+            })
+            .await
+            .unwrap();
+        wait_for(&mut events, |e| matches!(e, Event::State(s) if !s.running)).await;
+
+        client
+            .request(Command::Start { profile: None })
+            .await
+            .unwrap();
+        wait_for(&mut events, |e| matches!(e, Event::State(s) if s.running)).await;
+        wait_for(
+            &mut events,
+            |e| matches!(e, Event::Line { text, .. } if text.contains("20.00 Mh")),
+        )
+        .await;
+        match client.request(Command::GetState).await.unwrap() {
+            Reply::State(state) => {
+                assert!(state.running);
+                assert_eq!(state.profile.as_deref(), Some("default"));
+            }
+            reply => panic!("Unexpected reply {reply:?}"),
+        }
+        match client.request(Command::GetStats).await.unwrap() {
+            Reply::Stats(stats) => assert_eq!(stats.current_hashrate, 20e6),
+            reply => panic!("Unexpected reply {reply:?}"),
+        }
+
+        let missing = Command::Start {
+            profile: Some("missing".to_string()),
+        };
+        assert!(client.request(missing).await.is_err());
+        assert!(client
+            .request(Command::Hello { version: 999 })
+            .await
+            .is_err());
+
+        client.request(Command::Stop).await.unwrap();
+        wait_for(&mut events, |e| matches!(e, Event::State(s) if !s.running)).await;
+
+        // Launching again only shows a window if one is attached
+        let launch = Command::Launch {
+            profile: None,
+            start_mining: false,
+            show_window: true,
+        };
+        let shown = |window_shown| Reply::Launched { window_shown };
+        assert_eq!(client.request(launch.clone()).await.unwrap(), shown(false));
+        let window = Client::connect(&socket).await.unwrap();
+        let mut window_events = window.events().unwrap();
+        window
+            .request(Command::Subscribe {
+                topics: vec![Topic::Window],
+                backlog: 0,
+            })
+            .await
+            .unwrap();
+        assert_eq!(client.request(launch).await.unwrap(), shown(true));
+        wait_for(&mut window_events, |e| *e == Event::ShowWindow).await;
+
+        // A late subscriber gets the buffered lines first
+        let late = Client::connect(&socket).await.unwrap();
+        let mut late_events = late.events().unwrap();
+        late.request(Command::Subscribe {
+            topics: vec![Topic::Lines],
+            backlog: 100,
+        })
+        .await
+        .unwrap();
+        // Coloured like the lines sent as they come
+        wait_for(
+            &mut late_events,
+            |e| matches!(e, Event::Line { text, .. } if text.starts_with("\x1b[32m m\x1b[0m")),
+        )
+        .await;
+        wait_for(
+            &mut late_events,
+            |e| matches!(e, Event::Line { text, .. } if text.contains("Killed miner")),
+        )
+        .await;
+        match late.request(Command::GetLines { count: 2 }).await.unwrap() {
+            Reply::Lines { lines } => assert_eq!(lines, ["", "***** Killed miner *****"]),
+            reply => panic!("Unexpected reply {reply:?}"),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+use crate::app_options::AppOptions;
+use crate::miner_controller::MinerEvent;
+use crate::miner_handle::MinerHandle;
+use crate::miner_settings::MinerSettings;
+use crate::profiles::{load_profile, state_dir, Profiles};
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Number of crashes within CRASH_LOOP_WINDOW that make a crash loop
+const CRASH_LOOP_CRASHES: usize = 3;
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Number of crashes kept in the history
+const MAX_CRASHES: usize = 10;
+
+/// Crashes of recent sessions, used to skip starting on launch after a crash loop.
+/// Both miner crashes and sessions of the app that didn't exit cleanly count,
+/// as a miner that hangs the machine takes the app down with it.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct LaunchHistory {
+    /// Unix time of the last launch of the app
+    pub launched_secs: u64,
+    /// Whether the last session of the app exited cleanly
+    pub clean_exit: bool,
+    /// Unix times of the most recent crashes
+    pub crashes: Vec<u64>,
+}
+
+impl LaunchHistory {
+    /// The location of the history, "$XDG_STATE_HOME/ethminer-gui/launch.json"
+    pub fn path() -> PathBuf {
+        state_dir().join("launch.json")
+    }
+
+    /// Loads the history, returns an empty history if it doesn't exist or can't be read
+    pub fn load_from(path: &Path) -> LaunchHistory {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or(LaunchHistory {
+                clean_exit: true,
+                ..Default::default()
+            })
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+        }
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    /// Records a launch at now, returning whether the previous session ended in a crash loop
+    pub fn launched(&mut self, now: u64) -> bool {
+        if !self.clean_exit && self.launched_secs > 0 {
+            // The time the session ended is unknown, so its crash is counted when it started
+            self.crash(self.launched_secs);
+        }
+        let crash_loop = self.crash_loop();
+        self.launched_secs = now;
+        self.clean_exit = false;
+        crash_loop
+    }
+
+    pub fn crash(&mut self, now: u64) {
+        self.crashes.push(now);
+        if self.crashes.len() > MAX_CRASHES {
+            self.crashes.remove(0);
+        }
+    }
+
+    /// Whether the previous session ended with CRASH_LOOP_CRASHES crashes in CRASH_LOOP_WINDOW
+    fn crash_loop(&self) -> bool {
+        let last = match self.crashes.iter().max() {
+            Some(last) if *last >= self.launched_secs => *last,
+            _ => return false,
+        };
+        let recent = self
+            .crashes
+            .iter()
+            .filter(|t| last - **t <= CRASH_LOOP_WINDOW.as_secs())
+            .count();
+        recent >= CRASH_LOOP_CRASHES
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Records the launch of an app that controls the miner in this process, and records
+/// the crashes of the miner until the app exits. Returns whether the previous session
+/// ended in a crash loop.
+pub async fn track_launch(miner: &MinerHandle) -> bool {
+    let mut events = match miner.subscribe_events().await {
+        Some(events) => events,
+        None => return false,
+    };
+    let path = LaunchHistory::path();
+    let mut history = LaunchHistory::load_from(&path);
+    let crash_loop = history.launched(unix_secs());
+    if let Err(e) = history.save_to(&path) {
+        println!("Could not save the launch history: {e}");
+    }
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(MinerEvent::Crashed) => {
+                    let mut history = LaunchHistory::load_from(&path);
+                    history.crash(unix_secs());
+                    if let Err(e) = history.save_to(&path) {
+                        println!("Could not save the launch history: {e}");
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+    crash_loop
+}
+
+/// Records that the app is exiting cleanly, so the next launch doesn't count a crash
+pub fn record_clean_exit() {
+    let path = LaunchHistory::path();
+    let mut history = LaunchHistory::load_from(&path);
+    if !history.clean_exit {
+        history.clean_exit = true;
+        if let Err(e) = history.save_to(&path) {
+            println!("Could not save the launch history: {e}");
+        }
+    }
+}
+
+/// Starts the miner when the app launches, if the active profile asks for it.
+/// The start can be delayed, and is cancellable until it happens.
+#[derive(Clone, Default)]
+pub struct AutoStart {
+    pending: Arc<Mutex<Option<PendingStart>>>,
+}
+
+struct PendingStart {
+    profile: String,
+    settings: MinerSettings,
+    deadline: Instant,
+}
+
+impl AutoStart {
+    /// Schedules starting the miner with the active profile, if it is set to start on launch.
+    /// Returns a message for the user when starting is skipped because of a crash loop.
+    pub fn schedule(&self, miner: &MinerHandle, crash_loop: bool) -> Option<String> {
+        // The instance this attached to decides for itself
+        if miner.is_remote() || miner.state().running {
+            return None;
+        }
+        let (profile, settings) = match load_profile(&Profiles::path(), None) {
+            Ok((profile, settings)) if settings.start_on_launch => (profile, settings),
+            _ => return None,
+        };
+        if AppOptions::load().unwrap_or_default().schedule.enabled {
+            println!("Not starting on launch, the mining schedule decides when to mine");
+            return None;
+        }
+        if crash_loop {
+            return Some(
+                "Mining was not started on launch, as the miner kept crashing in the \
+                 previous session"
+                    .to_string(),
+            );
+        }
+
+        let delay = Duration::from_secs(settings.launch_delay_secs.into());
+        println!("Starting profile \"{profile}\" in {delay:?}");
+        *self.pending.lock().unwrap() = Some(PendingStart {
+            profile,
+            settings,
+            deadline: Instant::now() + delay,
+        });
+        let this = self.clone();
+        let miner = miner.clone();
+        tokio::spawn(async move {
+            // Ticks every second, so the countdown is redrawn
+            while this.remaining().is_some() {
+                let _ = miner.updated_tx.send(());
+                // Started by other means in the meantime, e.g. from the tray or ctl
+                if miner.state().running {
+                    this.cancel();
+                }
+                match this.remaining() {
+                    Some(remaining) if remaining.is_zero() => this.start_now(&miner),
+                    Some(remaining) => {
+                        tokio::time::sleep(remaining.min(Duration::from_secs(1))).await
+                    }
+                    None => {}
+                }
+            }
+        });
+        None
+    }
+
+    /// The profile that will be started, and the time until it is, if a start is pending
+    pub fn pending(&self) -> Option<(String, Duration)> {
+        let pending = self.pending.lock().unwrap();
+        pending.as_ref().map(|p| {
+            let remaining = p.deadline.saturating_duration_since(Instant::now());
+            (p.profile.clone(), remaining)
+        })
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        self.pending().map(|(_, remaining)| remaining)
+    }
+
+    /// Starts the pending start without waiting for the delay
+    pub fn start_now(&self, miner: &MinerHandle) {
+        if let Some(pending) = self.pending.lock().unwrap().take() {
+            miner.start(pending.profile, pending.settings);
+        }
+    }
+
+    pub fn cancel(&self) {
+        if self.pending.lock().unwrap().take().is_some() {
+            println!("Cancelled starting on launch");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crash_loop_detection() {
+        let minute = 60;
+        let mut history = LaunchHistory::load_from(Path::new("/nonexistent/launch.json"));
+        assert!(!history.launched(1000 * minute));
+
+        // Crashes spread over a long session aren't a loop
+        for t in [1010, 1100, 1200] {
+            history.crash(t * minute);
+        }
+        assert!(!history.launched(1300 * minute));
+
+        // Neither are two crashes
+        history.crash(1301 * minute);
+        history.crash(1302 * minute);
+        history.clean_exit = true;
+        assert!(!history.launched(1400 * minute));
+
+        history.crash(1401 * minute);
+        history.crash(1402 * minute);
+        history.crash(1403 * minute);
+        history.clean_exit = true;
+        assert!(history.launched(1404 * minute));
+        // Only the crashes of the previous session are a reason not to start
+        history.clean_exit = true;
+        assert!(!history.launched(1500 * minute));
+
+        // Sessions that didn't exit cleanly, e.g. because the machine hung, count as crashes
+        let mut history = LaunchHistory {
+            clean_exit: true,
+            ..Default::default()
+        };
+        assert!(!history.launched(2000 * minute));
+        assert!(!history.launched(2002 * minute));
+        assert!(!history.launched(2004 * minute));
+        history.crash(2005 * minute);
+        assert!(history.launched(2006 * minute));
+        assert!(history.crashes.len() <= MAX_CRASHES);
+    }
+}
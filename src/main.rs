@@ -1,10 +1,42 @@
 // This makes the compiled windows app not launch with a console window
 #![windows_subsystem = "windows"]
 
+mod alerts;
+mod ansi;
+mod app_options;
+mod autostart;
+mod cli;
+#[cfg(unix)]
+mod ctl;
+mod diagnosis;
+mod ethash;
+mod headless;
+mod idle;
 mod icon_data;
+mod instance;
+#[cfg(unix)]
+mod ipc;
+mod launch;
 mod miner_controller;
+mod miner_handle;
 mod miner_settings;
+#[cfg(target_os = "linux")]
+mod notifications;
+mod output_buffer;
+mod output_parser;
+mod output_reader;
+mod output_view;
+mod process_watch;
+mod profiles;
+mod schedule;
+mod stats;
+mod tariff;
 mod tray;
+#[cfg(target_os = "linux")]
+mod tray_icon;
+mod tui;
+mod watchdog;
+mod webhooks;
 mod miner_app;
 
 /**
@@ -55,13 +87,84 @@ use icon_data::get_icon_rgba;
 use eframe::{egui, epi};
 use tokio::runtime::Handle;
 
+use cli::{Args, USAGE};
 use miner_app::MinerApp;
 
 use tokio;
 
 #[tokio::main]
 async fn main() {
-    let mut app: MinerApp = MinerApp::default().await;
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{USAGE}");
+        return;
+    }
+    if let Some(ctl) = args.ctl {
+        #[cfg(unix)]
+        std::process::exit(ctl::run_ctl(ctl).await);
+        #[cfg(not(unix))]
+        {
+            eprintln!(
+                "ctl is only supported on unix, {:?} was not run",
+                ctl.command
+            );
+            std::process::exit(1);
+        }
+    }
+    if args.headless {
+        std::process::exit(headless::run_headless(args).await);
+    }
+    if args.tui {
+        std::process::exit(tui::run_tui(args.profile.as_deref(), args.start_mining).await);
+    }
+    if args.minimized {
+        #[cfg(target_os = "linux")]
+        std::process::exit(tray::run_minimized(args.profile.as_deref(), args.start_mining).await);
+        #[cfg(not(target_os = "linux"))]
+        {
+            eprintln!("--minimized is only supported on linux, where the app has a tray");
+            std::process::exit(1);
+        }
+    }
+
+    let options = app_options::AppOptions::load().unwrap_or_else(|e| {
+        println!("Options not loaded: {e}");
+        Default::default()
+    });
+    #[cfg(target_os = "linux")]
+    let miner = if options.close_to_tray {
+        tray::attach_or_spawn_minimized().await
+    } else {
+        miner_handle::attach_or_start().await
+    };
+    #[cfg(not(target_os = "linux"))]
+    let miner = miner_handle::attach_or_start().await;
+    let miner = miner.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    // A second launch hands its arguments to the running instance, and
+    // only opens a window if that instance doesn't have one
+    let launched = miner
+        .launch(args.profile.as_deref(), args.start_mining, true)
+        .await;
+    if launched == Ok(true) {
+        println!("ethminer-gui is already running, showing its window");
+        return;
+    }
+    if let Err(e) = miner.register_window().await {
+        println!("Not receiving requests to show the window: {e}");
+    }
+    let crash_loop = miner_handle::start_tasks(&miner).await;
+    #[cfg(target_os = "linux")]
+    notifications::start(&miner).await;
+    let mut app: MinerApp = MinerApp::new(miner, options);
     // Gets the icon
     let icon: Vec<u8> = get_icon_rgba().to_vec();
     let icon_data = epi::IconData {
@@ -81,10 +184,24 @@ async fn main() {
     };
 
     app.start_error_listener().await;
+    if let Err(e) = launched {
+        let _ = app.miner.error_tx.send(e);
+    }
+    if !args.start_mining {
+        if let Some(message) = app.auto_start.schedule(&app.miner, crash_loop) {
+            let _ = app.miner.error_tx.send(message);
+        }
+    }
 
     let handle = Handle::current();
-    if cfg!(target_os = "linux") {
-        tray::start_tray_linux(app.settings.clone(), app.miner_controller.clone(), handle);
+    // The instance running the miner has its own tray, if it has one
+    if cfg!(target_os = "linux") && !app.miner.is_remote() {
+        tray::start_tray_linux(
+            app.settings.clone(),
+            app.miner.clone(),
+            app.auto_start.clone(),
+            handle,
+        );
     } else if cfg!(target_os = "windows") {
     }
 
@@ -1,43 +1,112 @@
+use crate::alerts::{AlertRule, Condition, Severity};
+use crate::app_options::AppOptions;
+use crate::autostart::{self, AutostartOptions, AutostartStatus};
+use crate::ethash::{self, Device};
 use crate::icon_data::get_icon_rgba;
-use crate::miner_controller::MinerController;
+use crate::idle::IdleSourceKind;
+use crate::launch::{self, AutoStart};
+use crate::miner_handle::MinerHandle;
 use crate::miner_settings::*;
+use crate::output_buffer::OutputBuffer;
+use crate::output_view::OutputView;
+use crate::process_watch::{MatchKind, WatchedProcess};
+use crate::profiles::{active_profile_name, Profiles};
+use crate::schedule::{self, Timezone};
+use crate::stats::{self, LifetimeStats};
+use crate::tariff::TariffRate;
+use crate::webhooks::{self, PayloadFormat, Webhook, WebhookEventKind};
 
 use eframe::{egui, epi};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
 use tokio;
 use tokio::sync::Mutex;
 
 pub struct MinerError(String);
 
+/// The devices listed by ethminer, or why they couldn't be listed
+type DeviceList = Result<Vec<Device>, String>;
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 pub struct MinerApp {
     /// Stores the currently used settings
     pub settings: Arc<RwLock<MinerSettings>>,
     /// Stores the settings that haven't been applied yet
     temp_settings: MinerSettings,
-    /// Reference to the MinerController
-    pub miner_controller: Arc<Mutex<MinerController>>,
+    /// Reference to the miner, which is controlled by this or another instance
+    pub miner: MinerHandle,
     /// Reference to the output of the miner process
-    buffer: Arc<Mutex<Vec<String>>>,
+    buffer: Arc<std::sync::Mutex<OutputBuffer>>,
     /// Reference to the repaint_signal, which is sent to when receiving
     /// updates from the controller
     pub repaint_signal: Option<Arc<dyn epi::backend::RepaintSignal>>,
     /// Used to contain the errors that are generated by the controller
     error: Arc<Mutex<Option<MinerError>>>,
+    /// Search and filter state of the output view
+    output_view: OutputView,
+    /// The autostart entry, as last read from disk
+    autostart: AutostartStatus,
+    /// Options of the autostart entry, kept while it is disabled
+    autostart_options: AutostartOptions,
+    /// Starting the miner on launch, while it's pending
+    pub auto_start: AutoStart,
+    /// Options of the app, as saved in the options file
+    options: AppOptions,
+    /// When the options file was last written, as far as the window knows. The tray
+    /// writes it too, e.g. to mine now, so it is read again before the options are edited
+    options_modified: Option<SystemTime>,
+    /// Whether the window was opened with the miner running in the tray,
+    /// in which case closing it leaves the miner running
+    in_tray: bool,
+    /// Set while asking whether to quit with the miner running
+    confirm_quit: bool,
+    /// Set once quitting with the miner running was confirmed
+    quit_confirmed: bool,
+    /// Requests to show the window, subscribed to when the app is created,
+    /// so launching the app again finds this window right away
+    show_window_rx: Option<tokio::sync::broadcast::Receiver<()>>,
+    /// When launching the app again last asked to show the window
+    shown_again: Arc<std::sync::Mutex<Option<Instant>>>,
+    /// The outcome of the last test message sent to a webhook
+    webhook_test: Arc<std::sync::Mutex<Option<String>>>,
+    /// Epoch entered to look at its DAG, instead of the one being mined
+    dag_epoch: Option<u64>,
+    /// The devices of the last "List Devices"
+    devices: Arc<std::sync::Mutex<Option<DeviceList>>>,
+    /// The first epoch too large for the memory of a device, by the memory and the epoch
+    /// shown, as it takes a while to find
+    dag_fits: HashMap<(u64, u64), u64>,
+    /// Whether dragging over the week grid adds hours to the schedule or removes them
+    schedule_paint: Option<bool>,
 }
 
 impl MinerApp {
-    pub async fn default() -> Self {
-        let mc = MinerController::new();
-        let buffer = mc.lock().await.buffer.clone();
+    pub fn new(miner: MinerHandle, options: AppOptions) -> Self {
         Self {
+            in_tray: options.close_to_tray && miner.is_remote(),
+            show_window_rx: Some(miner.show_window_tx.subscribe()),
+            shown_again: Arc::new(std::sync::Mutex::new(None)),
+            webhook_test: Arc::new(std::sync::Mutex::new(None)),
+            dag_epoch: None,
+            devices: Arc::new(std::sync::Mutex::new(None)),
+            dag_fits: HashMap::new(),
+            schedule_paint: None,
             settings: Arc::new(RwLock::new(MinerSettings::default())),
             temp_settings: MinerSettings::default(),
-            miner_controller: mc.clone(),
-            buffer,
+            buffer: miner.buffer.clone(),
+            miner,
             repaint_signal: None,
             error: Arc::new(Mutex::new(None)),
+            output_view: OutputView::default(),
+            autostart: AutostartStatus::Disabled,
+            autostart_options: AutostartOptions::default(),
+            auto_start: AutoStart::default(),
+            options,
+            options_modified: AppOptions::modified(),
+            confirm_quit: false,
+            quit_confirmed: false,
         }
     }
 
@@ -59,13 +128,54 @@ impl MinerApp {
         });
     }
 
+    /// Starts the listener for requests to show the window.
+    /// eframe can't raise its window, so the request is acknowledged in the window instead.
+    fn start_show_window_task(&mut self) {
+        let mut rcv = match self.show_window_rx.take() {
+            Some(rcv) => rcv,
+            None => return,
+        };
+        let repaint_signal = self.repaint_signal.clone();
+        let shown_again = self.shown_again.clone();
+        tokio::task::spawn(async move {
+            loop {
+                match rcv.recv().await {
+                    Ok(()) => {
+                        *shown_again.lock().unwrap() = Some(Instant::now());
+                        if let Some(ref repaint) = repaint_signal {
+                            repaint.request_repaint();
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    /// Shows that launching the app again opened this window, for a few seconds
+    fn show_shown_again(&mut self, ui: &mut egui::Ui) {
+        let shown_again = *self.shown_again.lock().unwrap();
+        match shown_again {
+            Some(at) if at.elapsed() < Duration::from_secs(5) => {
+                ui.colored_label(
+                    egui::Color32::LIGHT_BLUE,
+                    "ethminer-gui is already running in this window",
+                );
+                ui.separator();
+                // Repaints until the message is no longer shown
+                ui.ctx().request_repaint();
+            }
+            _ => {}
+        }
+    }
+
     /// Starts a listener on the controller error channel,
-    /// listening to the miner_controller's error_tx.
+    /// listening to the miner's error_tx.
     /// Mutates self.error when an error is received
     /// This only works for one error at a time
     pub async fn start_error_listener(&mut self) {
-        let error_tx = self.miner_controller.lock().await.error_tx.clone();
-        let mut rcv = error_tx.subscribe();
+        let mut rcv = self.miner.error_tx.subscribe();
         let error = self.error.clone();
         tokio::task::spawn(async move {
             loop {
@@ -136,17 +246,882 @@ impl MinerApp {
         });
     }
 
-    fn show_ethminer_out(&mut self, ui: &mut egui::Ui) {
-        egui::ScrollArea::vertical()
-            .stick_to_bottom()
-            .show(ui, |ui| {
-                tokio::task::block_in_place(move || {
-                    let b: &Vec<String> = &*self.buffer.blocking_lock();
-                    b.into_iter().for_each(|line| {
-                        ui.horizontal_wrapped(|ui| {
-                            ui.label(line);
+    fn show_stats(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Statistics", |ui| {
+            let session = self.miner.stats();
+            let lifetime = &session.lifetime;
+            let uptime = session.uptime();
+            let lifetime_uptime = std::time::Duration::from_secs(lifetime.mining_secs);
+            let ratio = |accepted, rejected| match stats::accepted_ratio(accepted, rejected) {
+                Some(r) => format!("{:.1}%", r * 100.0),
+                None => "-".to_string(),
+            };
+
+            egui::Grid::new("stats_grid")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("");
+                    ui.strong("Session");
+                    ui.strong("Lifetime");
+                    ui.end_row();
+
+                    ui.label("Uptime");
+                    ui.label(stats::format_duration(uptime));
+                    ui.label(stats::format_duration(lifetime_uptime));
+                    ui.end_row();
+
+                    ui.label("Hashrate");
+                    ui.label(stats::format_hashrate(session.current_hashrate));
+                    ui.label("");
+                    ui.end_row();
+
+                    ui.label("Average Hashrate");
+                    ui.label(stats::format_hashrate(session.average_hashrate));
+                    ui.label("");
+                    ui.end_row();
+
+                    ui.label("Peak Hashrate");
+                    ui.label(stats::format_hashrate(session.peak_hashrate));
+                    ui.label(stats::format_hashrate(lifetime.peak_hashrate));
+                    ui.end_row();
+
+                    ui.label("Accepted / Rejected / Stale");
+                    ui.label(format!(
+                        "{} / {} / {}",
+                        session.accepted, session.rejected, session.stale
+                    ));
+                    ui.label(format!(
+                        "{} / {} / {}",
+                        lifetime.accepted, lifetime.rejected, lifetime.stale
+                    ));
+                    ui.end_row();
+
+                    ui.label("Accepted Ratio");
+                    ui.label(ratio(session.accepted, session.rejected));
+                    ui.label(ratio(lifetime.accepted, lifetime.rejected));
+                    ui.end_row();
+
+                    ui.label("Shares per Hour");
+                    ui.label(format!(
+                        "{:.2}",
+                        stats::shares_per_hour(session.accepted, uptime)
+                    ));
+                    ui.label(format!(
+                        "{:.2}",
+                        stats::shares_per_hour(lifetime.accepted, lifetime_uptime)
+                    ));
+                    ui.end_row();
+
+                    ui.label("Last Accepted Share");
+                    ui.label(match session.last_accepted_secs {
+                        Some(secs) => format!(
+                            "{} ago",
+                            stats::format_duration(std::time::Duration::from_secs(secs))
+                        ),
+                        None => "Never".to_string(),
+                    });
+                    ui.label("");
+                    ui.end_row();
+
+                    ui.label("Reconnects");
+                    ui.label(session.reconnects.to_string());
+                    ui.label(lifetime.reconnects.to_string());
+                    ui.end_row();
+
+                    ui.label("Restarts");
+                    ui.label(session.restarts.to_string());
+                    ui.label(lifetime.restarts.to_string());
+                    ui.end_row();
+
+                    ui.label("Stall Restarts");
+                    ui.label(session.stall_restarts.to_string());
+                    ui.label(lifetime.stall_restarts.to_string());
+                    ui.end_row();
+
+                    let tariff = &self.options.tariff;
+                    if tariff.watts > 0.0 {
+                        let cost = tariff.cost_per_hour(unix_secs());
+                        ui.label("Power Cost");
+                        ui.label(format!("{}/h", tariff.format_money(cost)));
+                        ui.label("");
+                        ui.end_row();
+                        if tariff.revenue_per_mhs_day > 0.0 {
+                            let revenue = tariff.revenue_per_hour(session.average_hashrate);
+                            ui.label("Estimated Revenue");
+                            ui.label(format!("{}/h", tariff.format_money(revenue)));
+                            ui.label("");
+                            ui.end_row();
+
+                            ui.label("Estimated Net Margin");
+                            ui.label(format!("{}/h", tariff.format_money(revenue - cost)));
+                            ui.label("");
+                            ui.end_row();
+                        }
+                    }
+                });
+
+            if ui.button("Reset Statistics").clicked() {
+                self.miner.reset_stats();
+            }
+        });
+    }
+
+    /// Shows the DAG size of the epoch, and until when each device has memory for it
+    fn show_dag(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("DAG Size", |ui| {
+            let stats = self.miner.stats();
+            let mut epoch = self.dag_epoch.or(stats.epoch).unwrap_or(0);
+            ui.horizontal(|ui| {
+                ui.label("Epoch");
+                if ui
+                    .add(egui::DragValue::new(&mut epoch).clamp_range(0..=2047))
+                    .changed()
+                {
+                    self.dag_epoch = Some(epoch);
+                }
+                match (stats.epoch, self.dag_epoch) {
+                    (Some(current), Some(_)) => {
+                        if ui.button(format!("Mined Epoch ({current})")).clicked() {
+                            self.dag_epoch = None;
+                        }
+                    }
+                    (Some(_), None) => {
+                        ui.label("Being mined");
+                    }
+                    (None, _) => {
+                        ui.label("Shown once the miner gets a job");
+                    }
+                }
+            });
+            ui.label(format!(
+                "DAG {}, cache {}",
+                ethash::format_bytes(ethash::dag_size(epoch)),
+                ethash::format_bytes(ethash::cache_size(epoch))
+            ));
+
+            if ui.button("List Devices").clicked() {
+                self.list_devices();
+            }
+            match &*self.devices.lock().unwrap() {
+                Some(Ok(devices)) => {
+                    // Without a job from the pool, the epoch is assumed to have just begun
+                    let block = match self.dag_epoch {
+                        None => stats.block,
+                        Some(_) => None,
+                    }
+                    .unwrap_or(epoch * ethash::EPOCH_LENGTH);
+                    egui::Grid::new("devices_grid")
+                        .num_columns(3)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for device in devices {
+                                ui.label(format!("{} {}", device.index, device.name));
+                                ui.label(ethash::format_bytes(device.memory));
+                                let last =
+                                    *self.dag_fits.entry((device.memory, epoch)).or_insert_with(
+                                        || ethash::first_epoch_exceeding(device.memory, epoch),
+                                    );
+                                if last == epoch {
+                                    ui.label("Too small for the DAG");
+                                } else {
+                                    let date = ethash::epoch_start(last, block, unix_secs());
+                                    ui.label(format!(
+                                        "Fits until epoch {last}, around {}",
+                                        stats::format_date(date)
+                                    ));
+                                }
+                                ui.end_row();
+                            }
                         });
+                }
+                Some(Err(e)) => {
+                    ui.label(e);
+                }
+                None => {}
+            }
+        });
+    }
+
+    /// Asks ethminer of the current settings for the devices, in the background
+    fn list_devices(&self) {
+        let bin_path = self.temp_settings.bin_path.clone();
+        let devices = self.devices.clone();
+        let updated_tx = self.miner.updated_tx.clone();
+        tokio::spawn(async move {
+            let listed = ethash::list_devices(&bin_path).await;
+            *devices.lock().unwrap() = Some(listed);
+            // Redraws the window with the devices
+            let _ = updated_tx.send(());
+        });
+    }
+
+    /// Edits launching on startup, the tray and the desktop notifications, returns whether the
+    /// options were changed
+    fn show_desktop_options(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut enabled = self.autostart != AutostartStatus::Disabled;
+        let mut changed = ui.checkbox(&mut enabled, "Launch on startup").changed();
+        ui.add_enabled_ui(enabled, |ui| {
+            ui.indent("autostart_options", |ui| {
+                let options = &mut self.autostart_options;
+                changed |= ui
+                    .checkbox(&mut options.minimized, "Start minimized to the tray")
+                    .changed();
+                changed |= ui
+                    .checkbox(&mut options.start_mining, "Start mining")
+                    .changed();
+            });
+        });
+        if let AutostartStatus::Stale { exec, .. } = &self.autostart {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!(
+                    "Launching on startup runs {}, which is not this program",
+                    exec.display()
+                ),
+            );
+            changed |= ui.button("Launch this program instead").clicked();
+        }
+        if changed {
+            self.set_autostart(enabled);
+        }
+
+        let mut options_changed = ui
+            .checkbox(
+                &mut self.options.close_to_tray,
+                "Closing the window leaves the miner running in the tray",
+            )
+            .changed();
+        if self.options.close_to_tray && !self.miner.is_remote() {
+            ui.label("Takes effect when the window is opened again");
+        }
+
+        ui.label("Desktop notifications");
+        ui.indent("notifications", |ui| {
+            let notifications = &mut self.options.notifications;
+            options_changed |= ui
+                .checkbox(&mut notifications.crashed, "When the miner crashes")
+                .changed();
+            options_changed |= ui
+                .checkbox(
+                    &mut notifications.pool_lost,
+                    "When the miner loses the connection to the pool",
+                )
+                .changed();
+            options_changed |= ui
+                .checkbox(
+                    &mut notifications.thermal_stop,
+                    "When a GPU stops mining as it got too hot",
+                )
+                .changed();
+            options_changed |= ui
+                .checkbox(&mut notifications.errors, "On errors")
+                .changed();
+            options_changed |= ui
+                .checkbox(&mut notifications.alerts, "When an alert rule fires")
+                .changed();
+        });
+        options_changed
+    }
+
+    fn show_options(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Options", |ui| {
+            let mut options_changed = false;
+            // Launching on startup, the tray and notifications use the desktop of linux
+            if cfg!(target_os = "linux") {
+                options_changed |= self.show_desktop_options(ui);
+            }
+            ui.label("Alert rules");
+            ui.indent("alert_rules", |ui| {
+                options_changed |= self.show_alert_rules(ui);
+            });
+            ui.label("Webhooks");
+            ui.indent("webhooks", |ui| {
+                options_changed |= self.show_webhooks(ui);
+            });
+            ui.label("Mining schedule");
+            ui.indent("schedule", |ui| {
+                options_changed |= self.show_schedule(ui);
+            });
+            ui.label("Electricity");
+            ui.indent("tariff", |ui| {
+                options_changed |= self.show_tariff(ui);
+            });
+            // The idle time is read from X11 or logind, and the processes from /proc
+            if cfg!(target_os = "linux") {
+                ui.label("Idle detection");
+                ui.indent("idle", |ui| {
+                    options_changed |= self.show_idle(ui);
+                });
+                ui.label("Applications that need the GPU");
+                ui.indent("process_watch", |ui| {
+                    options_changed |= self.show_process_watch(ui);
+                });
+            }
+            if options_changed {
+                self.save_options();
+            }
+        });
+    }
+
+    fn save_options(&mut self) {
+        if let Err(e) = self.options.save() {
+            tokio::task::block_in_place(|| {
+                *self.error.blocking_lock() = Some(MinerError(e));
+            });
+        }
+        self.options_modified = AppOptions::modified();
+    }
+
+    /// Reads the options again if another process wrote them since, so editing them in the
+    /// window doesn't write back what it changed
+    fn reload_options(&mut self) {
+        let modified = AppOptions::modified();
+        if modified == self.options_modified {
+            return;
+        }
+        self.options_modified = modified;
+        match AppOptions::load() {
+            Ok(options) => self.options = options,
+            Err(e) => println!("{e}, keeping the options shown"),
+        }
+    }
+
+    /// Edits the power draw of the rig, the prices of electricity and when mining is paused
+    /// for them, returns whether they were changed
+    fn show_tariff(&mut self, ui: &mut egui::Ui) -> bool {
+        let tariff = &mut self.options.tariff;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Power Draw");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut tariff.watts)
+                        .clamp_range(0.0..=100_000.0)
+                        .suffix(" W"),
+                )
+                .changed();
+            ui.label("Currency");
+            changed |= ui
+                .add(egui::TextEdit::singleline(&mut tariff.currency).desired_width(40.0))
+                .changed();
+        });
+        ui.horizontal(|ui| {
+            ui.label("Revenue per MH/s per Day");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut tariff.revenue_per_mhs_day)
+                        .clamp_range(0.0..=1000.0)
+                        .speed(0.001)
+                        .max_decimals(4),
+                )
+                .on_hover_text("As estimated by a mining calculator, 0 if unknown")
+                .changed();
+        });
+        changed |= timezone_entry(ui, &mut tariff.timezone);
+        ui.horizontal(|ui| {
+            ui.label("Price per kWh");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut tariff.base_price)
+                        .clamp_range(0.0..=100.0)
+                        .speed(0.01),
+                )
+                .on_hover_text("At the times no rate below applies to")
+                .changed();
+        });
+
+        let mut removed = None;
+        for (i, rate) in tariff.rates.iter_mut().enumerate() {
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    for (day, name) in schedule::DAYS.iter().enumerate() {
+                        changed |= ui.checkbox(&mut rate.days[day], *name).changed();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut start = rate.start / 60;
+                    let mut end = rate.end / 60;
+                    ui.label("From");
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut start)
+                                .clamp_range(0..=23)
+                                .suffix(":00"),
+                        )
+                        .changed()
+                    {
+                        rate.start = start * 60;
+                        changed = true;
+                    }
+                    ui.label("to");
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut end)
+                                .clamp_range(0..=24)
+                                .suffix(":00"),
+                        )
+                        .changed()
+                    {
+                        rate.end = end * 60;
+                        changed = true;
+                    }
+                    ui.label("Price per kWh");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut rate.price)
+                                .clamp_range(0.0..=100.0)
+                                .speed(0.01),
+                        )
+                        .changed();
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            tariff.rates.remove(i);
+            changed = true;
+        }
+        if ui.button("Add Rate").clicked() {
+            tariff.rates.push(TariffRate::default());
+            changed = true;
+        }
+
+        ui.horizontal(|ui| {
+            changed |= ui
+                .checkbox(
+                    &mut tariff.pause_above_price,
+                    "Pause mining while a kWh costs more than",
+                )
+                .changed();
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut tariff.max_price)
+                        .clamp_range(0.0..=100.0)
+                        .speed(0.01),
+                )
+                .changed();
+        });
+        changed |= ui
+            .checkbox(
+                &mut tariff.pause_unprofitable,
+                "Pause mining while it earns less than the electricity costs",
+            )
+            .changed();
+        changed
+    }
+
+    /// Edits when the computer counts as idle, returns whether it was changed
+    fn show_idle(&mut self, ui: &mut egui::Ui) -> bool {
+        let idle = &mut self.options.idle;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            changed |= ui
+                .checkbox(&mut idle.enabled, "Mine only after no input for")
+                .on_hover_text("Pauses the miner while the computer is in use")
+                .changed();
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut idle.idle_minutes)
+                        .clamp_range(1..=24 * 60)
+                        .suffix(" min"),
+                )
+                .changed();
+        });
+        ui.horizontal(|ui| {
+            ui.label("Input from");
+            for kind in IdleSourceKind::ALL {
+                changed |= ui
+                    .radio_value(&mut idle.source, kind, kind.label())
+                    .changed();
+            }
+        });
+        changed
+    }
+
+    /// Edits the processes mining is paused for, returns whether they were changed
+    fn show_process_watch(&mut self, ui: &mut egui::Ui) -> bool {
+        let watch = &mut self.options.process_watch;
+        let mut changed = ui
+            .checkbox(
+                &mut watch.enabled,
+                "Pause mining while any of these applications runs",
+            )
+            .changed();
+        let mut removed = None;
+        for (i, process) in watch.processes.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                for kind in MatchKind::ALL {
+                    changed |= ui
+                        .radio_value(&mut process.kind, kind, kind.label())
+                        .changed();
+                }
+                changed |= ui
+                    .text_edit_singleline(&mut process.pattern)
+                    .on_hover_text(match process.kind {
+                        MatchKind::Name => "The name of the executable, e.g. blender",
+                        MatchKind::CommandLine => {
+                            "Text anywhere in the command line, e.g. steamapps/common"
+                        }
+                    })
+                    .changed();
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            watch.processes.remove(i);
+            changed = true;
+        }
+        if ui.button("Add Application").clicked() {
+            watch.processes.push(WatchedProcess::default());
+            changed = true;
+        }
+        ui.horizontal(|ui| {
+            ui.label("Resume after they exited for");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut watch.resume_delay)
+                        .clamp_range(0..=24 * 3600)
+                        .suffix(" s"),
+                )
+                .changed();
+        });
+        changed
+    }
+
+    /// Shows what the mining schedule does next, and lets the miner be started until its
+    /// next window
+    fn show_schedule_status(&mut self, ui: &mut egui::Ui) {
+        let now = unix_secs();
+        let status = match self.options.schedule.status(now) {
+            Some(status) => status,
+            None => return,
+        };
+        ui.horizontal(|ui| {
+            ui.label(status);
+            if self.options.schedule.can_mine_now(now)
+                && ui.button("Mine Now Until Next Window").clicked()
+            {
+                self.options.schedule.mine_now(now);
+                self.save_options();
+                let settings = self.settings.read().unwrap().clone();
+                self.miner.start(active_profile_name(), settings);
+            }
+        });
+    }
+
+    /// Edits the mining schedule on a grid of the hours of the week, returns whether it
+    /// was changed
+    fn show_schedule(&mut self, ui: &mut egui::Ui) -> bool {
+        let schedule = &mut self.options.schedule;
+        let paint = &mut self.schedule_paint;
+        let mut changed = ui
+            .checkbox(
+                &mut schedule.enabled,
+                "Start and stop the miner on a weekly schedule",
+            )
+            .changed();
+        changed |= timezone_entry(ui, &mut schedule.timezone);
+
+        // Cells are painted by pressing on one and dragging over the others, which
+        // the cells can't sense themselves, as the first one would take the drag
+        let (pointer, pressed, down) = {
+            let input = ui.input();
+            (
+                input.pointer.interact_pos(),
+                input.pointer.any_pressed() && input.pointer.primary_down(),
+                input.pointer.primary_down(),
+            )
+        };
+        egui::Grid::new("schedule_grid")
+            .spacing([1.0, 1.0])
+            .show(ui, |ui| {
+                ui.label("");
+                for hour in 0..24 {
+                    ui.label(if hour % 6 == 0 {
+                        hour.to_string()
+                    } else {
+                        String::new()
                     });
+                }
+                ui.end_row();
+                for (day, name) in schedule::DAYS.iter().enumerate() {
+                    let day = day as u8;
+                    ui.label(*name);
+                    for hour in 0..24 {
+                        let scheduled = schedule.hour_scheduled(day, hour);
+                        let (rect, _) =
+                            ui.allocate_exact_size(egui::vec2(12.0, 16.0), egui::Sense::hover());
+                        let color = if scheduled {
+                            ui.visuals().selection.bg_fill
+                        } else {
+                            ui.visuals().extreme_bg_color
+                        };
+                        ui.painter().rect_filled(rect, 2.0, color);
+                        if !pointer.is_some_and(|pos| rect.contains(pos)) {
+                            continue;
+                        }
+                        if pressed {
+                            *paint = Some(!scheduled);
+                        }
+                        match *paint {
+                            Some(mining) if down && mining != scheduled => {
+                                schedule.set_range(day, hour * 60, (hour + 1) * 60, mining);
+                                changed = true;
+                            }
+                            _ => {}
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        if !down {
+            *paint = None;
+        }
+        for (day, name) in schedule::DAYS.iter().enumerate() {
+            let windows = schedule.day_windows(day as u8);
+            if !windows.is_empty() {
+                ui.label(format!("{name}: {windows}"));
+            }
+        }
+        changed
+    }
+
+    /// Edits the alert rules, returns whether they were changed
+    fn show_alert_rules(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        let mut remove = None;
+        for (i, rule) in self.options.alert_rules.iter_mut().enumerate() {
+            ui.group(|ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for condition in Condition::defaults() {
+                        let selected = std::mem::discriminant(&rule.condition)
+                            == std::mem::discriminant(&condition);
+                        if ui.radio(selected, condition.label()).clicked() && !selected {
+                            rule.condition = condition;
+                            changed = true;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    changed |= match &mut rule.condition {
+                        Condition::HashrateBelow { mhs, minutes } => {
+                            let threshold = ui.add(
+                                egui::DragValue::new(mhs)
+                                    .clamp_range(0.0..=1_000_000.0)
+                                    .suffix(" MH/s"),
+                            );
+                            ui.label("for");
+                            let duration = ui.add(
+                                egui::DragValue::new(minutes)
+                                    .clamp_range(1..=1440)
+                                    .suffix(" min"),
+                            );
+                            threshold.changed() || duration.changed()
+                        }
+                        Condition::RejectedRatioAbove { percent } => {
+                            let threshold = ui.add(
+                                egui::DragValue::new(percent)
+                                    .clamp_range(0.0..=100.0)
+                                    .suffix(" %"),
+                            );
+                            ui.label("over the last hour");
+                            threshold.changed()
+                        }
+                        Condition::NoAcceptedShare { minutes } => {
+                            ui.label("for");
+                            ui.add(
+                                egui::DragValue::new(minutes)
+                                    .clamp_range(1..=1440)
+                                    .suffix(" min"),
+                            )
+                            .changed()
+                        }
+                        Condition::GpuTemperatureAbove { gpu, celsius } => {
+                            ui.label("GPU");
+                            let gpu = ui.add(egui::DragValue::new(gpu).clamp_range(0..=31));
+                            let threshold = ui.add(
+                                egui::DragValue::new(celsius)
+                                    .clamp_range(0..=120)
+                                    .suffix(" °C"),
+                            );
+                            gpu.changed() || threshold.changed()
+                        }
+                    };
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Severity");
+                    for severity in Severity::ALL {
+                        changed |= ui
+                            .radio_value(&mut rule.severity, severity, severity.label())
+                            .changed();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("At most every");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut rule.cooldown_minutes)
+                                .clamp_range(0..=1440)
+                                .suffix(" min"),
+                        )
+                        .changed();
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = remove {
+            self.options.alert_rules.remove(i);
+            changed = true;
+        }
+        if ui.button("Add Rule").clicked() {
+            self.options.alert_rules.push(AlertRule::default());
+            changed = true;
+        }
+        changed
+    }
+
+    /// Edits the webhooks, returns whether they were changed
+    fn show_webhooks(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        let mut remove = None;
+        let mut test = None;
+        for (i, webhook) in self.options.webhooks.iter_mut().enumerate() {
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label("URL");
+                    changed |= ui.text_edit_singleline(&mut webhook.url).changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Format");
+                    for (format, label) in [
+                        (PayloadFormat::Json, "JSON"),
+                        (PayloadFormat::Slack, "Slack"),
+                        (PayloadFormat::Discord, "Discord"),
+                    ] {
+                        changed |= ui.radio_value(&mut webhook.format, format, label).changed();
+                    }
+                });
+                ui.horizontal_wrapped(|ui| {
+                    for kind in WebhookEventKind::ALL {
+                        let mut enabled = webhook.events.contains(&kind);
+                        if ui.checkbox(&mut enabled, kind.label()).changed() {
+                            if enabled {
+                                webhook.events.insert(kind);
+                            } else {
+                                webhook.events.remove(&kind);
+                            }
+                            changed = true;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Send Test").clicked() {
+                        test = Some(webhook.clone());
+                    }
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = remove {
+            self.options.webhooks.remove(i);
+            changed = true;
+        }
+        if ui.button("Add Webhook").clicked() {
+            self.options.webhooks.push(Webhook::default());
+            changed = true;
+        }
+        if let Some(webhook) = test {
+            self.send_webhook_test(webhook);
+        }
+        if let Some(result) = &*self.webhook_test.lock().unwrap() {
+            ui.label(result);
+        }
+        changed
+    }
+
+    fn send_webhook_test(&self, webhook: Webhook) {
+        *self.webhook_test.lock().unwrap() = Some(format!("Sending to {}", webhook.url));
+        let result = self.webhook_test.clone();
+        let updated_tx = self.miner.updated_tx.clone();
+        tokio::spawn(async move {
+            let message = match webhooks::send_test(&webhook).await {
+                Ok(()) => format!("Sent a test message to {}", webhook.url),
+                Err(e) => format!("Could not send to {}: {e}", webhook.url),
+            };
+            *result.lock().unwrap() = Some(message);
+            // Redraws the window with the result
+            let _ = updated_tx.send(());
+        });
+    }
+
+    /// Writes or removes the autostart entry
+    fn set_autostart(&mut self, enabled: bool) {
+        let path = autostart::desktop_path();
+        let exe = std::env::current_exe().unwrap_or_default();
+        let result = if enabled {
+            autostart::enable(&path, &exe, &self.autostart_options)
+        } else {
+            autostart::disable(&path)
+        };
+        if let Err(e) = result {
+            tokio::task::block_in_place(|| {
+                *self.error.blocking_lock() = Some(MinerError(e));
+            });
+        }
+        self.autostart = autostart::status(&path, &exe);
+    }
+
+    /// Shows the countdown to starting the miner on launch
+    fn show_auto_start(&mut self, ui: &mut egui::Ui) {
+        if let Some((profile, remaining)) = self.auto_start.pending() {
+            ui.horizontal(|ui| {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!(
+                        "Mining starts with profile \"{profile}\" in {} s",
+                        remaining.as_secs_f32().ceil()
+                    ),
+                );
+                if ui.button("Start Now").clicked() {
+                    self.auto_start.start_now(&self.miner);
+                }
+                if ui.button("Cancel").clicked() {
+                    self.auto_start.cancel();
+                }
+            });
+            ui.separator();
+        }
+    }
+
+    fn show_ethminer_out(&mut self, ui: &mut egui::Ui) {
+        self.output_view.show(ui, &self.buffer);
+    }
+
+    /// Asks whether to stop the miner and quit, as closing the window would stop it
+    fn confirm_quit_window(&mut self, ctx: &egui::Context, frame: &epi::Frame) {
+        egui::Window::new("Quit?")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("The miner is running, quitting stops it.");
+                ui.horizontal(|ui| {
+                    if ui.button("Stop Mining and Quit").clicked() {
+                        self.confirm_quit = false;
+                        self.quit_confirmed = true;
+                        frame.quit();
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_quit = false;
+                    }
                 });
             });
     }
@@ -167,7 +1142,10 @@ impl MinerApp {
 
 impl Drop for MinerApp {
     fn drop(&mut self) {
-        MinerController::kill_child_miner(self.miner_controller.clone());
+        // A controller in another instance keeps mining after the window is closed
+        if !self.miner.is_remote() {
+            self.miner.stop();
+        }
     }
 }
 
@@ -205,18 +1183,59 @@ impl epi::App for MinerApp {
             println!("storage is None!");
         }
 
+        // The active profile takes precedence, as it's shared with headless mode
+        match Profiles::load().and_then(|p| p.get(None).map(|(_, s)| s.clone())) {
+            Ok(miner_settings) => {
+                *self.settings.write().unwrap() = miner_settings.clone();
+                self.temp_settings = miner_settings;
+            }
+            Err(e) => println!("Active profile not loaded: {e}"),
+        }
+
+        self.autostart = autostart::status(
+            &autostart::desktop_path(),
+            &std::env::current_exe().unwrap_or_default(),
+        );
+        match &self.autostart {
+            AutostartStatus::Enabled(options) => self.autostart_options = options.clone(),
+            AutostartStatus::Stale { exec, options } => {
+                println!("The autostart entry launches {}", exec.display());
+                self.autostart_options = options.clone();
+            }
+            AutostartStatus::Disabled => {}
+        }
+
+        // Attempts to load the lifetime statistics from storage
+        if let Some(json) = storage.and_then(|s| s.get_string("lifetime_stats")) {
+            match serde_json::from_str::<LifetimeStats>(&json) {
+                Ok(lifetime) => {
+                    if let Some(stats) = self.miner.local_stats() {
+                        stats.lock().unwrap().set_lifetime(lifetime);
+                    }
+                }
+                Err(e) => {
+                    println!("could not parse lifetime stats from json: \"{json}\" error: \"{e}\"");
+                }
+            }
+        }
+
         // Assigns the repaint_signal
         tokio::task::block_in_place(|| {
             let rs: Arc<dyn epi::backend::RepaintSignal> =
                 frame.0.lock().unwrap().repaint_signal.clone();
             self.repaint_signal = Some(rs);
             // Starts update_tx listener
-            let update_tx = self.miner_controller.blocking_lock().updated_tx.clone();
-            self.start_updater_task(update_tx);
+            self.start_updater_task(self.miner.updated_tx.clone());
+            self.start_show_window_task();
         });
     }
 
-    fn update(&mut self, ctx: &egui::Context, _frame: &eframe::epi::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &eframe::epi::Frame) {
+        self.reload_options();
+        if self.confirm_quit {
+            self.confirm_quit_window(ctx, frame);
+        }
+
         // Checks if this app has an error stored in self.error
         tokio::task::block_in_place(|| {
             let mut error = self.error.blocking_lock();
@@ -227,6 +1246,9 @@ impl epi::App for MinerApp {
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            self.show_shown_again(ui);
+            self.show_auto_start(ui);
+
             for url in &mut self.temp_settings.url {
                 settings_entry("Wallet Address", ui, |ui| {
                     ui.add(egui::TextEdit::singleline(&mut url.wallet_address));
@@ -269,6 +1291,45 @@ impl epi::App for MinerApp {
                 }
             });
 
+            settings_entry("Output Lines", ui, |ui| {
+                ui.add(
+                    egui::DragValue::new(&mut self.temp_settings.output_capacity)
+                        .clamp_range(100..=1_000_000),
+                );
+            });
+
+            settings_entry("Start Mining on Launch", ui, |ui| {
+                ui.checkbox(&mut self.temp_settings.start_on_launch, "");
+            });
+            ui.add_enabled_ui(self.temp_settings.start_on_launch, |ui| {
+                settings_entry("Launch Delay", ui, |ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut self.temp_settings.launch_delay_secs)
+                            .clamp_range(0..=600)
+                            .suffix(" s"),
+                    );
+                });
+            });
+
+            let watchdog = &mut self.temp_settings.watchdog;
+            for (label, mins) in [
+                ("Restart After No Output", &mut watchdog.no_output_mins),
+                (
+                    "Restart After Zero Hashrate",
+                    &mut watchdog.zero_hashrate_mins,
+                ),
+                ("Restart After No New Job", &mut watchdog.no_job_mins),
+            ] {
+                settings_entry(label, ui, |ui| {
+                    ui.add(
+                        egui::DragValue::new(mins)
+                            .clamp_range(0..=1440)
+                            .suffix(" min"),
+                    )
+                    .on_hover_text("0 turns the check off");
+                });
+            }
+
             self.show_device_settings(ui);
 
             ui.horizontal(|ui| {
@@ -281,6 +1342,20 @@ impl epi::App for MinerApp {
                         let mut settings = self.settings.write().unwrap();
                         *settings = self.temp_settings.clone();
                     }
+                    self.buffer
+                        .lock()
+                        .unwrap()
+                        .set_capacity(self.temp_settings.output_capacity);
+                    // Saves the settings as the active profile, for headless mode
+                    let saved = Profiles::load().and_then(|mut profiles| {
+                        profiles.set_active_settings(self.temp_settings.clone());
+                        profiles.save()
+                    });
+                    if let Err(e) = saved {
+                        tokio::task::block_in_place(|| {
+                            *self.error.blocking_lock() = Some(MinerError(e));
+                        });
+                    }
                     println!(
                         "Settings saved. New CLI options: {:?}",
                         &self.settings.read().unwrap().render()
@@ -290,15 +1365,28 @@ impl epi::App for MinerApp {
             ui.separator();
             ui.horizontal(|ui| {
                 if ui.button("Run").clicked() {
-                    MinerController::run_ethminer(
-                        self.miner_controller.clone(),
-                        self.settings.read().unwrap().clone(),
-                    );
+                    let settings = self.settings.read().unwrap().clone();
+                    self.miner.start(active_profile_name(), settings);
                 }
                 if ui.button("Stop").clicked() {
-                    MinerController::kill_child_miner(self.miner_controller.clone());
+                    self.miner.stop();
+                }
+                if self.in_tray {
+                    ui.label("Closing the window leaves the miner running in the tray");
+                } else if self.miner.is_remote() {
+                    ui.label("Attached to a running miner controller");
                 }
             });
+            let paused = self.miner.state().paused;
+            if !paused.is_empty() {
+                ui.label(format!("Paused, as {}", paused.join(", and ")));
+            }
+            self.show_schedule_status(ui);
+
+            self.show_stats(ui);
+            self.show_dag(ui);
+
+            self.show_options(ui);
 
             ui.vertical_centered_justified(|ui| {
                 self.show_ethminer_out(ui);
@@ -306,6 +1394,25 @@ impl epi::App for MinerApp {
         });
     }
 
+    fn on_exit_event(&mut self) -> bool {
+        // A miner in another instance, e.g. the one in the tray, keeps running
+        if self.quit_confirmed || self.miner.is_remote() || !self.miner.state().running {
+            return true;
+        }
+        self.confirm_quit = true;
+        false
+    }
+
+    fn on_exit(&mut self) {
+        if !self.miner.is_remote() {
+            // The process exits without dropping the app, so the miner is stopped here
+            tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(self.miner.shutdown())
+            });
+            launch::record_clean_exit();
+        }
+    }
+
     fn name(&self) -> &str {
         "etherminer-gui"
     }
@@ -321,9 +1428,48 @@ impl epi::App for MinerApp {
             }
             _ => {}
         }
+        if let Some(stats) = self.miner.local_stats() {
+            let lifetime = stats.lock().unwrap().lifetime();
+            if let Ok(json) = serde_json::to_string(&lifetime) {
+                storage.set_string("lifetime_stats", json);
+            }
+        }
     }
 }
 
+/// Edits the timezone of times of the week, returns whether it was changed
+fn timezone_entry(ui: &mut egui::Ui, timezone: &mut Timezone) -> bool {
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.label("Timezone");
+        changed |= ui.radio_value(timezone, Timezone::Local, "Local").changed();
+        let minutes = match *timezone {
+            Timezone::UtcOffset { minutes } => minutes,
+            Timezone::Local => 0,
+        };
+        changed |= ui
+            .radio_value(timezone, Timezone::UtcOffset { minutes }, "UTC Offset")
+            .changed();
+        if let Timezone::UtcOffset { minutes } = timezone {
+            changed |= ui
+                .add(
+                    egui::DragValue::new(minutes)
+                        .clamp_range(-720..=840)
+                        .speed(15)
+                        .suffix(" min"),
+                )
+                .changed();
+        }
+    });
+    changed
+}
+
+fn unix_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
 fn settings_entry<R>(
     label: &'static str,
     ui: &mut egui::Ui,
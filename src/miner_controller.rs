@@ -1,13 +1,20 @@
-use console::strip_ansi_codes;
+use std::collections::{BTreeMap, VecDeque};
 use std::process::Stdio;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{Child, ChildStdout, Command};
 use tokio::sync::Mutex;
 use tokio::sync::{mpsc, mpsc::Sender};
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout, Duration, Instant};
 
+use crate::alerts::Alert;
+use crate::ansi::StyledLine;
+use crate::diagnosis;
 use crate::miner_settings::MinerSettings;
+use crate::output_buffer::OutputBuffer;
+use crate::output_reader::{read_output, LineEvent};
+use crate::profiles::{load_profile, Profiles};
+use crate::stats::MinerStats;
+use crate::watchdog::Watchdog;
 
 /// Async controller for the child mining process.
 /// Interaction with MinerController is done via tokio channels
@@ -25,10 +32,64 @@ pub struct MinerController {
     pub error_tx: tokio::sync::broadcast::Sender<String>,
     /// The handle to the child process. This only set to None when the child is killed intentionally.
     child_handle: Option<Child>,
-    /// Contains the output of the miner as a Vec of the lines
-    pub buffer: Arc<Mutex<Vec<String>>>,
+    /// Contains the most recent lines of output of the miner
+    pub buffer: Arc<std::sync::Mutex<OutputBuffer>>,
+    /// Statistics of the mining session, updated from the output of the miner
+    pub stats: Arc<std::sync::Mutex<MinerStats>>,
+    /// Sends every new line of output, including the controller's own messages
+    /// Subscribe to this to follow the output, e.g. to write it to a log
+    pub lines_tx: tokio::sync::broadcast::Sender<LineEvent>,
+    /// Sends the new state whenever the miner is started or stopped
+    pub state_tx: tokio::sync::broadcast::Sender<MinerState>,
+    /// Name of the profile the miner was last started with, if it was started from a profile
+    pub profile: Option<String>,
+    /// Sends when the miner crashes, and when it is restarted or given up on
+    pub events_tx: tokio::sync::broadcast::Sender<MinerEvent>,
+    /// Times of the recent crashes, to give up restarting a miner that keeps crashing
+    crashes: VecDeque<Instant>,
+    /// Set while the miner is restarted after a crash
+    restarting: bool,
+    /// Sent to when launching the app again asks to show the window, received by the windows
+    pub show_window_tx: tokio::sync::broadcast::Sender<()>,
+    /// Why the miner is paused, by the policy holding each pause
+    pauses: BTreeMap<&'static str, String>,
+    /// Set when the miner was stopped or kept from starting by a pause, to start it once
+    /// no policy holds a pause anymore
+    resume: bool,
 }
 
+/// Whether the miner is running, as reported to the GUI and control socket clients
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MinerState {
+    pub running: bool,
+    pub profile: Option<String>,
+    pub pid: Option<u32>,
+    /// Why the miner is paused, empty if it isn't
+    #[serde(default)]
+    pub paused: Vec<String>,
+}
+
+/// Events of the miner that aren't visible in its state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MinerEvent {
+    /// The miner exited without having been stopped
+    Crashed,
+    /// The miner was started again after it crashed
+    Restarted,
+    /// The miner crashed GIVE_UP_CRASHES times within GIVE_UP_WINDOW, and isn't restarted
+    GaveUp,
+    /// The miner could not be started
+    SpawnFailed,
+    /// The watchdog restarted the miner, as it ran without mining, for the reason given
+    Stalled(String),
+    /// An alert rule fired
+    Alert(Alert),
+}
+
+/// Number of crashes within GIVE_UP_WINDOW after which the miner isn't restarted anymore
+const GIVE_UP_CRASHES: usize = 5;
+const GIVE_UP_WINDOW: Duration = Duration::from_secs(10 * 60);
+
 impl MinerController {
     /// The controller has multiple threads mutating it, which necessitates its references be
     /// encapsulated by Arc<Mutex<>>
@@ -37,6 +98,10 @@ impl MinerController {
         let (spawn_tx, mut spawn_rx) = mpsc::channel(2);
         let (updated_tx, _) = tokio::sync::broadcast::channel(2);
         let (error_tx, _) = tokio::sync::broadcast::channel(10);
+        let (lines_tx, lines) = tokio::sync::broadcast::channel(256);
+        let (state_tx, states) = tokio::sync::broadcast::channel(16);
+        let (events_tx, _) = tokio::sync::broadcast::channel(16);
+        let (show_window_tx, _) = tokio::sync::broadcast::channel(4);
 
         let controller = Arc::new(Mutex::new(MinerController {
             kill_tx,
@@ -44,16 +109,32 @@ impl MinerController {
             updated_tx: updated_tx.clone(),
             error_tx: error_tx.clone(),
             child_handle: None,
-            buffer: Arc::new(Mutex::new(Vec::new())),
+            buffer: Arc::new(std::sync::Mutex::new(OutputBuffer::default())),
+            stats: Arc::new(std::sync::Mutex::new(MinerStats::default())),
+            lines_tx,
+            state_tx,
+            profile: None,
+            events_tx,
+            crashes: VecDeque::new(),
+            restarting: false,
+            show_window_tx,
+            pauses: BTreeMap::new(),
+            resume: false,
         }));
 
+        // Points out known failures in the output of the miner
+        tokio::spawn(diagnosis::watch_output(lines, states, error_tx.clone()));
+
         let controller2 = controller.clone();
         // Starts a thread that kills when receiving the kill signal
         tokio::spawn(async move {
             loop {
                 if let Some(()) = kill_rx.recv().await {
                     println!("recv kill");
-                    controller2.lock().await.kill_miner().await;
+                    let mut mc = controller2.lock().await;
+                    // Stopped on purpose, so it isn't started when a pause ends
+                    mc.resume = false;
+                    mc.kill_miner().await;
                 }
             }
         });
@@ -66,6 +147,17 @@ impl MinerController {
                     println!("recv spawn");
                     {
                         let mut mc = controller3.lock().await;
+                        if !mc.pauses.is_empty() {
+                            let reasons: Vec<&str> =
+                                mc.pauses.values().map(String::as_str).collect();
+                            mc.push_message(&format!(
+                                "***** Not starting the miner while paused, as {} *****",
+                                reasons.join(", and ")
+                            ));
+                            mc.resume = true;
+                            mc.restarting = false;
+                            continue;
+                        }
                         let miner_setttings_clone = miner_settings.clone();
 
                         if mc.spawn_miner(miner_settings).await {
@@ -79,7 +171,14 @@ impl MinerController {
                             });
 
                             mc.update_buffer(updated_tx.clone()).await;
+                            if mc.restarting {
+                                let _ = mc.events_tx.send(MinerEvent::Restarted);
+                            }
+                        } else {
+                            let _ = mc.events_tx.send(MinerEvent::SpawnFailed);
                         }
+                        mc.restarting = false;
+                        mc.send_state();
                     }
                 }
             }
@@ -90,10 +189,12 @@ impl MinerController {
 
     /// Checks every few seconds if the child process has exited
     /// If it has, it will send on the child_died_tx channel, and exit
+    /// A child that is still running but stalled is restarted
     async fn spawn_child_exited_checker(
         controller: Arc<Mutex<MinerController>>,
         miner_settings: MinerSettings,
     ) {
+        let mut watchdog = Watchdog::new(&miner_settings.watchdog, Instant::now().into_std());
         loop {
             sleep(tokio::time::Duration::from_secs(7)).await;
             println!("checking if child died...",);
@@ -103,16 +204,46 @@ impl MinerController {
                 Some(child) => {
                     match child.try_wait() {
                         Ok(option_exit) => {
-                            if option_exit.is_some() {
+                            if let Some(status) = option_exit {
                                 // The child has exited, without being killed intentionally
                                 println!("Miner has exited unexpectedly!");
-                                {
-                                    let mut buf = mc.buffer.lock().await;
-                                    buf.push("".to_string());
-                                    buf.push("***** Miner Crashed! *****".to_string());
-                                    buf.push("***** Restarting.... *****".to_string());
+                                mc.push_message("");
+                                mc.push_message("***** Miner Crashed! *****");
+                                if let Some(diagnosis) = diagnosis::diagnose_exit(&status) {
+                                    // Nobody might be subscribed
+                                    let _ = mc.error_tx.send(diagnosis.to_string());
                                 }
                                 mc.child_handle = None;
+                                mc.stats.lock().unwrap().miner_crashed();
+                                let _ = mc.events_tx.send(MinerEvent::Crashed);
+                                if keeps_crashing(&mut mc.crashes, Instant::now()) {
+                                    mc.push_message(
+                                        "***** Miner keeps crashing, not restarting it *****",
+                                    );
+                                    mc.send_state();
+                                    let _ = mc.events_tx.send(MinerEvent::GaveUp);
+                                    return;
+                                }
+                                mc.push_message("***** Restarting.... *****");
+                                mc.stats.lock().unwrap().miner_restarted();
+                                mc.restarting = true;
+                                mc.send_state();
+                                mc.spawn_tx.send(miner_settings).await.unwrap();
+                                return;
+                            }
+                            let stall = {
+                                let stats = mc.stats.lock().unwrap();
+                                watchdog.check(&stats.session, Instant::now().into_std())
+                            };
+                            if let Some(stall) = stall {
+                                println!("Miner stalled: {stall}");
+                                mc.push_message("");
+                                mc.push_message(&format!(
+                                    "***** Miner stalled with {stall}, restarting.... *****"
+                                ));
+                                mc.kill_miner().await;
+                                mc.stats.lock().unwrap().miner_stalled();
+                                let _ = mc.events_tx.send(MinerEvent::Stalled(stall.to_string()));
                                 mc.spawn_tx.send(miner_settings).await.unwrap();
                                 return;
                             }
@@ -141,23 +272,46 @@ impl MinerController {
         });
     }
 
-    /// Aquires the lock and sends to the spawn channel
-    /// Sends a reference to the MinerSettings to the controller
-    pub fn run_ethminer(mc: Arc<Mutex<MinerController>>, miner_settings: MinerSettings) {
+    /// Aquires the lock, remembers the profile name and sends to the spawn channel
+    pub fn run_profile(
+        mc: Arc<Mutex<MinerController>>,
+        profile: String,
+        miner_settings: MinerSettings,
+    ) {
         tokio::spawn(async move {
-            mc.lock()
-                .await
-                .spawn_tx
+            let mut mc = mc.lock().await;
+            mc.profile = Some(profile);
+            mc.spawn_tx
                 .send(miner_settings)
                 .await
                 .expect("Could not send spawn");
         });
     }
 
+    /// The current state of the miner
+    pub fn state(&self) -> MinerState {
+        let pid = self.child_handle.as_ref().and_then(|c| c.id());
+        MinerState {
+            running: pid.is_some(),
+            profile: self.profile.clone(),
+            pid,
+            paused: self.pauses.values().cloned().collect(),
+        }
+    }
+
+    fn send_state(&self) {
+        // Nobody might be subscribed
+        let _ = self.state_tx.send(self.state());
+    }
+
     /// This function is run by the spawn_rx on receiving
     /// returns true if the child was spawned
     async fn spawn_miner(&mut self, miner_settings: MinerSettings) -> bool {
         self.kill_miner().await;
+        self.buffer
+            .lock()
+            .unwrap()
+            .set_capacity(miner_settings.output_capacity);
 
         println!("Spawning...");
         let cmd = Command::new(miner_settings.bin_path.to_owned())
@@ -168,6 +322,7 @@ impl MinerController {
         match cmd {
             Ok(child) => {
                 self.child_handle = Some(child);
+                self.stats.lock().unwrap().miner_started();
                 true
             }
             Err(error) => {
@@ -183,46 +338,158 @@ impl MinerController {
         }
     }
 
-    #[allow(unused_must_use)]
     /// This function is run by spawn_miner, and starts a task that appends
     /// the output of the child_handle process to the output buffer
     async fn update_buffer(&mut self, updated_tx: tokio::sync::broadcast::Sender<()>) {
         if let Some(child_handle) = self.child_handle.as_mut() {
             let stdout: ChildStdout = child_handle.stdout.take().expect("No child stdout");
-
-            let buf = BufReader::new(stdout);
-            let mut lines = buf.lines();
             let out = self.buffer.clone();
+            let stats = self.stats.clone();
+            let lines_tx = self.lines_tx.clone();
 
             // Spawns a thread to read the lines from the buffer as they
             // are made available
-            tokio::spawn(async move {
-                while let Some(line) = lines.next_line().await.unwrap() {
-                    println!(" > {}", &line);
-                    let mut o = out.lock().await;
-                    o.push(strip_ansi_codes(&line).to_string());
-                    if o.len() > 1000 {
-                        o.drain(0..500);
-                    }
-                    // I don't care if this fails if the rx is not recieving
-                    updated_tx.send(());
-                }
-            });
+            tokio::spawn(read_output(stdout, out, stats, lines_tx, updated_tx));
         }
     }
 
+    /// Stops the miner, waiting for it to exit, and prevents it from being restarted
+    pub async fn shutdown(&mut self) {
+        self.kill_miner().await;
+    }
+
     /// This function is run by the kill_rx on receiving
     async fn kill_miner(&mut self) {
         println!("kill_miner()");
         if let Some(x) = self.child_handle.as_mut() {
             println!("Killing");
-            x.kill().await.expect("Could not kill");
+            stop_child(x).await;
             self.child_handle = None;
-            {
-                let mut buf = self.buffer.lock().await;
-                buf.push("".to_string());
-                buf.push("***** Killed miner *****".to_string());
+            self.stats.lock().unwrap().miner_stopped();
+            self.push_message("");
+            self.push_message("***** Killed miner *****");
+            self.send_state();
+        }
+    }
+
+    /// Stops the miner while the policy holds a pause, and keeps it from being started
+    pub async fn pause(&mut self, policy: &'static str, reason: String) {
+        if self.pauses.get(policy) == Some(&reason) {
+            return;
+        }
+        self.pauses.insert(policy, reason.clone());
+        if self.child_handle.is_some() {
+            println!("Pausing the miner: {reason}");
+            self.push_message("");
+            self.push_message(&format!("***** Pausing the miner, as {reason} *****"));
+            self.kill_miner().await;
+            self.resume = true;
+        }
+        self.send_state();
+    }
+
+    /// Releases the pause of the policy, starting the miner again with the profile it last
+    /// ran if it was paused and no other policy holds a pause. Returns whether it was started.
+    pub async fn unpause(&mut self, policy: &str) -> bool {
+        if self.pauses.remove(policy).is_none() {
+            return false;
+        }
+        self.send_state();
+        if !self.pauses.is_empty() || !std::mem::take(&mut self.resume) {
+            return false;
+        }
+        match load_profile(&Profiles::path(), self.profile.as_deref()) {
+            Ok((_, settings)) => {
+                println!("Resuming the miner");
+                self.push_message("***** Resuming the miner *****");
+                self.spawn_tx.send(settings).await.unwrap();
+                true
+            }
+            Err(e) => {
+                // Nobody might be subscribed
+                let _ = self.error_tx.send(e);
+                false
+            }
+        }
+    }
+
+    /// Writes the alert to the output, and sends it to the subscribers of the events
+    pub fn raise_alert(&self, alert: Alert) {
+        self.push_message(&format!(
+            "***** {} alert: {} *****",
+            alert.severity.label(),
+            alert.message
+        ));
+        let _ = self.events_tx.send(MinerEvent::Alert(alert));
+    }
+
+    /// Adds a message from the controller to the output
+    pub fn push_message(&self, message: &str) {
+        let mut buf = self.buffer.lock().unwrap();
+        buf.push(StyledLine::plain(message));
+        // Sent while holding the buffer lock, so subscribers that copy the buffer
+        // before subscribing never miss or duplicate a line. Nobody might be subscribed
+        let _ = self.lines_tx.send(LineEvent::Push(message.to_string()));
+        let _ = self.updated_tx.send(());
+    }
+}
+
+/// Records a crash, returning whether the miner crashed too often to restart it
+fn keeps_crashing(crashes: &mut VecDeque<Instant>, now: Instant) -> bool {
+    crashes.push_back(now);
+    while let Some(first) = crashes.front() {
+        if now.duration_since(*first) <= GIVE_UP_WINDOW {
+            break;
+        }
+        crashes.pop_front();
+    }
+    if crashes.len() >= GIVE_UP_CRASHES {
+        crashes.clear();
+        return true;
+    }
+    false
+}
+
+/// How long the miner gets to exit after being asked to, before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Asks the child to exit with SIGTERM, so the miner can release the GPUs
+/// cleanly, and kills it if it hasn't exited after STOP_TIMEOUT
+async fn stop_child(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // Safe, as the child hasn't been waited on, so the pid can't have been reused
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == 0 {
+            if let Ok(status) = timeout(STOP_TIMEOUT, child.wait()).await {
+                println!("Miner exited with {:?}", status);
+                return;
             }
+            println!("Miner did not exit in time, killing");
+        }
+    }
+    child.kill().await.expect("Could not kill");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_crashing() {
+        let mut crashes = VecDeque::new();
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        // Crashes spread out over a long time keep being restarted
+        for i in 0..10 {
+            assert!(!keeps_crashing(&mut crashes, start + i * 3 * minute));
+        }
+
+        let start = start + 60 * minute;
+        for i in 0..GIVE_UP_CRASHES as u32 - 1 {
+            assert!(!keeps_crashing(&mut crashes, start + i * minute));
         }
+        assert!(keeps_crashing(&mut crashes, start + 5 * minute));
+        // Starting again gets a fresh set of restarts
+        assert!(!keeps_crashing(&mut crashes, start + 6 * minute));
     }
 }
@@ -0,0 +1,524 @@
+use crate::alerts::{self, Alert};
+use crate::idle;
+use crate::instance::apply_launch;
+use crate::launch;
+use crate::miner_controller::{MinerController, MinerEvent, MinerState};
+use crate::miner_settings::MinerSettings;
+use crate::output_buffer::OutputBuffer;
+use crate::output_reader::LineEvent;
+use crate::process_watch;
+use crate::profiles::{activate_profile, load_profile, Profiles};
+use crate::schedule;
+use crate::stats::{MinerStats, StatsSnapshot};
+use crate::tariff;
+use crate::webhooks;
+
+#[cfg(unix)]
+use crate::ansi::StyledLine;
+#[cfg(unix)]
+use crate::ipc::{Client, Command, Event, Reply, Topic};
+
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+
+/// The miner as seen by the GUI and tray.
+/// The controller either runs in this process, or in another instance
+/// that the GUI attached to through the control socket.
+#[derive(Clone)]
+pub struct MinerHandle {
+    /// Contains the most recent lines of output of the miner
+    pub buffer: Arc<std::sync::Mutex<OutputBuffer>>,
+    /// Sent to when the output or the statistics have been updated
+    pub updated_tx: broadcast::Sender<()>,
+    /// Sent to with the message of recoverable errors
+    pub error_tx: broadcast::Sender<String>,
+    /// Sent to when launching the app again asks to show the window
+    pub show_window_tx: broadcast::Sender<()>,
+    /// Whether the miner is running, updated as the controller reports it
+    state: Arc<std::sync::Mutex<MinerState>>,
+    backend: Backend,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Local {
+        controller: Arc<Mutex<MinerController>>,
+        stats: Arc<std::sync::Mutex<MinerStats>>,
+    },
+    #[cfg(unix)]
+    Remote {
+        client: Arc<Client>,
+        /// The statistics last sent by the controller
+        stats: Arc<std::sync::Mutex<StatsSnapshot>>,
+    },
+}
+
+impl MinerHandle {
+    /// Uses a controller running in this process
+    pub async fn local(controller: Arc<Mutex<MinerController>>) -> MinerHandle {
+        let mc = controller.lock().await;
+        let handle = MinerHandle {
+            buffer: mc.buffer.clone(),
+            updated_tx: mc.updated_tx.clone(),
+            error_tx: mc.error_tx.clone(),
+            show_window_tx: mc.show_window_tx.clone(),
+            state: Arc::new(std::sync::Mutex::new(mc.state())),
+            backend: Backend::Local {
+                stats: mc.stats.clone(),
+                controller: controller.clone(),
+            },
+        };
+        let mut state_rx = mc.state_tx.subscribe();
+        let mirror = handle.clone();
+        tokio::spawn(async move {
+            loop {
+                match state_rx.recv().await {
+                    Ok(state) => mirror.set_state(state),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+        handle
+    }
+
+    /// Attaches to the controller serving the control socket.
+    /// The output and statistics are mirrored into this process as the controller sends them.
+    #[cfg(unix)]
+    pub async fn attach(path: &std::path::Path) -> Result<MinerHandle, String> {
+        let client = Arc::new(Client::connect(path).await?);
+        let mut events = client
+            .events()
+            .ok_or("The connection to the controller was closed")?;
+        client
+            .request(Command::Subscribe {
+                topics: vec![Topic::Lines, Topic::Stats, Topic::State],
+                backlog: OutputBuffer::DEFAULT_CAPACITY,
+            })
+            .await?;
+        // The state is also requested, so it is known before the first state event arrives
+        let state = match client.request(Command::GetState).await? {
+            Reply::State(state) => state,
+            reply => return Err(format!("Unexpected reply from the controller: {reply:?}")),
+        };
+
+        let handle = MinerHandle {
+            buffer: Arc::new(std::sync::Mutex::new(OutputBuffer::default())),
+            updated_tx: broadcast::channel(2).0,
+            error_tx: broadcast::channel(10).0,
+            show_window_tx: broadcast::channel(4).0,
+            state: Arc::new(std::sync::Mutex::new(state)),
+            backend: Backend::Remote {
+                client,
+                stats: Arc::new(std::sync::Mutex::new(StatsSnapshot::default())),
+            },
+        };
+        let mirror = handle.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(Event::Line { text, replace }) => {
+                        let line = StyledLine::parse(&text);
+                        let mut buf = mirror.buffer.lock().unwrap();
+                        if replace {
+                            buf.replace_last(line);
+                        } else {
+                            buf.push(line);
+                        }
+                    }
+                    Ok(Event::Stats(snapshot)) => {
+                        if let Backend::Remote { stats, .. } = &mirror.backend {
+                            *stats.lock().unwrap() = snapshot;
+                        }
+                    }
+                    Ok(Event::State(state)) => mirror.set_state(state),
+                    Ok(Event::ShowWindow) => {
+                        let _ = mirror.show_window_tx.send(());
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => {
+                        let message = "Lost the connection to the miner controller";
+                        mirror
+                            .buffer
+                            .lock()
+                            .unwrap()
+                            .push(StyledLine::plain(&format!("***** {message} *****")));
+                        let _ = mirror.error_tx.send(message.to_string());
+                        let _ = mirror.updated_tx.send(());
+                        return;
+                    }
+                }
+                let _ = mirror.updated_tx.send(());
+            }
+        });
+        Ok(handle)
+    }
+
+    /// Applies the arguments of this launch, see instance::apply_launch. With show_window,
+    /// a remote controller asks its window to show itself, returning whether there was one.
+    pub async fn launch(
+        &self,
+        profile: Option<&str>,
+        start_mining: bool,
+        show_window: bool,
+    ) -> Result<bool, String> {
+        match &self.backend {
+            Backend::Local { controller, .. } => {
+                apply_launch(controller.clone(), &Profiles::path(), profile, start_mining).await?;
+                Ok(false)
+            }
+            #[cfg(unix)]
+            Backend::Remote { client, .. } => {
+                let command = Command::Launch {
+                    profile: profile.map(str::to_string),
+                    start_mining,
+                    show_window,
+                };
+                match client.request(command).await? {
+                    Reply::Launched { window_shown } => Ok(window_shown),
+                    reply => Err(format!("Unexpected reply from the controller: {reply:?}")),
+                }
+            }
+        }
+    }
+
+    /// Makes this the window of a remote controller, receiving its requests to show the window
+    pub async fn register_window(&self) -> Result<(), String> {
+        #[cfg(unix)]
+        if let Backend::Remote { client, .. } = &self.backend {
+            client
+                .request(Command::Subscribe {
+                    topics: vec![Topic::Window],
+                    backlog: 0,
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// True if the controller runs in another instance
+    pub fn is_remote(&self) -> bool {
+        !matches!(self.backend, Backend::Local { .. })
+    }
+
+    /// Starts the miner with the settings of the profile.
+    /// A remote controller loads the profile itself, so the settings need to have been saved to it.
+    pub fn start(&self, profile: String, settings: MinerSettings) {
+        match &self.backend {
+            Backend::Local { controller, .. } => {
+                MinerController::run_profile(controller.clone(), profile, settings)
+            }
+            #[cfg(unix)]
+            Backend::Remote { client, .. } => self.send(
+                client,
+                Command::Start {
+                    profile: Some(profile),
+                },
+            ),
+        }
+    }
+
+    /// Starts the miner with the active profile
+    pub fn start_active_profile(&self) -> Result<(), String> {
+        let (name, settings) = load_profile(&Profiles::path(), None)?;
+        self.start(name, settings);
+        Ok(())
+    }
+
+    /// Restarts the miner with the profile it was last started with
+    pub fn restart(&self) {
+        match &self.backend {
+            Backend::Local { controller, .. } => {
+                let controller = controller.clone();
+                let error_tx = self.error_tx.clone();
+                tokio::spawn(async move {
+                    let profile = controller.lock().await.profile.clone();
+                    match load_profile(&Profiles::path(), profile.as_deref()) {
+                        Ok((name, settings)) => {
+                            MinerController::run_profile(controller, name, settings)
+                        }
+                        Err(e) => {
+                            let _ = error_tx.send(e);
+                        }
+                    }
+                });
+            }
+            #[cfg(unix)]
+            Backend::Remote { client, .. } => self.send(client, Command::Restart),
+        }
+    }
+
+    /// Makes the profile the active profile, restarting the miner with it if it is running
+    pub fn switch_profile(&self, profile: String) {
+        match &self.backend {
+            Backend::Local { controller, .. } => {
+                match activate_profile(&Profiles::path(), &profile) {
+                    Ok(settings) if self.state().running => {
+                        MinerController::run_profile(controller.clone(), profile, settings)
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        let _ = self.error_tx.send(e);
+                    }
+                }
+            }
+            #[cfg(unix)]
+            Backend::Remote { client, .. } => self.send(client, Command::SwitchProfile { profile }),
+        }
+    }
+
+    pub fn stop(&self) {
+        match &self.backend {
+            Backend::Local { controller, .. } => {
+                MinerController::kill_child_miner(controller.clone())
+            }
+            #[cfg(unix)]
+            Backend::Remote { client, .. } => self.send(client, Command::Stop),
+        }
+    }
+
+    /// Stops a miner running in this process, waiting for it to exit.
+    /// A remote controller keeps mining.
+    pub async fn shutdown(&self) {
+        if let Backend::Local { controller, .. } = &self.backend {
+            controller.lock().await.shutdown().await;
+        }
+    }
+
+    /// Receives the crashes, restarts and alerts of a miner running in this process.
+    /// A remote controller keeps track of its own crashes.
+    pub async fn subscribe_events(&self) -> Option<broadcast::Receiver<MinerEvent>> {
+        match &self.backend {
+            Backend::Local { controller, .. } => {
+                Some(controller.lock().await.events_tx.subscribe())
+            }
+            #[cfg(unix)]
+            Backend::Remote { .. } => None,
+        }
+    }
+
+    /// Raises an alert on a miner running in this process
+    pub async fn raise_alert(&self, alert: Alert) {
+        if let Backend::Local { controller, .. } = &self.backend {
+            controller.lock().await.raise_alert(alert);
+        }
+    }
+
+    /// Pauses a miner running in this process for the policy, see MinerController::pause
+    pub async fn pause(&self, policy: &'static str, reason: String) {
+        if let Backend::Local { controller, .. } = &self.backend {
+            controller.lock().await.pause(policy, reason).await;
+        }
+    }
+
+    /// Ends the pause of the policy on a miner running in this process.
+    /// Returns whether that started the miner again.
+    pub async fn unpause(&self, policy: &str) -> bool {
+        match &self.backend {
+            Backend::Local { controller, .. } => controller.lock().await.unpause(policy).await,
+            #[cfg(unix)]
+            Backend::Remote { .. } => false,
+        }
+    }
+
+    /// Writes a message to the output of a miner running in this process
+    pub async fn push_message(&self, message: &str) {
+        if let Backend::Local { controller, .. } = &self.backend {
+            controller.lock().await.push_message(message);
+        }
+    }
+
+    /// Receives every line of output of a miner running in this process.
+    /// A remote controller notifies of its own output.
+    pub async fn subscribe_lines(&self) -> Option<broadcast::Receiver<LineEvent>> {
+        match &self.backend {
+            Backend::Local { controller, .. } => Some(controller.lock().await.lines_tx.subscribe()),
+            #[cfg(unix)]
+            Backend::Remote { .. } => None,
+        }
+    }
+
+    pub fn state(&self) -> MinerState {
+        self.state.lock().unwrap().clone()
+    }
+
+    fn set_state(&self, state: MinerState) {
+        *self.state.lock().unwrap() = state;
+        let _ = self.updated_tx.send(());
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        match &self.backend {
+            Backend::Local { stats, .. } => stats.lock().unwrap().snapshot(),
+            #[cfg(unix)]
+            Backend::Remote { stats, .. } => stats.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        match &self.backend {
+            Backend::Local { stats, .. } => stats.lock().unwrap().reset(),
+            #[cfg(unix)]
+            Backend::Remote { client, .. } => self.send(client, Command::ResetStats),
+        }
+    }
+
+    /// The statistics of a controller running in this process, which the GUI persists.
+    /// A remote controller keeps its own statistics.
+    pub fn local_stats(&self) -> Option<&Arc<std::sync::Mutex<MinerStats>>> {
+        match &self.backend {
+            Backend::Local { stats, .. } => Some(stats),
+            #[cfg(unix)]
+            Backend::Remote { .. } => None,
+        }
+    }
+
+    /// Sends the request in a new task, reporting failures on error_tx
+    #[cfg(unix)]
+    fn send(&self, client: &Arc<Client>, command: Command) {
+        let client = client.clone();
+        let error_tx = self.error_tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = client.request(command).await {
+                let _ = error_tx.send(e);
+            }
+        });
+    }
+}
+
+/// Attaches to the controller of the instance that is already running, e.g. in
+/// headless mode. Otherwise starts a controller in this process, and serves
+/// the control socket for it. Fails if another instance holds the instance lock,
+/// but can't be attached to.
+pub async fn attach_or_start() -> Result<MinerHandle, String> {
+    #[cfg(unix)]
+    {
+        use crate::instance::{lock_path, InstanceLock};
+
+        let path = crate::ipc::socket_path();
+        match InstanceLock::acquire(&lock_path()) {
+            // Held until the process exits
+            Ok(Some(lock)) => std::mem::forget(lock),
+            Ok(None) => {
+                let handle = wait_and_attach(&path).await.map_err(|e| {
+                    format!("Another instance is running, but could not be attached to: {e}")
+                })?;
+                println!("Attached to the miner controller at {}", path.display());
+                return Ok(handle);
+            }
+            Err(e) => {
+                println!("Not using the instance lock: {e}");
+                if let Ok(handle) = MinerHandle::attach(&path).await {
+                    println!("Attached to the miner controller at {}", path.display());
+                    return Ok(handle);
+                }
+            }
+        }
+    }
+
+    let controller = MinerController::new();
+    #[cfg(unix)]
+    {
+        let server = crate::ipc::Server::new(controller.clone(), crate::profiles::Profiles::path());
+        if let Err(e) = server.listen(&crate::ipc::socket_path()).await {
+            println!("Could not serve the control socket: {e}");
+        }
+    }
+    Ok(MinerHandle::local(controller).await)
+}
+
+/// Starts the tasks acting on a miner running in this process, whichever app runs it:
+/// recording the launch and the crashes, the alerts and webhooks, and the policies pausing
+/// the miner. Returns whether the previous session ended in a crash loop, or false when
+/// attached to another instance, which starts these tasks itself.
+pub async fn start_tasks(miner: &MinerHandle) -> bool {
+    if miner.is_remote() {
+        return false;
+    }
+    let crash_loop = launch::track_launch(miner).await;
+    webhooks::start(miner).await;
+    alerts::start(miner);
+    schedule::start(miner);
+    tariff::start(miner);
+    idle::start(miner);
+    process_watch::start(miner);
+    crash_loop
+}
+
+/// Attaches to the controller at path, waiting for an instance that is
+/// still starting to serve the control socket
+#[cfg(unix)]
+pub async fn wait_and_attach(path: &std::path::Path) -> Result<MinerHandle, String> {
+    let mut tries = 0;
+    loop {
+        match MinerHandle::attach(path).await {
+            Ok(handle) => return Ok(handle),
+            Err(e) if tries == 50 => return Err(e),
+            Err(_) => tries += 1,
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::Server;
+    use crate::miner_settings::tests::fake_miner_settings;
+    use crate::profiles::Profiles;
+    use tokio::time::{sleep, Duration};
+
+    /// Polls until the condition is met
+    async fn wait_until(mut condition: impl FnMut() -> bool) {
+        for _ in 0..100 {
+            if condition() {
+                return;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Timed out");
+    }
+
+    fn has_line(handle: &MinerHandle, text: &str) -> bool {
+        let mut snapshot = Default::default();
+        handle.buffer.lock().unwrap().update_snapshot(&mut snapshot);
+        snapshot.lines.iter().any(|l| l.text.contains(text))
+    }
+
+    #[tokio::test]
+    async fn test_attach_to_controller() {
+        let dir = std::env::temp_dir().join(format!("ethminer-gui-attach-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("control.sock");
+        let profiles_path = dir.join("profiles.json");
+        let mut profiles = Profiles::default();
+        profiles.set_active_settings(fake_miner_settings(
+            &dir,
+            "echo ' m 17:29:50 ethminer 0:00 A0 20.00 Mh - cu0 20.00'\nexec sleep 30",
+        ));
+        profiles.save_to(&profiles_path).unwrap();
+
+        let controller = MinerController::new();
+        let local = MinerHandle::local(controller.clone()).await;
+        assert!(!local.is_remote());
+        Server::new(controller, profiles_path)
+            .listen(&socket)
+            .await
+            .unwrap();
+
+        let remote = MinerHandle::attach(&socket).await.unwrap();
+        assert!(remote.is_remote());
+        assert!(remote.local_stats().is_none());
+        remote.start("default".to_string(), MinerSettings::default());
+        wait_until(|| has_line(&remote, "20.00 Mh")).await;
+        wait_until(|| remote.stats().current_hashrate == 20e6).await;
+        wait_until(|| remote.state().running && local.state().running).await;
+        // The output is the same as in the controller's process
+        assert!(has_line(&local, "20.00 Mh"));
+
+        remote.stop();
+        wait_until(|| has_line(&remote, "Killed miner")).await;
+        wait_until(|| !remote.state().running && !local.state().running).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
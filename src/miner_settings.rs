@@ -1,3 +1,5 @@
+use crate::output_buffer::OutputBuffer;
+
 /// Defines cli settings to be passed to ethminer
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct MinerSettings {
@@ -8,6 +10,21 @@ pub struct MinerSettings {
     pub display_interval: f32,
     /// Path to ethminer bin
     pub bin_path: String,
+    /// Number of lines of miner output kept for the output view
+    #[serde(default = "default_output_capacity")]
+    pub output_capacity: usize,
+    /// Start mining with this profile when the app launches
+    #[serde(default)]
+    pub start_on_launch: bool,
+    /// Seconds to wait before starting on launch, so the desktop and GPU drivers can settle
+    #[serde(default)]
+    pub launch_delay_secs: u32,
+    #[serde(default)]
+    pub watchdog: WatchdogSettings,
+}
+
+fn default_output_capacity() -> usize {
+    OutputBuffer::DEFAULT_CAPACITY
 }
 
 impl Default for MinerSettings {
@@ -17,6 +34,10 @@ impl Default for MinerSettings {
             device_type: None,
             display_interval: 1.0,
             bin_path: "/home/figes/Desktop/ethminer/bin/ethminer".to_owned(),
+            output_capacity: default_output_capacity(),
+            start_on_launch: false,
+            launch_delay_secs: 0,
+            watchdog: WatchdogSettings::default(),
         }
     }
 }
@@ -34,6 +55,10 @@ impl Clone for MinerSettings {
             device_type: self.device_type.clone(),
             display_interval: self.display_interval,
             bin_path: self.bin_path.clone(),
+            output_capacity: self.output_capacity,
+            start_on_launch: self.start_on_launch,
+            launch_delay_secs: self.launch_delay_secs,
+            watchdog: self.watchdog.clone(),
         }
     }
 }
@@ -55,6 +80,36 @@ impl MinerSettings {
         }
         out
     }
+
+    /// Moves the pool to the front of the list, as ethminer tries the pools in order
+    pub fn move_pool_first(&mut self, index: usize) {
+        if index < self.url.len() {
+            let url = self.url.remove(index);
+            self.url.insert(0, url);
+        }
+    }
+}
+
+/// How long the miner may run without making progress before it is restarted, in minutes.
+/// 0 turns a check off
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WatchdogSettings {
+    pub no_output_mins: u32,
+    /// Leaves time to generate the DAG, during which the hashrate is reported as zero
+    pub zero_hashrate_mins: u32,
+    pub no_job_mins: u32,
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        Self {
+            no_output_mins: 5,
+            zero_hashrate_mins: 10,
+            no_job_mins: 10,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -155,6 +210,11 @@ impl Default for Url {
 }
 
 impl Url {
+    /// Short description of the pool, e.g. "us2.ethermine.org:4444"
+    pub fn label(&self) -> String {
+        format!("{}:{}", self.pool, self.port)
+    }
+
     fn render(&self) -> Vec<String> {
         let mut str_o = String::new();
         str_o.push_str(&self.scheme.stratum.to_string());
@@ -216,8 +276,23 @@ pub enum Transport {
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
+
+    /// Settings that only render to a script, for running a fake miner in the tests of the
+    /// modules that run the miner
+    #[cfg(unix)]
+    pub fn fake_miner_settings(dir: &std::path::Path, script: &str) -> MinerSettings {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join("fake-ethminer.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        MinerSettings {
+            bin_path: path.to_str().unwrap().to_string(),
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn test_url_render() {
         let url = Url::default();
@@ -255,6 +330,22 @@ mod tests {
         println!("Cl cli: {:?}", settings.render());
     }
 
+    #[test]
+    fn test_move_pool_first() {
+        let pool = |name: &str| Url {
+            pool: name.to_string(),
+            ..Default::default()
+        };
+        let mut settings = MinerSettings {
+            url: vec![pool("a"), pool("b"), pool("c")],
+            ..Default::default()
+        };
+        settings.move_pool_first(2);
+        settings.move_pool_first(5);
+        let pools: Vec<String> = settings.url.iter().map(Url::label).collect();
+        assert_eq!(pools, ["c:4444", "a:4444", "b:4444"]);
+    }
+
     #[test]
     fn test_mult_urls() {
         let settings = MinerSettings {
@@ -0,0 +1,596 @@
+use crate::alerts::Severity;
+use crate::ansi::StyledLine;
+use crate::app_options::{self, NotificationOptions, OptionsReader};
+use crate::miner_controller::MinerEvent;
+use crate::miner_handle::MinerHandle;
+use crate::output_parser::{parse_event, OutputEvent};
+use crate::output_reader::LineEvent;
+use crate::tray::open_log_folder;
+
+use futures_util::StreamExt;
+use proxy::{ActionInvoked, ActionInvokedStream, NotificationsProxy};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+/// Minimum time between two notifications of the same event
+const RATE_LIMIT: Duration = Duration::from_secs(5 * 60);
+/// Number of notifications whose actions are still handled
+const MAX_SENT: usize = 32;
+
+mod proxy {
+    // The arguments are given by the specification
+    #![allow(clippy::too_many_arguments)]
+
+    use std::collections::HashMap;
+    use zbus::dbus_proxy;
+    use zbus::zvariant::Value;
+
+    /// The freedesktop notification service, see
+    /// https://specifications.freedesktop.org/notification-spec/latest/
+    #[dbus_proxy(
+        interface = "org.freedesktop.Notifications",
+        default_service = "org.freedesktop.Notifications",
+        default_path = "/org/freedesktop/Notifications"
+    )]
+    trait Notifications {
+        fn notify(
+            &self,
+            app_name: &str,
+            replaces_id: u32,
+            app_icon: &str,
+            summary: &str,
+            body: &str,
+            actions: &[&str],
+            hints: HashMap<&str, Value<'_>>,
+            expire_timeout: i32,
+        ) -> zbus::Result<u32>;
+
+        #[dbus_proxy(signal)]
+        fn action_invoked(&self, id: u32, action_key: &str) -> zbus::Result<()>;
+    }
+}
+
+/// The events of the miner that can be notified
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NotifyEvent {
+    Crashed,
+    GaveUp,
+    PoolLost,
+    /// A GPU stopped mining as it got too hot
+    ThermalStop,
+    Error,
+    /// An alert rule fired, with the severity of the rule
+    Alert(Severity),
+}
+
+impl NotifyEvent {
+    fn summary(self) -> &'static str {
+        match self {
+            NotifyEvent::Crashed => "The miner crashed",
+            NotifyEvent::GaveUp => "The miner keeps crashing",
+            NotifyEvent::PoolLost => "The miner lost the connection to the pool",
+            NotifyEvent::ThermalStop => "A GPU stopped mining as it got too hot",
+            NotifyEvent::Error => "Mining error",
+            NotifyEvent::Alert(Severity::Info) => "Mining info",
+            NotifyEvent::Alert(Severity::Warning) => "Mining warning",
+            NotifyEvent::Alert(Severity::Critical) => "Mining alert",
+        }
+    }
+
+    /// The urgency hint, critical notifications stay until they are dismissed
+    fn urgency(self) -> u8 {
+        match self {
+            NotifyEvent::Alert(Severity::Info) => 0,
+            NotifyEvent::Alert(Severity::Critical) => 2,
+            _ => 1,
+        }
+    }
+
+    fn enabled(self, options: &NotificationOptions) -> bool {
+        match self {
+            NotifyEvent::Crashed | NotifyEvent::GaveUp => options.crashed,
+            NotifyEvent::PoolLost => options.pool_lost,
+            NotifyEvent::ThermalStop => options.thermal_stop,
+            NotifyEvent::Error => options.errors,
+            NotifyEvent::Alert(_) => options.alerts,
+        }
+    }
+}
+
+/// The buttons of the notifications
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotifyAction {
+    Restart,
+    OpenLog,
+}
+
+impl NotifyAction {
+    const ALL: [NotifyAction; 2] = [NotifyAction::Restart, NotifyAction::OpenLog];
+
+    fn key(self) -> &'static str {
+        match self {
+            NotifyAction::Restart => "restart",
+            NotifyAction::OpenLog => "open-log",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            NotifyAction::Restart => "Restart",
+            NotifyAction::OpenLog => "Open log",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<NotifyAction> {
+        NotifyAction::ALL
+            .into_iter()
+            .find(|action| action.key() == key)
+    }
+}
+
+/// Allows one notification of each event per RATE_LIMIT, counting the ones suppressed in between
+#[derive(Default)]
+struct RateLimiter {
+    last: HashMap<NotifyEvent, Instant>,
+    suppressed: HashMap<NotifyEvent, u32>,
+}
+
+impl RateLimiter {
+    /// Returns the number of notifications of the event suppressed since the last one,
+    /// or None if this one is suppressed too
+    fn allow(&mut self, event: NotifyEvent, now: Instant) -> Option<u32> {
+        // Alert rules have cooldowns of their own
+        if let NotifyEvent::Alert(_) = event {
+            return Some(0);
+        }
+        match self.last.get(&event) {
+            Some(last) if now.duration_since(*last) < RATE_LIMIT => {
+                *self.suppressed.entry(event).or_default() += 1;
+                None
+            }
+            _ => {
+                self.last.insert(event, now);
+                Some(self.suppressed.remove(&event).unwrap_or(0))
+            }
+        }
+    }
+}
+
+/// Sends the notifications, and recognises the actions invoked on them
+pub struct Notifier {
+    proxy: NotificationsProxy<'static>,
+    limiter: RateLimiter,
+    /// Ids of the most recent notifications, as the service signals the actions of all apps
+    sent: VecDeque<u32>,
+}
+
+impl Notifier {
+    pub async fn new(connection: &Connection) -> zbus::Result<Notifier> {
+        Ok(Notifier {
+            proxy: NotificationsProxy::new(connection).await?,
+            limiter: RateLimiter::default(),
+            sent: VecDeque::new(),
+        })
+    }
+
+    /// Notifies of the event, unless it was notified recently. Returns the id of the notification
+    pub async fn notify(
+        &mut self,
+        event: NotifyEvent,
+        body: &str,
+        now: Instant,
+    ) -> zbus::Result<Option<u32>> {
+        let suppressed = match self.limiter.allow(event, now) {
+            Some(suppressed) => suppressed,
+            None => return Ok(None),
+        };
+        let body = match suppressed {
+            0 => body.to_string(),
+            n => format!("{body}\n{n} more since the last notification"),
+        };
+        let actions: Vec<&str> = NotifyAction::ALL
+            .iter()
+            .flat_map(|action| [action.key(), action.label()])
+            .collect();
+        let mut hints = HashMap::new();
+        hints.insert("urgency", Value::U8(event.urgency()));
+        let id = self
+            .proxy
+            .notify(
+                "ethminer-gui",
+                0,
+                "ethminer-gui",
+                event.summary(),
+                &body,
+                &actions,
+                hints,
+                -1,
+            )
+            .await?;
+        self.sent.push_back(id);
+        if self.sent.len() > MAX_SENT {
+            self.sent.pop_front();
+        }
+        Ok(Some(id))
+    }
+
+    pub async fn receive_actions(&self) -> zbus::Result<ActionInvokedStream<'static>> {
+        self.proxy.receive_action_invoked().await
+    }
+
+    /// The action invoked by a signal, if it is one of the actions of this app's notifications
+    pub fn action(&self, signal: &ActionInvoked) -> Option<NotifyAction> {
+        let args = signal.args().ok()?;
+        if !self.sent.contains(&args.id) {
+            return None;
+        }
+        NotifyAction::from_key(args.action_key)
+    }
+}
+
+/// Notifies on the desktop of the events of the miner, as enabled in the options, see
+/// app_options::follow_events
+pub async fn start(miner: &MinerHandle) {
+    let (events, lines) = match (
+        miner.subscribe_events().await,
+        miner.subscribe_lines().await,
+    ) {
+        (Some(events), Some(lines)) => (events, lines),
+        _ => return,
+    };
+    let errors = miner.error_tx.subscribe();
+    let connected = match Connection::session().await {
+        Ok(connection) => Notifier::new(&connection).await,
+        Err(e) => Err(e),
+    };
+    match connected {
+        Ok(notifier) => app_options::follow_events(miner, |miner, options| {
+            run(notifier, miner, options, events, lines, errors)
+        }),
+        Err(e) => println!("Not sending notifications: {e}"),
+    }
+}
+
+async fn run(
+    mut notifier: Notifier,
+    miner: MinerHandle,
+    mut options: OptionsReader,
+    mut events: broadcast::Receiver<MinerEvent>,
+    mut lines: broadcast::Receiver<LineEvent>,
+    mut errors: broadcast::Receiver<String>,
+) {
+    let mut actions = match notifier.receive_actions().await {
+        Ok(actions) => Some(actions),
+        Err(e) => {
+            println!("Not receiving the actions of notifications: {e}");
+            None
+        }
+    };
+    let mut pool_lost = false;
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Ok(MinerEvent::Crashed) => Some((
+                    NotifyEvent::Crashed,
+                    "ethminer exited unexpectedly".to_string(),
+                )),
+                Ok(MinerEvent::GaveUp) => Some((
+                    NotifyEvent::GaveUp,
+                    "ethminer crashed too often, and isn't restarted anymore".to_string(),
+                )),
+                Ok(MinerEvent::Alert(alert)) => Some((NotifyEvent::Alert(alert.severity), alert.message)),
+                Ok(MinerEvent::Restarted | MinerEvent::Stalled(_) | MinerEvent::SpawnFailed) | Err(RecvError::Lagged(_)) => None,
+                Err(RecvError::Closed) => return,
+            },
+            line = lines.recv() => match line {
+                Ok(LineEvent::Push(line)) => line_event(&StyledLine::parse(&line).text, &mut pool_lost),
+                Ok(LineEvent::Replace(_)) | Err(RecvError::Lagged(_)) => None,
+                Err(RecvError::Closed) => return,
+            },
+            error = errors.recv() => match error {
+                Ok(message) => Some((NotifyEvent::Error, message)),
+                Err(RecvError::Lagged(_)) => None,
+                Err(RecvError::Closed) => return,
+            },
+            Some(signal) = next_action(&mut actions) => {
+                match notifier.action(&signal) {
+                    Some(NotifyAction::Restart) => miner.restart(),
+                    Some(NotifyAction::OpenLog) => open_log_folder(),
+                    None => {}
+                }
+                None
+            }
+        };
+
+        if let Some((event, body)) = event {
+            if !event.enabled(&options.read().notifications) {
+                continue;
+            }
+            if let Err(e) = notifier.notify(event, &body, Instant::now()).await {
+                println!("Could not send a notification: {e}");
+            }
+        }
+    }
+}
+
+/// The event to notify for a line of miner output, if any. Ethminer keeps repeating that it
+/// has no connection, so that is only notified once until it connects again.
+fn line_event(line: &str, pool_lost: &mut bool) -> Option<(NotifyEvent, String)> {
+    match parse_event(line) {
+        Some(OutputEvent::Disconnected) if !*pool_lost => {
+            *pool_lost = true;
+            Some((NotifyEvent::PoolLost, line.trim().to_string()))
+        }
+        Some(OutputEvent::Connected) => {
+            *pool_lost = false;
+            None
+        }
+        Some(OutputEvent::ThermalStop { gpu, temperature }) => {
+            let body = match temperature {
+                Some(temperature) => format!("GPU {gpu} reached {temperature}°C"),
+                None => format!("GPU {gpu} reached the --tstop temperature"),
+            };
+            Some((NotifyEvent::ThermalStop, body))
+        }
+        _ => None,
+    }
+}
+
+/// The next invoked action, or never if actions aren't received
+async fn next_action(actions: &mut Option<ActionInvokedStream<'static>>) -> Option<ActionInvoked> {
+    match actions {
+        Some(actions) => actions.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Stdio};
+    use std::sync::{Arc, Mutex};
+    use zbus::{dbus_interface, ConnectionBuilder, SignalContext};
+
+    const PATH: &str = "/org/freedesktop/Notifications";
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        assert_eq!(limiter.allow(NotifyEvent::Crashed, start), Some(0));
+        assert_eq!(limiter.allow(NotifyEvent::Crashed, start), None);
+        // Events are limited separately
+        assert_eq!(limiter.allow(NotifyEvent::PoolLost, start), Some(0));
+
+        let later = start + Duration::from_secs(60);
+        assert_eq!(limiter.allow(NotifyEvent::Crashed, later), None);
+        let after_limit = start + RATE_LIMIT;
+        assert_eq!(limiter.allow(NotifyEvent::Crashed, after_limit), Some(2));
+        assert_eq!(
+            limiter.allow(NotifyEvent::Crashed, after_limit + RATE_LIMIT),
+            Some(0)
+        );
+
+        let alert = NotifyEvent::Alert(Severity::Warning);
+        assert_eq!(limiter.allow(alert, start), Some(0));
+        assert_eq!(limiter.allow(alert, start), Some(0));
+    }
+
+    #[test]
+    fn test_line_events() {
+        let log = " X 14:01:50 ethminer No connection. Suspend mining ...
+ X 14:01:55 ethminer No connection. Suspend mining ...
+ i 14:02:01 ethminer Established connection to eu1.ethermine.org [1.2.3.4:4444]
+ X 14:02:11 ethminer Pause mining on gpu0 : temperature 86 is equal/above --tstop 85
+ i 14:05:41 ethminer Resume mining on gpu0 : temperature 55 is equal/below --tstart 55
+ X 14:09:30 ethminer No connection. Suspend mining ...";
+        let mut pool_lost = false;
+        let events: Vec<(NotifyEvent, String)> = log
+            .lines()
+            .filter_map(|line| line_event(line, &mut pool_lost))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (
+                    NotifyEvent::PoolLost,
+                    "X 14:01:50 ethminer No connection. Suspend mining ...".to_string()
+                ),
+                (NotifyEvent::ThermalStop, "GPU 0 reached 86°C".to_string()),
+                (
+                    NotifyEvent::PoolLost,
+                    "X 14:09:30 ethminer No connection. Suspend mining ...".to_string()
+                ),
+            ]
+        );
+        let options = NotificationOptions {
+            thermal_stop: false,
+            ..Default::default()
+        };
+        assert!(!NotifyEvent::ThermalStop.enabled(&options));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Received {
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+    }
+
+    /// The notification service of a desktop, recording the notifications it receives
+    #[derive(Default)]
+    struct MockNotifications {
+        received: Arc<Mutex<Vec<Received>>>,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.Notifications")]
+    impl MockNotifications {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &mut self,
+            _app_name: &str,
+            _replaces_id: u32,
+            _app_icon: &str,
+            summary: &str,
+            body: &str,
+            actions: Vec<String>,
+            _hints: HashMap<String, zbus::zvariant::OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            let mut received = self.received.lock().unwrap();
+            received.push(Received {
+                summary: summary.to_string(),
+                body: body.to_string(),
+                actions,
+            });
+            received.len() as u32
+        }
+
+        #[dbus_interface(signal)]
+        async fn action_invoked(
+            ctxt: &SignalContext<'_>,
+            id: u32,
+            action_key: &str,
+        ) -> zbus::Result<()>;
+    }
+
+    /// A session bus of its own, so the test doesn't need or disturb a desktop
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> PrivateBus {
+            let mut daemon = std::process::Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon is needed to run this test");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            PrivateBus {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Needs dbus-daemon, run with `cargo test private_bus -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_notifications_on_private_bus() {
+        let bus = PrivateBus::start();
+        let mock = MockNotifications::default();
+        let received = mock.received.clone();
+        let service = ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.freedesktop.Notifications")
+            .unwrap()
+            .serve_at(PATH, mock)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let mut notifier = Notifier::new(&client).await.unwrap();
+        let mut actions = notifier.receive_actions().await.unwrap();
+        let now = Instant::now();
+        let id = notifier
+            .notify(NotifyEvent::Crashed, "exited with 1", now)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            notifier
+                .notify(NotifyEvent::Crashed, "exited with 1", now)
+                .await
+                .unwrap(),
+            None
+        );
+        notifier
+            .notify(NotifyEvent::PoolLost, "No connection", now)
+            .await
+            .unwrap()
+            .unwrap();
+        notifier
+            .notify(NotifyEvent::Crashed, "exited again", now + RATE_LIMIT)
+            .await
+            .unwrap()
+            .unwrap();
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 3);
+            assert_eq!(
+                received[0],
+                Received {
+                    summary: "The miner crashed".to_string(),
+                    body: "exited with 1".to_string(),
+                    actions: ["restart", "Restart", "open-log", "Open log"]
+                        .map(String::from)
+                        .to_vec(),
+                }
+            );
+            assert_eq!(
+                received[1].summary,
+                "The miner lost the connection to the pool"
+            );
+            assert_eq!(
+                received[2].body,
+                "exited again\n1 more since the last notification"
+            );
+        }
+
+        // Actions of the notifications of other apps are ignored
+        let iface = service
+            .object_server()
+            .interface::<_, MockNotifications>(PATH)
+            .await
+            .unwrap();
+        let ctxt = iface.signal_context();
+        MockNotifications::action_invoked(ctxt, 99, "restart")
+            .await
+            .unwrap();
+        MockNotifications::action_invoked(ctxt, id, "open-log")
+            .await
+            .unwrap();
+        MockNotifications::action_invoked(ctxt, id, "restart")
+            .await
+            .unwrap();
+        let mut invoked = Vec::new();
+        for _ in 0..3 {
+            let signal = tokio::time::timeout(Duration::from_secs(5), actions.next())
+                .await
+                .unwrap()
+                .unwrap();
+            invoked.push(notifier.action(&signal));
+        }
+        assert_eq!(
+            invoked,
+            [
+                None,
+                Some(NotifyAction::OpenLog),
+                Some(NotifyAction::Restart)
+            ]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use crate::ansi::StyledLine;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Ring buffer holding the most recent lines of miner output.
+/// Lines are stored behind an Arc, so readers can take a copy of the
+/// buffer without cloning any text, and only ever need to hold the lock
+/// for as long as it takes to copy the lines they have not seen yet.
+pub struct OutputBuffer {
+    lines: VecDeque<Arc<StyledLine>>,
+    capacity: usize,
+    /// Total number of lines ever pushed, used to work out which lines a snapshot is missing
+    pushed: u64,
+    /// Incremented on every change, so readers can cheaply tell if anything changed
+    generation: u64,
+}
+
+impl Default for OutputBuffer {
+    fn default() -> Self {
+        Self::new(OutputBuffer::DEFAULT_CAPACITY)
+    }
+}
+
+impl OutputBuffer {
+    pub const DEFAULT_CAPACITY: usize = 5000;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::with_capacity(capacity.min(OutputBuffer::DEFAULT_CAPACITY)),
+            capacity: capacity.max(1),
+            pushed: 0,
+            generation: 0,
+        }
+    }
+
+    /// Appends a line, dropping the oldest line if the buffer is full
+    pub fn push(&mut self, line: StyledLine) {
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(Arc::new(line));
+        self.pushed += 1;
+        self.generation += 1;
+    }
+
+    /// Replaces the newest line, used when the miner redraws a line
+    pub fn replace_last(&mut self, line: StyledLine) {
+        match self.lines.back_mut() {
+            Some(last) => {
+                *last = Arc::new(line);
+                self.generation += 1;
+            }
+            None => self.push(line),
+        }
+    }
+
+    /// Changes the number of lines kept, dropping the oldest lines if needed
+    pub fn set_capacity(&mut self, capacity: usize) {
+        let capacity = capacity.max(1);
+        if capacity == self.capacity {
+            return;
+        }
+        self.capacity = capacity;
+        while self.lines.len() > capacity {
+            self.lines.pop_front();
+        }
+        self.generation += 1;
+    }
+
+    /// Brings the snapshot up to date with the buffer.
+    /// Only the lines the snapshot hasn't seen are copied, so this is cheap
+    /// when called every frame. Returns true if the snapshot changed.
+    pub fn update_snapshot(&self, snapshot: &mut OutputSnapshot) -> bool {
+        if snapshot.generation == self.generation && snapshot.pushed == self.pushed {
+            return false;
+        }
+        let new_lines = (self.pushed - snapshot.pushed) as usize;
+        if new_lines >= self.lines.len() || snapshot.pushed > self.pushed {
+            // Too far behind, everything the snapshot has was already dropped
+            snapshot.lines = self.lines.clone();
+        } else {
+            // Only the newest line can be replaced, so the last line the snapshot
+            // has is copied again in case it was redrawn
+            let start = self.lines.len() - new_lines;
+            if let (Some(last), Some(start)) = (snapshot.lines.back_mut(), start.checked_sub(1)) {
+                *last = self.lines[start].clone();
+            }
+            snapshot.lines.extend(self.lines.range(start..).cloned());
+            while snapshot.lines.len() > self.lines.len() {
+                snapshot.lines.pop_front();
+            }
+        }
+        snapshot.pushed = self.pushed;
+        snapshot.generation = self.generation;
+        true
+    }
+}
+
+/// A reader's own copy of the OutputBuffer, see OutputBuffer::update_snapshot
+#[derive(Default, Clone)]
+pub struct OutputSnapshot {
+    pub lines: VecDeque<Arc<StyledLine>>,
+    pushed: u64,
+    generation: u64,
+}
+
+impl OutputSnapshot {
+    /// Sequence number of the first line in the snapshot.
+    /// Every line ever pushed has a unique sequence number, which stays valid
+    /// as older lines are dropped from the front of the buffer.
+    pub fn first_seq(&self) -> u64 {
+        self.pushed - self.lines.len() as u64
+    }
+
+    /// Returns the line with the sequence number, if it is still in the snapshot
+    pub fn get(&self, seq: u64) -> Option<&Arc<StyledLine>> {
+        let index = seq.checked_sub(self.first_seq())?;
+        self.lines.get(index as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(snapshot: &OutputSnapshot) -> Vec<&str> {
+        snapshot.lines.iter().map(|l| l.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_ring_buffer_drops_oldest() {
+        let mut buffer = OutputBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(StyledLine::plain(&i.to_string()));
+        }
+        let mut snapshot = OutputSnapshot::default();
+        assert!(buffer.update_snapshot(&mut snapshot));
+        assert_eq!(texts(&snapshot), vec!["2", "3", "4"]);
+        assert_eq!(snapshot.first_seq(), 2);
+        assert_eq!(snapshot.get(4).unwrap().text, "4");
+        assert!(snapshot.get(1).is_none());
+    }
+
+    #[test]
+    fn test_incremental_snapshot() {
+        let mut buffer = OutputBuffer::new(4);
+        let mut snapshot = OutputSnapshot::default();
+        buffer.push(StyledLine::plain("a"));
+        buffer.push(StyledLine::plain("b"));
+        assert!(buffer.update_snapshot(&mut snapshot));
+        assert!(!buffer.update_snapshot(&mut snapshot));
+
+        let b = snapshot.lines[1].clone();
+        buffer.push(StyledLine::plain("c"));
+        buffer.push(StyledLine::plain("d"));
+        buffer.push(StyledLine::plain("e"));
+        assert!(buffer.update_snapshot(&mut snapshot));
+        assert_eq!(texts(&snapshot), vec!["b", "c", "d", "e"]);
+        // Lines already in the snapshot are shared, not copied
+        assert!(Arc::ptr_eq(&b, &snapshot.lines[0]));
+
+        // Falling further behind than the capacity copies the whole buffer
+        for i in 0..10 {
+            buffer.push(StyledLine::plain(&i.to_string()));
+        }
+        buffer.update_snapshot(&mut snapshot);
+        assert_eq!(texts(&snapshot), vec!["6", "7", "8", "9"]);
+    }
+
+    #[test]
+    fn test_replace_last() {
+        let mut buffer = OutputBuffer::new(3);
+        let mut snapshot = OutputSnapshot::default();
+        buffer.replace_last(StyledLine::plain("DAG 1%"));
+        buffer.update_snapshot(&mut snapshot);
+        buffer.replace_last(StyledLine::plain("DAG 50%"));
+        assert!(buffer.update_snapshot(&mut snapshot));
+        assert_eq!(texts(&snapshot), vec!["DAG 50%"]);
+
+        buffer.replace_last(StyledLine::plain("DAG 100%"));
+        buffer.push(StyledLine::plain("next"));
+        buffer.update_snapshot(&mut snapshot);
+        assert_eq!(texts(&snapshot), vec!["DAG 100%", "next"]);
+    }
+
+    #[test]
+    fn test_set_capacity() {
+        let mut buffer = OutputBuffer::new(10);
+        let mut snapshot = OutputSnapshot::default();
+        for i in 0..6 {
+            buffer.push(StyledLine::plain(&i.to_string()));
+        }
+        buffer.update_snapshot(&mut snapshot);
+        buffer.set_capacity(2);
+        assert!(buffer.update_snapshot(&mut snapshot));
+        assert_eq!(texts(&snapshot), vec!["4", "5"]);
+        assert_eq!(snapshot.first_seq(), 4);
+    }
+}
@@ -0,0 +1,395 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Severity of a line of miner output.
+/// Ethminer starts every line with a short channel tag (" i", " m", "cu", " X", ...),
+/// which together with a few well known keywords is enough to sort lines into
+/// info, warnings and errors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Info,
+    Warn,
+    Error,
+}
+
+/// Returns the channel tag at the start of an ethminer line, if there is one
+/// For example " i 17:29:34 ethminer ..." returns "i"
+pub fn channel_tag(line: &str) -> Option<&str> {
+    let tag = line.split_whitespace().next()?;
+    if tag.len() <= 2 && tag.chars().all(|c| c.is_ascii_alphabetic()) {
+        Some(tag)
+    } else {
+        None
+    }
+}
+
+/// Classifies a line of (ansi stripped) miner output
+pub fn severity(line: &str) -> Severity {
+    let lower = line.to_lowercase();
+    if lower.contains("error")
+        || lower.contains("fatal")
+        || lower.contains("exception")
+        || lower.contains("crashed")
+    {
+        return Severity::Error;
+    }
+    if channel_tag(line) == Some("X")
+        || lower.contains("warn")
+        || lower.contains("**rejected")
+        || lower.contains("no connection")
+        || lower.contains("disconnected")
+    {
+        return Severity::Warn;
+    }
+    Severity::Info
+}
+
+/// Returns the host of the pool a line reports connecting to
+pub fn connected_pool(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("Established connection to ")?;
+    rest.split_whitespace().next()
+}
+
+/// Returns true if the line reports a found, accepted or rejected share
+pub fn is_share_event(line: &str) -> bool {
+    line.contains("**Accepted") || line.contains("**Rejected") || line.contains("Sol: 0x")
+}
+
+fn gpu_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // Matches "cuda-0", "cl-1", "gpu/2", "gpu-3", and the short forms "cu0", "cl1", "gpu2"
+    // used in the hashrate summary lines
+    RE.get_or_init(|| Regex::new(r"\b(?:cuda|cu|cl|gpu)[-/]?(\d{1,2})\b").unwrap())
+}
+
+/// Returns the indices of the GPUs mentioned in the line
+pub fn gpu_indices(line: &str) -> Vec<usize> {
+    let mut out: Vec<usize> = gpu_regex()
+        .captures_iter(line)
+        .filter_map(|c| c[1].parse().ok())
+        .collect();
+    out.sort_unstable();
+    out.dedup();
+    out
+}
+
+/// Hashrate and sensor readings of one GPU, from a hashrate summary line
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GpuStatus {
+    pub index: usize,
+    /// Hashes per second
+    pub hashrate: f64,
+    /// Degrees celsius, only reported when ethminer runs with --HWMON
+    pub temperature: Option<u32>,
+    /// Fan speed percentage, only reported when ethminer runs with --HWMON
+    pub fan: Option<u32>,
+}
+
+/// Something that happened according to a line of miner output
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputEvent {
+    /// A periodic hashrate summary, in hashes per second
+    Hashrate {
+        total: f64,
+        gpus: Vec<GpuStatus>,
+    },
+    Accepted {
+        stale: bool,
+    },
+    Rejected {
+        stale: bool,
+    },
+    /// A connection to the pool was established
+    Connected,
+    /// The connection to the pool was lost
+    Disconnected,
+    /// The pool sent a new job to work on, with its block number if the pool gives it
+    NewJob {
+        block: Option<u64>,
+    },
+    /// Mining moved to a new epoch, with a new DAG
+    Epoch(u64),
+    /// A GPU stopped mining as it reached the temperature given with --tstop
+    ThermalStop {
+        gpu: usize,
+        temperature: Option<u32>,
+    },
+    /// A GPU stopped for its temperature cooled down to --tstart, and mines again
+    ThermalResume {
+        gpu: usize,
+    },
+}
+
+fn hashrate_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // "0:01 A3:R1 59.20 Mh - cu0 ..." since ethminer 0.16, "Speed 59.20 Mh/s gpu/0 ..." before
+    RE.get_or_init(|| {
+        Regex::new(r"(?:\bA\d+\S*\s+|Speed\s+)(\d+(?:\.\d+)?)\s*([kKMGT]?)h(?:/s)?\b").unwrap()
+    })
+}
+
+fn gpu_status_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"\b(?:cu|cl|cp|gpu/?)(\d{1,2})\s+(\d+(?:\.\d+)?)(?:\s+(\d+)C)?(?:\s+(\d+)%)?")
+            .unwrap()
+    })
+}
+
+fn unit_multiplier(unit: &str) -> f64 {
+    match unit {
+        "k" | "K" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        _ => 1.0,
+    }
+}
+
+/// The number following the text in the line, e.g. the block number after "block "
+fn number_after(line: &str, text: &str) -> Option<u64> {
+    let (_, rest) = line.split_once(text)?;
+    let digits = rest.split(|c: char| !c.is_ascii_digit()).next()?;
+    digits.parse().ok()
+}
+
+/// Recognises the lines of miner output that the statistics are built from
+pub fn parse_event(line: &str) -> Option<OutputEvent> {
+    if line.contains("**Accepted") {
+        return Some(OutputEvent::Accepted {
+            stale: line.contains("stale"),
+        });
+    }
+    if line.contains("**Rejected") {
+        return Some(OutputEvent::Rejected {
+            stale: line.contains("stale"),
+        });
+    }
+    if line.contains("Established connection") {
+        return Some(OutputEvent::Connected);
+    }
+    if line.contains("Disconnected from") || line.contains("No connection") {
+        return Some(OutputEvent::Disconnected);
+    }
+    // "Job: 8a4e7c3b… block 8593242 eu1.ethermine.org:4444" since ethminer 0.16, on the info
+    // channel as the warnings about late solutions mention the job too
+    if (line.contains("Job: ") && channel_tag(line) == Some("i"))
+        || line.contains("Received new job")
+    {
+        let block = number_after(line, "block ");
+        return Some(OutputEvent::NewJob { block });
+    }
+    // "Pause mining on gpu0 : temperature 86 is equal/above --tstop 85", and "Resume mining on
+    // gpu0 : temperature 55 is equal/below --tstart 55" once it cooled down
+    if line.contains("--tstop") || line.contains("--tstart") {
+        let gpu = number_after(line, "mining on gpu")? as usize;
+        if line.contains("--tstart") {
+            return Some(OutputEvent::ThermalResume { gpu });
+        }
+        let temperature = number_after(line, "temperature ").map(|t| t as u32);
+        return Some(OutputEvent::ThermalStop { gpu, temperature });
+    }
+    // "Epoch : 430 Difficulty : 4.00 Gh"
+    if let Some(epoch) = number_after(line, "Epoch : ") {
+        return Some(OutputEvent::Epoch(epoch));
+    }
+    if channel_tag(line) == Some("m") {
+        let caps = hashrate_regex().captures(line)?;
+        let multiplier = unit_multiplier(&caps[2]);
+        let total = caps[1].parse::<f64>().ok()? * multiplier;
+        // The per GPU figures follow the total
+        let rest = &line[caps.get(0).unwrap().end()..];
+        let gpus = gpu_status_regex()
+            .captures_iter(rest)
+            .filter_map(|c| {
+                Some(GpuStatus {
+                    index: c[1].parse().ok()?,
+                    hashrate: c[2].parse::<f64>().ok()? * multiplier,
+                    temperature: c.get(3).and_then(|t| t.as_str().parse().ok()),
+                    fan: c.get(4).and_then(|f| f.as_str().parse().ok()),
+                })
+            })
+            .collect();
+        return Some(OutputEvent::Hashrate { total, gpus });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_severity() {
+        assert_eq!(
+            severity(" i 17:29:36 ethminer Established connection to eu1.ethermine.org"),
+            Severity::Info
+        );
+        assert_eq!(
+            severity(" X 17:31:02 ethminer Job: 8a4e7c3b… submitted too late"),
+            Severity::Warn
+        );
+        assert_eq!(
+            severity(" i 17:30:06 ethminer **Rejected  48 ms. eu1.ethermine.org:4444"),
+            Severity::Warn
+        );
+        assert_eq!(
+            severity("cu 17:29:40 cuda-0   CUDA error in func ethash_generate_dag: out of memory"),
+            Severity::Error
+        );
+        assert_eq!(severity("***** Miner Crashed! *****"), Severity::Error);
+    }
+
+    #[test]
+    fn test_share_events() {
+        assert!(is_share_event(
+            " i 17:30:06 ethminer **Accepted  48 ms. eu1.ethermine.org:4444"
+        ));
+        assert!(is_share_event(
+            "cu 17:30:06 cuda-0   Job: 1b4f5a6c… Sol: 0x5b2c3d4e5f607182"
+        ));
+        assert!(!is_share_event(
+            " m 17:29:50 ethminer 0:00 A0 29.47 Mh - cu0 29.47"
+        ));
+    }
+
+    #[test]
+    fn test_gpu_indices() {
+        assert_eq!(gpu_indices("cu 17:29:40 cuda-1   Generating DAG"), vec![1]);
+        assert_eq!(
+            gpu_indices(
+                " m 17:29:50 ethminer 0:01 A3 59.20 Mh - cu0 29.47 56C 58%, cu1 29.73 61C 63%"
+            ),
+            vec![0, 1]
+        );
+        assert_eq!(gpu_indices("cl 17:29:40 cl-2     Using device"), vec![2]);
+        assert!(gpu_indices(" i 17:29:36 ethminer Selected pool").is_empty());
+    }
+
+    #[test]
+    fn test_parse_hashrate() {
+        let event = parse_event(
+            " m 17:29:50 ethminer 0:01 A3:R1 59.20 Mh - cu0 29.47 56C 58%, cu1 29.73 61C 63%",
+        );
+        assert_eq!(
+            event,
+            Some(OutputEvent::Hashrate {
+                total: 59.2e6,
+                gpus: vec![
+                    GpuStatus {
+                        index: 0,
+                        hashrate: 29.47e6,
+                        temperature: Some(56),
+                        fan: Some(58)
+                    },
+                    GpuStatus {
+                        index: 1,
+                        hashrate: 29.73e6,
+                        temperature: Some(61),
+                        fan: Some(63)
+                    },
+                ]
+            })
+        );
+
+        // Ethminer before 0.16
+        let event = parse_event(
+            " m 17:29:50|ethminer|  Speed 29.51 Mh/s    gpu/0 29.51  [A1+0:R0+0:F0] Time: 00:01",
+        );
+        assert_eq!(
+            event,
+            Some(OutputEvent::Hashrate {
+                total: 29.51e6,
+                gpus: vec![GpuStatus {
+                    index: 0,
+                    hashrate: 29.51e6,
+                    temperature: None,
+                    fan: None
+                }]
+            })
+        );
+
+        // While the DAG is generated the hashrate is reported in h
+        let event = parse_event(" m 17:29:45 ethminer 0:00 A0 0.00 h - cu0 0.00");
+        assert!(matches!(event, Some(OutputEvent::Hashrate { total, .. }) if total == 0.0));
+    }
+
+    #[test]
+    fn test_parse_share_and_connection_events() {
+        assert_eq!(
+            parse_event(" i 17:30:06 ethminer **Accepted  48 ms. eu1.ethermine.org:4444"),
+            Some(OutputEvent::Accepted { stale: false })
+        );
+        assert_eq!(
+            parse_event(" i 17:30:06 ethminer **Accepted stale 210 ms. eu1.ethermine.org:4444"),
+            Some(OutputEvent::Accepted { stale: true })
+        );
+        assert_eq!(
+            parse_event(" X 17:30:07 ethminer **Rejected  48 ms. eu1.ethermine.org:4444"),
+            Some(OutputEvent::Rejected { stale: false })
+        );
+        assert_eq!(
+            parse_event(
+                " i 17:29:36 ethminer Established connection to eu1.ethermine.org [1.2.3.4:4444]"
+            ),
+            Some(OutputEvent::Connected)
+        );
+        assert_eq!(
+            parse_event(" X 17:40:02 ethminer No connection. Suspend mining ..."),
+            Some(OutputEvent::Disconnected)
+        );
+        assert_eq!(
+            parse_event(" i 17:29:37 ethminer Job: 8a4e7c3b… block 8593242 eu1.ethermine.org:4444"),
+            Some(OutputEvent::NewJob {
+                block: Some(8593242)
+            })
+        );
+        assert_eq!(
+            parse_event(
+                "  m  15:37:46|stratum  Received new job #8a4e7c3b from eu1.ethermine.org:4444"
+            ),
+            Some(OutputEvent::NewJob { block: None })
+        );
+        assert_eq!(
+            parse_event(" i 11:02:14 ethminer Epoch : 430 Difficulty : 4.00 Gh"),
+            Some(OutputEvent::Epoch(430))
+        );
+        assert_eq!(
+            parse_event(" X 17:31:02 ethminer Job: 8a4e7c3b… submitted too late"),
+            None
+        );
+        assert_eq!(parse_event(" i 17:29:36 ethminer Selected pool"), None);
+    }
+
+    #[test]
+    fn test_parse_thermal_events() {
+        let log = " m 14:02:06 ethminer 2:11 A112 61.02 Mh - cu0 30.51 84C 90% cu1 30.51 79C 85%
+ X 14:02:11 ethminer Pause mining on gpu0 : temperature 86 is equal/above --tstop 85
+ m 14:02:16 ethminer 2:11 A112 30.51 Mh - cu0 0.00 86C 100% cu1 30.51 79C 85%
+ i 14:05:41 ethminer Resume mining on gpu0 : temperature 55 is equal/below --tstart 55";
+        let events: Vec<OutputEvent> = log.lines().filter_map(parse_event).collect();
+        assert_eq!(
+            events[1],
+            OutputEvent::ThermalStop {
+                gpu: 0,
+                temperature: Some(86)
+            }
+        );
+        assert_eq!(events[3], OutputEvent::ThermalResume { gpu: 0 });
+        assert_eq!(events.len(), 4);
+    }
+
+    #[test]
+    fn test_connected_pool() {
+        assert_eq!(
+            connected_pool(
+                " i 17:29:36 ethminer Established connection to eu1.ethermine.org [1.2.3.4:4444]"
+            ),
+            Some("eu1.ethermine.org")
+        );
+        assert_eq!(
+            connected_pool(" X 17:40:02 ethminer No connection. Suspend mining ..."),
+            None
+        );
+    }
+}
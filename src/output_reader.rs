@@ -0,0 +1,307 @@
+use crate::ansi::StyledLine;
+use crate::output_buffer::OutputBuffer;
+use crate::stats::MinerStats;
+
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::broadcast;
+use tokio::time::{sleep, Duration};
+
+/// Number of read errors in a row after which the reader gives up
+const MAX_READ_ERRORS: u32 = 5;
+
+/// A change to the output produced by LineSplitter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineEvent {
+    /// A new line
+    Push(String),
+    /// The last line was redrawn with a carriage return, and should be replaced
+    Replace(String),
+}
+
+/// Splits raw miner output into lines.
+/// Invalid UTF-8 is decoded lossily instead of failing, and a lone '\r'
+/// redraws the current line like it would in a terminal, which ethminer
+/// uses for progress lines.
+#[derive(Default)]
+pub struct LineSplitter {
+    /// Bytes of the line being read
+    current: Vec<u8>,
+    /// True if the line being read has already been emitted once, ending with '\r'
+    current_emitted: bool,
+    /// The last byte seen was a '\r', it is only known to be a
+    /// redraw once it's clear that it isn't followed by '\n'
+    pending_cr: bool,
+}
+
+impl LineSplitter {
+    /// Feeds a chunk of output, returning the changes to the output
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<LineEvent> {
+        let mut out = Vec::new();
+        for &byte in bytes {
+            if self.pending_cr {
+                self.pending_cr = false;
+                if byte != b'\n' {
+                    // A redraw of the current line
+                    out.push(self.emit());
+                    self.current_emitted = true;
+                }
+            }
+            match byte {
+                b'\r' => self.pending_cr = true,
+                b'\n' => {
+                    if !self.current_emitted || !self.current.is_empty() {
+                        out.push(self.emit());
+                    }
+                    self.current_emitted = false;
+                }
+                _ => self.current.push(byte),
+            }
+        }
+        out
+    }
+
+    /// Flushes whatever is left once the output has ended
+    pub fn finish(&mut self) -> Option<LineEvent> {
+        self.pending_cr = false;
+        if self.current.is_empty() {
+            return None;
+        }
+        let event = self.emit();
+        self.current_emitted = false;
+        Some(event)
+    }
+
+    fn emit(&mut self) -> LineEvent {
+        let line = String::from_utf8_lossy(&self.current).into_owned();
+        self.current.clear();
+        if self.current_emitted {
+            LineEvent::Replace(line)
+        } else {
+            LineEvent::Push(line)
+        }
+    }
+}
+
+/// Reads the output of the miner into the buffer until the output ends,
+/// updating the statistics from each complete line.
+/// Every change is also sent on lines_tx, with the escape sequences left in.
+/// Read errors are reported in the output and retried, until too many happen in a row.
+pub async fn read_output<R: AsyncRead + Unpin>(
+    mut reader: R,
+    buffer: Arc<std::sync::Mutex<OutputBuffer>>,
+    stats: Arc<std::sync::Mutex<MinerStats>>,
+    lines_tx: broadcast::Sender<LineEvent>,
+    updated_tx: broadcast::Sender<()>,
+) {
+    let mut splitter = LineSplitter::default();
+    let mut chunk = vec![0u8; 4096];
+    let mut errors = 0;
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(n) => {
+                errors = 0;
+                let events = splitter.feed(&chunk[..n]);
+                apply_events(&buffer, &stats, &lines_tx, events);
+            }
+            Err(e) => {
+                errors += 1;
+                println!("Error reading miner output: {e}");
+                let give_up = errors >= MAX_READ_ERRORS;
+                let message = if give_up {
+                    format!("***** Could not read miner output: {e}. Giving up *****")
+                } else {
+                    format!("***** Error reading miner output: {e}. Retrying... *****")
+                };
+                apply_events(&buffer, &stats, &lines_tx, vec![LineEvent::Push(message)]);
+                if give_up {
+                    break;
+                }
+            }
+        }
+        // I don't care if this fails if the rx is not recieving
+        let _ = updated_tx.send(());
+        if errors > 0 {
+            sleep(Duration::from_millis(100 * errors as u64)).await;
+        }
+    }
+    apply_events(
+        &buffer,
+        &stats,
+        &lines_tx,
+        splitter.finish().into_iter().collect(),
+    );
+    let _ = updated_tx.send(());
+}
+
+fn apply_events(
+    buffer: &std::sync::Mutex<OutputBuffer>,
+    stats: &std::sync::Mutex<MinerStats>,
+    lines_tx: &broadcast::Sender<LineEvent>,
+    events: Vec<LineEvent>,
+) {
+    if events.is_empty() {
+        return;
+    }
+    // Lines are sent while holding the buffer lock, so subscribers that copy
+    // the buffer before subscribing never miss or duplicate a line
+    let mut buf = buffer.lock().unwrap();
+    for event in events {
+        match &event {
+            LineEvent::Push(line) => {
+                println!(" > {}", line);
+                let line = StyledLine::parse(line);
+                // Redrawn lines are only progress output, so only new lines are counted
+                stats.lock().unwrap().handle_line(&line.text);
+                buf.push(line);
+            }
+            LineEvent::Replace(line) => buf.replace_last(StyledLine::parse(line)),
+        }
+        let _ = lines_tx.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output_buffer::OutputSnapshot;
+    use std::collections::VecDeque;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Reader that returns each chunk from a separate read call
+    struct ChunkedReader {
+        chunks: VecDeque<io::Result<Vec<u8>>>,
+    }
+
+    impl AsyncRead for ChunkedReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            match self.chunks.pop_front() {
+                Some(Ok(chunk)) => {
+                    buf.put_slice(&chunk);
+                    Poll::Ready(Ok(()))
+                }
+                Some(Err(e)) => Poll::Ready(Err(e)),
+                None => Poll::Ready(Ok(())),
+            }
+        }
+    }
+
+    async fn read_all(chunks: Vec<io::Result<Vec<u8>>>) -> Vec<String> {
+        let buffer = Arc::new(std::sync::Mutex::new(OutputBuffer::default()));
+        let stats = Arc::new(std::sync::Mutex::new(MinerStats::default()));
+        let (lines_tx, _) = broadcast::channel(16);
+        let (updated_tx, _) = broadcast::channel(2);
+        let reader = ChunkedReader {
+            chunks: chunks.into(),
+        };
+        read_output(reader, buffer.clone(), stats, lines_tx, updated_tx).await;
+        let mut snapshot = OutputSnapshot::default();
+        buffer.lock().unwrap().update_snapshot(&mut snapshot);
+        snapshot.lines.iter().map(|l| l.text.clone()).collect()
+    }
+
+    fn split_all(chunks: &[&[u8]]) -> Vec<LineEvent> {
+        let mut splitter = LineSplitter::default();
+        let mut out: Vec<LineEvent> = chunks.iter().flat_map(|c| splitter.feed(c)).collect();
+        out.extend(splitter.finish());
+        out
+    }
+
+    #[test]
+    fn test_split_lines() {
+        assert_eq!(
+            split_all(&[b"one\ntwo\r\nthr", b"ee"]),
+            vec![
+                LineEvent::Push("one".into()),
+                LineEvent::Push("two".into()),
+                LineEvent::Push("three".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_carriage_return_redraws_line() {
+        assert_eq!(
+            split_all(&[b"DAG 10%\rDAG 50%\r", b"DAG 100%\nnext\n"]),
+            vec![
+                LineEvent::Push("DAG 10%".into()),
+                LineEvent::Replace("DAG 50%".into()),
+                LineEvent::Replace("DAG 100%".into()),
+                LineEvent::Push("next".into()),
+            ]
+        );
+        // "\r\n" split across two chunks is still a single line ending
+        assert_eq!(
+            split_all(&[b"done\r", b"\nnext\n"]),
+            vec![
+                LineEvent::Push("done".into()),
+                LineEvent::Push("next".into())
+            ]
+        );
+        // A redraw followed by a newline doesn't add an empty line
+        assert_eq!(
+            split_all(&[b"50%\r\r\n"]),
+            vec![LineEvent::Push("50%".into())]
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        // A multi-byte character split between chunks is still decoded
+        assert_eq!(
+            split_all(&[b"Job: 8a4e\xe2\x80", b"\xa6 ok\n\xff\xfe bad\n"]),
+            vec![
+                LineEvent::Push("Job: 8a4e… ok".into()),
+                LineEvent::Push("\u{fffd}\u{fffd} bad".into())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reader_fills_buffer() {
+        let lines = read_all(vec![
+            Ok(b"\x1b[34m i\x1b[0m 17:29:36 ethminer Selected pool\n".to_vec()),
+            Ok(b"cu 17:29:40 cuda-0   Generating DAG 1%\r".to_vec()),
+            Ok(b"cu 17:29:41 cuda-0   Generating DAG 100%\n\xc3".to_vec()),
+            Ok(b"\x28 garbage\n".to_vec()),
+        ])
+        .await;
+        assert_eq!(
+            lines,
+            vec![
+                " i 17:29:36 ethminer Selected pool",
+                "cu 17:29:41 cuda-0   Generating DAG 100%",
+                "\u{fffd}( garbage",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reader_recovers_from_errors() {
+        let lines = read_all(vec![
+            Ok(b"before\n".to_vec()),
+            Err(io::Error::other("oops")),
+            Ok(b"after\n".to_vec()),
+        ])
+        .await;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "before");
+        assert!(lines[1].contains("Error reading miner output: oops"));
+        assert_eq!(lines[2], "after");
+
+        let errors = (0..MAX_READ_ERRORS)
+            .map(|_| Err(io::Error::other("broken pipe")))
+            .chain([Ok(b"never read\n".to_vec())])
+            .collect();
+        let lines = read_all(errors).await;
+        assert!(lines.last().unwrap().contains("Giving up"));
+        assert!(!lines.contains(&"never read".to_string()));
+    }
+}
@@ -0,0 +1,521 @@
+use crate::ansi::StyledLine;
+use crate::output_buffer::{OutputBuffer, OutputSnapshot};
+use crate::output_parser::{gpu_indices, is_share_event, severity, Severity};
+
+use eframe::egui;
+use egui::text::{LayoutJob, TextFormat};
+use regex::{Regex, RegexBuilder};
+use std::collections::{BTreeSet, VecDeque};
+use std::ops::Range;
+
+/// Decides which lines of the miner output are shown, and what is highlighted
+pub struct OutputFilter {
+    /// The incremental search query
+    pub query: String,
+    /// Treat the query as a regular expression instead of plain text
+    pub use_regex: bool,
+    pub show_info: bool,
+    pub show_warn: bool,
+    pub show_error: bool,
+    /// Only show lines about found, accepted or rejected shares
+    pub shares_only: bool,
+    /// Only show lines that mention this GPU
+    pub gpu: Option<usize>,
+    /// The compiled search, cached together with the query and mode it was built from
+    compiled: Option<(String, bool, Result<Regex, String>)>,
+}
+
+impl Default for OutputFilter {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            use_regex: false,
+            show_info: true,
+            show_warn: true,
+            show_error: true,
+            shares_only: false,
+            gpu: None,
+            compiled: None,
+        }
+    }
+}
+
+impl OutputFilter {
+    /// Recompiles the search if the query or mode changed since the last call
+    fn update_search(&mut self) {
+        let stale = match &self.compiled {
+            Some((query, use_regex, _)) => query != &self.query || *use_regex != self.use_regex,
+            None => true,
+        };
+        if stale {
+            let pattern = if self.use_regex {
+                self.query.clone()
+            } else {
+                regex::escape(&self.query)
+            };
+            let compiled = RegexBuilder::new(&pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| e.to_string());
+            self.compiled = Some((self.query.clone(), self.use_regex, compiled));
+        }
+    }
+
+    fn search(&self) -> Option<&Regex> {
+        if self.query.is_empty() {
+            return None;
+        }
+        match &self.compiled {
+            Some((_, _, Ok(re))) => Some(re),
+            _ => None,
+        }
+    }
+
+    /// Returns the error message if the query is not a valid regex
+    pub fn search_error(&self) -> Option<&str> {
+        match &self.compiled {
+            Some((_, _, Err(e))) if !self.query.is_empty() => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the line passes the severity, share and GPU filters
+    pub fn is_visible(&self, line: &str) -> bool {
+        let severity_shown = match severity(line) {
+            Severity::Info => self.show_info,
+            Severity::Warn => self.show_warn,
+            Severity::Error => self.show_error,
+        };
+        if !severity_shown {
+            return false;
+        }
+        if self.shares_only && !is_share_event(line) {
+            return false;
+        }
+        match self.gpu {
+            Some(gpu) => gpu_indices(line).contains(&gpu),
+            None => true,
+        }
+    }
+
+    /// Returns true if the search query matches somewhere in the line
+    pub fn has_match(&self, line: &str) -> bool {
+        self.search().is_some_and(|re| re.is_match(line))
+    }
+
+    fn key(&self) -> FilterKey {
+        (
+            self.query.clone(),
+            self.use_regex,
+            self.show_info,
+            self.show_warn,
+            self.show_error,
+            self.shares_only,
+            self.gpu,
+        )
+    }
+
+    /// Returns the byte ranges of the line that match the search query
+    pub fn find_matches(&self, line: &str) -> Vec<Range<usize>> {
+        match self.search() {
+            Some(re) => re
+                .find_iter(line)
+                .filter(|m| !m.range().is_empty())
+                .map(|m| m.range())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The parts of the filter that decide which lines are visible and matching.
+/// When any of them change the visible lines are worked out again.
+type FilterKey = (String, bool, bool, bool, bool, bool, Option<usize>);
+
+/// The filterable, searchable view of the miner output.
+/// Lines are run through the filters once when they arrive, and only the
+/// rows inside the scroll area are laid out, so the cost of a frame
+/// doesn't depend on how many lines are buffered.
+#[derive(Default)]
+pub struct OutputView {
+    pub filter: OutputFilter,
+    /// When set the view stops sticking to the newest line
+    pub pause_scroll: bool,
+    /// Local copy of the output buffer, painting never touches the shared buffer
+    snapshot: OutputSnapshot,
+    /// Sequence numbers of the lines that pass the filters
+    visible: VecDeque<u64>,
+    /// Sequence numbers of the visible lines that match the search
+    matching: VecDeque<u64>,
+    /// Lines with a sequence number below this have been run through the filters
+    filtered_to: u64,
+    /// The filter settings that visible and matching were computed with
+    filter_key: Option<FilterKey>,
+    /// Index into matching of the selected match
+    current_match: usize,
+    /// Set when the selected match changed and should be scrolled into view
+    scroll_to_match: bool,
+    /// Every GPU index seen in the output, offered by the GPU filter
+    known_gpus: BTreeSet<usize>,
+}
+
+impl OutputView {
+    pub fn show(&mut self, ui: &mut egui::Ui, buffer: &std::sync::Mutex<OutputBuffer>) {
+        self.show_controls(ui);
+        self.refresh(buffer);
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Body);
+        let mut area = egui::ScrollArea::both().auto_shrink([false; 2]);
+        if self.scroll_to_match {
+            let selected = self.matching.get(self.current_match);
+            if let Some(row) = selected.and_then(|seq| self.visible.binary_search(seq).ok()) {
+                let row_spacing = row_height + ui.spacing().item_spacing.y;
+                let offset = row as f32 * row_spacing - ui.available_height() / 2.0;
+                area = area.vertical_scroll_offset(offset.max(0.0));
+            }
+            self.scroll_to_match = false;
+        } else if !self.pause_scroll {
+            area = area.stick_to_bottom();
+        }
+
+        let visible = &self.visible;
+        let snapshot = &self.snapshot;
+        let filter = &self.filter;
+        let selected = self.matching.get(self.current_match).copied();
+        area.show_rows(ui, row_height, visible.len(), |ui, rows| {
+            for row in rows {
+                let seq = visible[row];
+                if let Some(line) = snapshot.get(seq) {
+                    let matches = filter.find_matches(&line.text);
+                    let job = line_job(ui, line, &matches, selected == Some(seq));
+                    ui.add(egui::Label::new(job).wrap(false));
+                }
+            }
+        });
+    }
+
+    /// Copies new lines from the shared buffer and runs them through the filters.
+    /// The buffer is only locked while the new lines are copied.
+    fn refresh(&mut self, buffer: &std::sync::Mutex<OutputBuffer>) {
+        buffer.lock().unwrap().update_snapshot(&mut self.snapshot);
+        self.filter.update_search();
+
+        let key = self.filter.key();
+        if self.filter_key.as_ref() != Some(&key) {
+            self.visible.clear();
+            self.matching.clear();
+            self.filtered_to = 0;
+            self.current_match = 0;
+            self.filter_key = Some(key);
+        }
+
+        // Forgets lines that were dropped from the buffer
+        let first = self.snapshot.first_seq();
+        while self.visible.front().is_some_and(|seq| *seq < first) {
+            self.visible.pop_front();
+        }
+        while self.matching.front().is_some_and(|seq| *seq < first) {
+            self.matching.pop_front();
+            self.current_match = self.current_match.saturating_sub(1);
+        }
+
+        // The last filtered line is filtered again, in case it was redrawn
+        let start = self.filtered_to.saturating_sub(1).max(first);
+        while self.visible.back().is_some_and(|seq| *seq >= start) {
+            self.visible.pop_back();
+        }
+        while self.matching.back().is_some_and(|seq| *seq >= start) {
+            self.matching.pop_back();
+        }
+
+        let end = first + self.snapshot.lines.len() as u64;
+        for seq in start..end {
+            let line = &self.snapshot.lines[(seq - first) as usize];
+            self.known_gpus.extend(gpu_indices(&line.text));
+            if self.filter.is_visible(&line.text) {
+                self.visible.push_back(seq);
+                if self.filter.has_match(&line.text) {
+                    self.matching.push_back(seq);
+                }
+            }
+        }
+        self.filtered_to = end;
+        if self.current_match >= self.matching.len() {
+            self.current_match = 0;
+        }
+    }
+
+    /// Shows the search box and filter options
+    fn show_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let search = ui.add(
+                egui::TextEdit::singleline(&mut self.filter.query)
+                    .hint_text("Search")
+                    .desired_width(160.0),
+            );
+            // Enter jumps to the next match, keeping focus in the search box
+            if search.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                self.select_match(1);
+                search.request_focus();
+            }
+            ui.checkbox(&mut self.filter.use_regex, "Regex");
+            if ui.small_button("Prev").on_hover_text("Previous match").clicked() {
+                self.select_match(-1);
+            }
+            if ui.small_button("Next").on_hover_text("Next match").clicked() {
+                self.select_match(1);
+            }
+            if let Some(error) = self.filter.search_error() {
+                ui.colored_label(egui::Color32::RED, "Invalid regex")
+                    .on_hover_text(error);
+            } else if !self.filter.query.is_empty() {
+                if self.matching.is_empty() {
+                    ui.label("No matches");
+                } else {
+                    ui.label(format!("{}/{}", self.current_match + 1, self.matching.len()));
+                }
+            }
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.checkbox(&mut self.filter.show_info, "Info");
+            ui.checkbox(&mut self.filter.show_warn, "Warn");
+            ui.checkbox(&mut self.filter.show_error, "Error");
+            ui.checkbox(&mut self.filter.shares_only, "Shares");
+            let selected = match self.filter.gpu {
+                Some(gpu) => format!("GPU {gpu}"),
+                None => "All GPUs".to_string(),
+            };
+            egui::ComboBox::from_id_source("output_gpu_filter")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.filter.gpu, None, "All GPUs");
+                    for gpu in &self.known_gpus {
+                        ui.selectable_value(&mut self.filter.gpu, Some(*gpu), format!("GPU {gpu}"));
+                    }
+                });
+            ui.checkbox(&mut self.pause_scroll, "Pause scroll");
+            if ui.button("Export").clicked() {
+                self.export();
+            }
+        });
+    }
+
+    /// Saves the plain text of the visible lines to a file chosen by the user
+    fn export(&self) {
+        let path = rfd::FileDialog::new()
+            .set_file_name("ethminer.log")
+            .save_file();
+        if let Some(path) = path {
+            let text: String = self
+                .visible
+                .iter()
+                .filter_map(|seq| self.snapshot.get(*seq))
+                .map(|line| format!("{}\n", line.text))
+                .collect();
+            if let Err(e) = std::fs::write(&path, text) {
+                println!("Could not export output to {:?}: {e}", path);
+            }
+        }
+    }
+
+    /// Moves the selected match forwards or backwards, wrapping around.
+    /// Pauses autoscroll so the view stays on the match.
+    fn select_match(&mut self, step: isize) {
+        if self.matching.is_empty() {
+            return;
+        }
+        let count = self.matching.len() as isize;
+        self.current_match = (self.current_match as isize + step).rem_euclid(count) as usize;
+        self.scroll_to_match = true;
+        self.pause_scroll = true;
+    }
+}
+
+/// Lays out a line with its ANSI colours and the search matches highlighted.
+/// Text without a colour of its own is coloured by the severity of the line.
+fn line_job(ui: &egui::Ui, line: &StyledLine, matches: &[Range<usize>], is_current: bool) -> LayoutJob {
+    let default_color = match severity(&line.text) {
+        Severity::Info => ui.visuals().text_color(),
+        Severity::Warn => egui::Color32::from_rgb(230, 180, 60),
+        Severity::Error => egui::Color32::from_rgb(230, 80, 80),
+    };
+    let font_id = egui::TextStyle::Body.resolve(ui.style());
+    let highlight = if is_current {
+        egui::Color32::from_rgb(255, 150, 50)
+    } else {
+        egui::Color32::from_rgb(230, 220, 110)
+    };
+
+    // The line is cut wherever either the style or the highlighting changes
+    let mut cuts = line.boundaries();
+    for range in matches {
+        cuts.push(range.start);
+        cuts.push(range.end);
+    }
+    cuts.sort_unstable();
+    cuts.dedup();
+
+    let mut job = LayoutJob::default();
+    for cut in cuts.windows(2) {
+        let (start, end) = (cut[0], cut[1]);
+        let style = line.style_at(start);
+        let color = match style.fg {
+            Some(fg) => fg.to_color32(style.bold),
+            None if style.bold => ui.visuals().strong_text_color(),
+            None => default_color,
+        };
+        let mut format = TextFormat {
+            font_id: font_id.clone(),
+            color,
+            background: style
+                .bg
+                .map(|bg| bg.to_color32(false))
+                .unwrap_or(egui::Color32::TRANSPARENT),
+            underline: if style.underline {
+                egui::Stroke::new(1.0, color)
+            } else {
+                egui::Stroke::none()
+            },
+            ..Default::default()
+        };
+        if matches.iter().any(|m| m.contains(&start)) {
+            format.color = egui::Color32::BLACK;
+            format.background = highlight;
+        }
+        job.append(&line.text[start..end], 0.0, format);
+    }
+    job
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_search_is_escaped_and_case_insensitive() {
+        let mut filter = OutputFilter {
+            query: "a0.".to_string(),
+            ..Default::default()
+        };
+        filter.update_search();
+        assert!(filter.find_matches(" m ethminer 0:00 A0 29.47 Mh").is_empty());
+        assert_eq!(filter.find_matches(" m ethminer 0:00 A0. 29.47 Mh"), vec![17..20]);
+    }
+
+    #[test]
+    fn test_regex_search() {
+        let mut filter = OutputFilter {
+            query: r"\d+\.\d+ Mh".to_string(),
+            use_regex: true,
+            ..Default::default()
+        };
+        filter.update_search();
+        assert_eq!(filter.find_matches(" m 0:00 A0 29.47 Mh - cu0 29.47"), vec![11..19]);
+
+        filter.query = "(unclosed".to_string();
+        filter.update_search();
+        assert!(filter.search_error().is_some());
+        assert!(filter.find_matches("(unclosed").is_empty());
+    }
+
+    #[test]
+    fn test_visibility_filters() {
+        let accepted = " i 17:30:06 ethminer **Accepted  48 ms. eu1.ethermine.org:4444";
+        let rejected = " i 17:30:07 ethminer **Rejected  48 ms. eu1.ethermine.org:4444";
+        let dag = "cu 17:29:40 cuda-1   Generating DAG + Light : 4.28 GB";
+
+        let mut filter = OutputFilter {
+            shares_only: true,
+            ..Default::default()
+        };
+        assert!(filter.is_visible(accepted));
+        assert!(!filter.is_visible(dag));
+
+        filter.show_warn = false;
+        assert!(!filter.is_visible(rejected));
+
+        filter = OutputFilter {
+            gpu: Some(1),
+            ..Default::default()
+        };
+        assert!(filter.is_visible(dag));
+        assert!(!filter.is_visible(accepted));
+    }
+
+    #[test]
+    fn test_view_filters_incrementally() {
+        let buffer = std::sync::Mutex::new(OutputBuffer::new(3));
+        let mut view = OutputView::default();
+        view.filter.query = "accepted".to_string();
+        for line in [
+            " i 17:30:06 ethminer **Accepted  48 ms.",
+            " m 17:30:07 ethminer 0:01 A1 29.47 Mh - cu0 29.47",
+            " i 17:30:08 ethminer **Accepted  51 ms.",
+        ] {
+            buffer.lock().unwrap().push(StyledLine::plain(line));
+        }
+        view.refresh(&buffer);
+        assert_eq!(view.visible, vec![0, 1, 2]);
+        assert_eq!(view.matching, vec![0, 2]);
+
+        // The first line drops out of the ring buffer
+        buffer.lock().unwrap().push(StyledLine::plain(" X 17:30:09 ethminer **Rejected"));
+        view.refresh(&buffer);
+        assert_eq!(view.visible, vec![1, 2, 3]);
+        assert_eq!(view.matching, vec![2]);
+
+        view.filter.show_warn = false;
+        view.refresh(&buffer);
+        assert_eq!(view.visible, vec![1, 2]);
+    }
+
+    /// Average time to draw a frame while a line arrives every frame
+    fn frame_time(buffered_lines: usize) -> std::time::Duration {
+        const FRAMES: u32 = 200;
+        let line = |i: usize| {
+            StyledLine::parse(&format!(
+                "\x1b[32m m\x1b[0m 17:30:{:02} \x1b[35methminer\x1b[0m 0:{i} A{i} 29.47 Mh - cu0 29.47 56C 58%",
+                i % 60
+            ))
+        };
+        let buffer = std::sync::Mutex::new(OutputBuffer::new(buffered_lines));
+        for i in 0..buffered_lines {
+            buffer.lock().unwrap().push(line(i));
+        }
+        let ctx = egui::Context::default();
+        let mut view = OutputView::default();
+        view.filter.query = "A1".to_string();
+        let frame = |view: &mut OutputView| {
+            let input = egui::RawInput {
+                screen_rect: Some(egui::Rect::from_min_size(
+                    egui::Pos2::ZERO,
+                    egui::vec2(500.0, 400.0),
+                )),
+                ..Default::default()
+            };
+            let _ = ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| view.show(ui, &buffer));
+            });
+        };
+        // The first frames copy and filter the whole buffer, which is a one off cost
+        frame(&mut view);
+        frame(&mut view);
+
+        let start = std::time::Instant::now();
+        for i in 0..FRAMES as usize {
+            buffer.lock().unwrap().push(line(buffered_lines + i));
+            frame(&mut view);
+        }
+        start.elapsed() / FRAMES
+    }
+
+    /// Run with `cargo test --release bench_frame_time -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_frame_time() {
+        let small = frame_time(1_000);
+        let large = frame_time(100_000);
+        println!("frame time with 1k lines: {small:?}, with 100k lines: {large:?}");
+        assert!(large < small * 3);
+    }
+}
@@ -0,0 +1,331 @@
+use crate::app_options;
+use crate::miner_handle::MinerHandle;
+use crate::schedule::{Clock, SystemClock};
+
+use std::path::Path;
+use tokio::time::Duration;
+
+/// Name of the pause held while a watched process runs, see MinerController::pause
+const POLICY: &str = "processes";
+
+/// Time between scans of the running processes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How a watched process is recognized
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    /// The name of the executable, e.g. "blender"
+    #[default]
+    Name,
+    /// Text anywhere in the command line, e.g. "steamapps/common"
+    CommandLine,
+}
+
+impl MatchKind {
+    pub const ALL: [MatchKind; 2] = [MatchKind::Name, MatchKind::CommandLine];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MatchKind::Name => "Name",
+            MatchKind::CommandLine => "Command Line",
+        }
+    }
+}
+
+/// A process that needs the GPU
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct WatchedProcess {
+    pub pattern: String,
+    pub kind: MatchKind,
+}
+
+impl WatchedProcess {
+    fn matches(&self, process: &Process) -> bool {
+        if self.pattern.is_empty() {
+            return false;
+        }
+        match self.kind {
+            MatchKind::Name => process.name == self.pattern || process.exe_name() == self.pattern,
+            MatchKind::CommandLine => process.command_line.contains(&self.pattern),
+        }
+    }
+}
+
+/// Pausing mining while games, renders and the like run
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ProcessWatch {
+    pub enabled: bool,
+    pub processes: Vec<WatchedProcess>,
+    /// Seconds to wait after the last watched process exited before resuming
+    pub resume_delay: u64,
+}
+
+impl Default for ProcessWatch {
+    fn default() -> Self {
+        ProcessWatch {
+            enabled: false,
+            processes: Vec::new(),
+            resume_delay: 60,
+        }
+    }
+}
+
+impl ProcessWatch {
+    /// The first of the processes that is watched
+    pub fn find<'a>(&self, processes: &'a [Process]) -> Option<&'a Process> {
+        processes.iter().find(|process| {
+            self.processes
+                .iter()
+                .any(|watched| watched.matches(process))
+        })
+    }
+}
+
+/// A running process, as read from the proc filesystem
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    /// As the kernel knows it, cut to 15 bytes
+    pub name: String,
+    /// The arguments, separated by spaces
+    pub command_line: String,
+}
+
+impl Process {
+    /// The file name of the first argument, which unlike the name isn't cut short
+    fn exe_name(&self) -> &str {
+        let exe = self.command_line.split(' ').next().unwrap_or_default();
+        exe.rsplit('/').next().unwrap_or(exe)
+    }
+}
+
+/// Lists the processes under the proc root, which is /proc outside of tests. Processes that
+/// exit during the scan, or can't be read, are left out.
+pub fn scan(proc_root: &Path) -> Vec<Process> {
+    let entries = match std::fs::read_dir(proc_root) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let own_pid = std::process::id();
+    let mut processes: Vec<Process> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            if pid == own_pid {
+                return None;
+            }
+            let name = std::fs::read_to_string(entry.path().join("comm")).ok()?;
+            let command_line = std::fs::read(entry.path().join("cmdline")).ok()?;
+            let command_line = String::from_utf8_lossy(&command_line);
+            Some(Process {
+                pid,
+                name: name.trim_end_matches('\n').to_string(),
+                command_line: command_line.trim_end_matches('\0').replace('\0', " "),
+            })
+        })
+        .collect();
+    processes.sort_by_key(|process| process.pid);
+    processes
+}
+
+/// Decides from scans of the processes when mining is paused, holding the pause for the
+/// resume delay after the last watched process exited
+#[derive(Default)]
+pub struct Watcher {
+    /// The process the pause is held for, and when it was last seen running
+    found: Option<(Process, u64)>,
+}
+
+impl Watcher {
+    /// Takes the processes running at the unix time, returns why mining should be paused,
+    /// if it should
+    pub fn update(
+        &mut self,
+        watch: &ProcessWatch,
+        processes: &[Process],
+        now: u64,
+    ) -> Option<String> {
+        let found = watch.enabled.then(|| watch.find(processes)).flatten();
+        match (found, &mut self.found) {
+            (Some(process), Some((held, seen))) if held.pid == process.pid => *seen = now,
+            (Some(process), _) => {
+                println!(
+                    "\"{}\" is running (pid {}): {}",
+                    process.name, process.pid, process.command_line
+                );
+                self.found = Some((process.clone(), now));
+            }
+            (None, Some((held, seen))) => {
+                if !watch.enabled {
+                    self.found = None;
+                } else if now >= *seen + watch.resume_delay {
+                    println!(
+                        "\"{}\" (pid {}) exited {}s ago",
+                        held.name,
+                        held.pid,
+                        now - *seen
+                    );
+                    self.found = None;
+                }
+            }
+            (None, None) => {}
+        }
+        self.found
+            .as_ref()
+            .map(|(held, _)| format!("\"{}\" is running (pid {})", held.name, held.pid))
+    }
+}
+
+/// Pauses the miner while a process of the watch list of the options runs, see
+/// app_options::follow
+pub fn start(miner: &MinerHandle) {
+    let mut watcher = Watcher::default();
+    app_options::follow(
+        miner,
+        Duration::ZERO,
+        POLL_INTERVAL,
+        move |miner, options| {
+            let watch = &options.process_watch;
+            let processes = if watch.enabled && !watch.processes.is_empty() {
+                scan(Path::new("/proc"))
+            } else {
+                Vec::new()
+            };
+            let reason = watcher.update(watch, &processes, SystemClock.now());
+            async move {
+                match reason {
+                    Some(reason) => miner.pause(POLICY, reason).await,
+                    None => {
+                        miner.unpause(POLICY).await;
+                    }
+                }
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    /// A proc root with the processes, given by pid, name and arguments
+    fn fake_proc(name: &str, processes: &[(u32, &str, &[&str])]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("ethminer-gui-proc-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (pid, comm, args) in processes {
+            let dir = root.join(pid.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("comm"), format!("{comm}\n")).unwrap();
+            let cmdline: String = args.iter().map(|arg| format!("{arg}\0")).collect();
+            std::fs::write(dir.join("cmdline"), cmdline).unwrap();
+        }
+        // Not a process
+        std::fs::create_dir_all(root.join("sys")).unwrap();
+        root
+    }
+
+    fn watch(processes: &[(&str, MatchKind)]) -> ProcessWatch {
+        ProcessWatch {
+            enabled: true,
+            processes: processes
+                .iter()
+                .map(|(pattern, kind)| WatchedProcess {
+                    pattern: pattern.to_string(),
+                    kind: *kind,
+                })
+                .collect(),
+            resume_delay: 60,
+        }
+    }
+
+    #[test]
+    fn test_scan() {
+        let root = fake_proc(
+            "scan",
+            &[
+                (42, "blender", &["/usr/bin/blender", "-b", "scene.blend"]),
+                (7, "kthreadd", &[]),
+            ],
+        );
+        assert_eq!(
+            scan(&root),
+            [
+                Process {
+                    pid: 7,
+                    name: "kthreadd".to_string(),
+                    command_line: String::new(),
+                },
+                Process {
+                    pid: 42,
+                    name: "blender".to_string(),
+                    command_line: "/usr/bin/blender -b scene.blend".to_string(),
+                },
+            ]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(scan(&root), []);
+    }
+
+    #[test]
+    fn test_find() {
+        let root = fake_proc(
+            "find",
+            &[
+                (10, "bash", &["bash"]),
+                // The kernel cuts the name to 15 bytes
+                (
+                    20,
+                    "HorizonZeroDawn",
+                    &["/games/steamapps/common/HZD/HorizonZeroDawn.exe"],
+                ),
+                (30, "blender", &["blender"]),
+            ],
+        );
+        let processes = scan(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let found = |watch: ProcessWatch| watch.find(&processes).map(|process| process.pid);
+        assert_eq!(found(watch(&[("blender", MatchKind::Name)])), Some(30));
+        assert_eq!(found(watch(&[("blend", MatchKind::Name)])), None);
+        assert_eq!(
+            found(watch(&[("HorizonZeroDawn.exe", MatchKind::Name)])),
+            Some(20)
+        );
+        assert_eq!(
+            found(watch(&[("steamapps/common", MatchKind::CommandLine)])),
+            Some(20)
+        );
+        assert_eq!(found(watch(&[("", MatchKind::CommandLine)])), None);
+        assert_eq!(found(watch(&[])), None);
+    }
+
+    #[test]
+    fn test_pause_and_resume_delay() {
+        let running = [Process {
+            pid: 30,
+            name: "blender".to_string(),
+            command_line: "blender".to_string(),
+        }];
+        let mut watch = watch(&[("blender", MatchKind::Name)]);
+        let mut watcher = Watcher::default();
+        assert_eq!(watcher.update(&watch, &[], 0), None);
+        let reason = Some("\"blender\" is running (pid 30)".to_string());
+        assert_eq!(watcher.update(&watch, &running, 5), reason);
+        assert_eq!(watcher.update(&watch, &running, 10), reason);
+        // Held until the delay passed since it was last seen
+        assert_eq!(watcher.update(&watch, &[], 15), reason);
+        assert_eq!(watcher.update(&watch, &[], 69), reason);
+        assert_eq!(watcher.update(&watch, &[], 70), None);
+
+        // Released right away when the watch is turned off
+        assert_eq!(watcher.update(&watch, &running, 100), reason);
+        watch.enabled = false;
+        assert_eq!(watcher.update(&watch, &running, 105), None);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::miner_settings::MinerSettings;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the profile created from the settings of the GUI
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Named sets of MinerSettings, shared by the GUI and headless mode.
+/// Stored as json in the config dir, see Profiles::path
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Profiles {
+    /// Name of the profile used when none is given
+    pub active: String,
+    pub profiles: BTreeMap<String, MinerSettings>,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Profiles {
+    /// The location of the profiles file, "$XDG_CONFIG_HOME/ethminer-gui/profiles.json"
+    pub fn path() -> PathBuf {
+        config_dir().join("profiles.json")
+    }
+
+    /// Loads the profiles file, returns the default if it doesn't exist yet
+    pub fn load() -> Result<Profiles, String> {
+        Profiles::load_from(&Profiles::path())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Profiles, String> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("Could not parse {}: {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Profiles::default()),
+            Err(e) => Err(format!("Could not read {}: {e}", path.display())),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        self.save_to(&Profiles::path())
+    }
+
+    /// Writes to a temporary file first, so a crash can't leave a half written file
+    pub fn save_to(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    /// Returns the named profile, or the active profile if no name is given
+    pub fn get(&self, name: Option<&str>) -> Result<(&str, &MinerSettings), String> {
+        let name = name.unwrap_or(&self.active);
+        match self.profiles.get_key_value(name) {
+            Some((name, settings)) => Ok((name, settings)),
+            None => Err(format!("No profile named \"{name}\"")),
+        }
+    }
+
+    /// Stores the settings as the active profile
+    pub fn set_active_settings(&mut self, settings: MinerSettings) {
+        self.profiles.insert(self.active.clone(), settings);
+    }
+}
+
+/// Loads the named profile, or the active profile if no name is given, from the profiles file
+pub fn load_profile(path: &Path, name: Option<&str>) -> Result<(String, MinerSettings), String> {
+    let profiles = Profiles::load_from(path)?;
+    let (name, settings) = profiles.get(name)?;
+    Ok((name.to_string(), settings.clone()))
+}
+
+/// Makes the named profile the active profile in the profiles file, returning its settings
+pub fn activate_profile(path: &Path, name: &str) -> Result<MinerSettings, String> {
+    let mut profiles = Profiles::load_from(path)?;
+    let settings = profiles.get(Some(name))?.1.clone();
+    profiles.active = name.to_string();
+    profiles.save_to(path)?;
+    Ok(settings)
+}
+
+/// Name of the active profile, or the default profile if the profiles can't be loaded
+pub fn active_profile_name() -> String {
+    Profiles::load()
+        .map(|p| p.active)
+        .unwrap_or_else(|_| DEFAULT_PROFILE.to_string())
+}
+
+/// Directory holding the config files of the app, honouring XDG_CONFIG_HOME
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join("ethminer-gui")
+}
+
+/// Directory holding the logs of headless mode, honouring XDG_STATE_HOME
+pub fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state").join("ethminer-gui")
+}
+
+/// Returns the XDG base dir from the environment variable,
+/// falling back to the default relative to the home dir
+pub fn xdg_dir(var: &str, default: &str) -> PathBuf {
+    match std::env::var_os(var) {
+        // Relative paths are invalid according to the spec, and should be ignored
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+        _ => {
+            let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+            PathBuf::from(home).join(default)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir =
+            std::env::temp_dir().join(format!("ethminer-gui-profiles-{}", std::process::id()));
+        let path = dir.join("profiles.json");
+        assert!(Profiles::load_from(&path).unwrap().profiles.is_empty());
+
+        let mut profiles = Profiles::default();
+        profiles.set_active_settings(MinerSettings::default());
+        profiles.profiles.insert(
+            "backup".to_string(),
+            MinerSettings {
+                bin_path: "/opt/ethminer".to_string(),
+                ..Default::default()
+            },
+        );
+        profiles.save_to(&path).unwrap();
+
+        let loaded = Profiles::load_from(&path).unwrap();
+        assert_eq!(loaded.get(None).unwrap().0, DEFAULT_PROFILE);
+        assert_eq!(
+            loaded.get(Some("backup")).unwrap().1.bin_path,
+            "/opt/ethminer"
+        );
+        assert!(loaded.get(Some("missing")).is_err());
+
+        activate_profile(&path, "backup").unwrap();
+        let (name, settings) = load_profile(&path, None).unwrap();
+        assert_eq!(
+            (name.as_str(), settings.bin_path.as_str()),
+            ("backup", "/opt/ethminer")
+        );
+        assert!(activate_profile(&path, "missing").is_err());
+        assert_eq!(Profiles::load_from(&path).unwrap().active, "backup");
+
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(Profiles::load_from(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,560 @@
+use crate::app_options;
+use crate::miner_handle::MinerHandle;
+
+use tokio::time::Duration;
+
+pub const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+pub const DAY_MINUTES: u32 = 24 * 60;
+const WEEK_MINUTES: u32 = 7 * DAY_MINUTES;
+
+/// Time between checks of the schedule, which is how late a transition can be
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The timezone the windows of the schedule are in
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Timezone {
+    /// The timezone of the system, following its daylight saving time
+    #[default]
+    Local,
+    /// A fixed offset from UTC, e.g. for a rig whose system clock is set to UTC
+    UtcOffset { minutes: i32 },
+}
+
+impl Timezone {
+    /// Seconds to add to UTC to get the time in this timezone, at the unix time
+    pub fn offset_secs(self, unix_secs: u64) -> i64 {
+        match self {
+            Timezone::Local => local_offset_secs(unix_secs),
+            Timezone::UtcOffset { minutes } => minutes as i64 * 60,
+        }
+    }
+
+    /// The minute of the week of the unix time in this timezone, 0 being midnight on
+    /// Monday, and the seconds into it
+    pub fn week_minute(self, unix_secs: u64) -> (u32, i64) {
+        let local = unix_secs as i64 + self.offset_secs(unix_secs);
+        // 1970-01-01 was a Thursday
+        let day = (local.div_euclid(86_400) + 3).rem_euclid(7) as u32;
+        let secs = local.rem_euclid(86_400);
+        (day * DAY_MINUTES + secs as u32 / 60, secs % 60)
+    }
+}
+
+#[cfg(unix)]
+fn local_offset_secs(unix_secs: u64) -> i64 {
+    let time = unix_secs as libc::time_t;
+    // Safe, as localtime_r only writes to the tm it is given
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return 0;
+        }
+        tm.tm_gmtoff as i64
+    }
+}
+
+#[cfg(not(unix))]
+fn local_offset_secs(_unix_secs: u64) -> i64 {
+    0
+}
+
+/// A time of a day of the week to mine in, in minutes since midnight
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeWindow {
+    /// 0 is Monday
+    pub day: u8,
+    pub start: u16,
+    /// Exclusive, 1440 is midnight at the end of the day
+    pub end: u16,
+}
+
+/// When the schedule next starts or stops the miner
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transition {
+    /// Unix time of the transition
+    pub at: u64,
+    /// Whether the miner is started then, otherwise it is stopped
+    pub mining: bool,
+}
+
+/// Weekly times to mine in, e.g. at night and on weekends
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Schedule {
+    pub enabled: bool,
+    pub timezone: Timezone,
+    pub windows: Vec<TimeWindow>,
+    /// Unix time until which the miner was started by hand outside of the windows
+    pub mine_until: Option<u64>,
+}
+
+impl Schedule {
+    /// The windows as sorted and merged ranges of minutes of the week
+    fn week_ranges(&self) -> Vec<(u32, u32)> {
+        let mut ranges: Vec<(u32, u32)> = self
+            .windows
+            .iter()
+            .filter(|w| w.day < 7 && w.start < w.end)
+            .map(|w| {
+                let day = w.day as u32 * DAY_MINUTES;
+                (day + w.start as u32, day + (w.end as u32).min(DAY_MINUTES))
+            })
+            .collect();
+        ranges.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+
+    /// Whether the unix time is in one of the windows
+    pub fn is_mining_time(&self, unix_secs: u64) -> bool {
+        let (minute, _) = self.timezone.week_minute(unix_secs);
+        self.week_ranges()
+            .iter()
+            .any(|&(start, end)| (start..end).contains(&minute))
+    }
+
+    /// Whether the miner should run at the unix time, in a window or started by hand
+    pub fn wants_mining(&self, unix_secs: u64) -> bool {
+        self.is_mining_time(unix_secs) || self.mine_until.is_some_and(|until| until > unix_secs)
+    }
+
+    /// The next start or end of a window after the unix time, none if nothing ever changes
+    pub fn next_transition(&self, unix_secs: u64) -> Option<Transition> {
+        let mut boundaries: Vec<(u32, bool)> = Vec::new();
+        for (start, end) in self.week_ranges() {
+            boundaries.push((start, true));
+            boundaries.push((end % WEEK_MINUTES, false));
+        }
+        // A window ending at midnight on Sunday goes on in one starting at midnight on Monday
+        if let [(0, true), .., (0, false)] = boundaries[..] {
+            boundaries.remove(0);
+            boundaries.pop();
+        }
+
+        let (minute, secs) = self.timezone.week_minute(unix_secs);
+        let (delta, mining) = boundaries
+            .into_iter()
+            .map(|(boundary, mining)| {
+                let delta = (boundary + WEEK_MINUTES - minute) % WEEK_MINUTES;
+                // The transition of the current minute already happened
+                let delta = if delta == 0 { WEEK_MINUTES } else { delta };
+                (delta, mining)
+            })
+            .min()?;
+        let at = unix_secs as i64 - secs + delta as i64 * 60;
+        // The offset may change in between, with daylight saving time
+        let offset = self.timezone.offset_secs(unix_secs);
+        let at = at - (self.timezone.offset_secs(at as u64) - offset);
+        Some(Transition {
+            at: at as u64,
+            mining,
+        })
+    }
+
+    /// Whether the miner can be started by hand until the next window
+    pub fn can_mine_now(&self, unix_secs: u64) -> bool {
+        self.enabled && !self.wants_mining(unix_secs) && self.next_transition(unix_secs).is_some()
+    }
+
+    /// Keeps the miner running from now until the next window, which it then mines through
+    pub fn mine_now(&mut self, unix_secs: u64) {
+        self.mine_until = self.next_transition(unix_secs).map(|t| t.at);
+    }
+
+    /// Formats the unix time in the timezone, e.g. "Fri 22:00"
+    pub fn format_time(&self, unix_secs: u64) -> String {
+        let (minute, _) = self.timezone.week_minute(unix_secs);
+        let day = DAYS[(minute / DAY_MINUTES) as usize];
+        let minute = minute % DAY_MINUTES;
+        format!("{day} {:02}:{:02}", minute / 60, minute % 60)
+    }
+
+    /// What the schedule does next, e.g. "Scheduled mining until Sat 06:00"
+    pub fn status(&self, unix_secs: u64) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        let status = match self.next_transition(unix_secs) {
+            None if self.is_mining_time(unix_secs) => "Scheduled mining all week".to_string(),
+            None => "No mining windows scheduled".to_string(),
+            Some(next) if !self.is_mining_time(unix_secs) && self.wants_mining(unix_secs) => {
+                format!("Mining by hand until {}", self.format_time(next.at))
+            }
+            Some(next) if next.mining => {
+                format!("Next scheduled mining at {}", self.format_time(next.at))
+            }
+            Some(next) => format!("Scheduled mining until {}", self.format_time(next.at)),
+        };
+        Some(status)
+    }
+
+    /// Whether any minute of the hour of the day is in a window
+    pub fn hour_scheduled(&self, day: u8, hour: u16) -> bool {
+        let (start, end) = (hour * 60, (hour + 1) * 60);
+        self.windows
+            .iter()
+            .any(|w| w.day == day && w.start < end && start < w.end)
+    }
+
+    /// Adds the minutes of the day to the windows, or removes them
+    pub fn set_range(&mut self, day: u8, start: u16, end: u16, mining: bool) {
+        let mut minutes = [false; DAY_MINUTES as usize];
+        // Windows can be malformed, as the options are edited by hand, see week_ranges
+        for window in self.windows.iter().filter(|w| w.day == day) {
+            let end = window.end.min(DAY_MINUTES as u16);
+            minutes[window.start.min(end) as usize..end as usize].fill(true);
+        }
+        let end = end.min(DAY_MINUTES as u16);
+        minutes[start.min(end) as usize..end as usize].fill(mining);
+
+        self.windows.retain(|w| w.day != day);
+        let mut minute = 0;
+        while minute < minutes.len() {
+            if !minutes[minute] {
+                minute += 1;
+                continue;
+            }
+            let start = minute;
+            while minute < minutes.len() && minutes[minute] {
+                minute += 1;
+            }
+            self.windows.push(TimeWindow {
+                day,
+                start: start as u16,
+                end: minute as u16,
+            });
+        }
+        self.windows.sort_by_key(|w| (w.day, w.start));
+    }
+
+    /// The windows of the day, e.g. "00:00-06:00, 22:00-24:00"
+    pub fn day_windows(&self, day: u8) -> String {
+        let windows: Vec<String> = self
+            .windows
+            .iter()
+            .filter(|w| w.day == day)
+            .map(|w| {
+                format!(
+                    "{:02}:{:02}-{:02}:{:02}",
+                    w.start / 60,
+                    w.start % 60,
+                    w.end / 60,
+                    w.end % 60
+                )
+            })
+            .collect();
+        windows.join(", ")
+    }
+}
+
+/// The source of the current time, replaced in tests
+pub trait Clock {
+    /// The current unix time in seconds
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Start,
+    Stop,
+}
+
+/// Starts and stops the miner when the schedule changes whether it should run. In between,
+/// starting or stopping the miner by other means is left alone until the next transition.
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    /// Whether the schedule wanted the miner to run when last polled, none while disabled
+    mining: Option<bool>,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(clock: C) -> Scheduler<C> {
+        Scheduler {
+            clock,
+            mining: None,
+        }
+    }
+
+    pub fn poll(&mut self, schedule: &Schedule) -> Option<Action> {
+        if !schedule.enabled {
+            self.mining = None;
+            return None;
+        }
+        let mining = schedule.wants_mining(self.clock.now());
+        if self.mining == Some(mining) {
+            return None;
+        }
+        self.mining = Some(mining);
+        Some(if mining { Action::Start } else { Action::Stop })
+    }
+}
+
+/// Follows the schedule of the options, see app_options::follow
+pub fn start(miner: &MinerHandle) {
+    let mut scheduler = Scheduler::new(SystemClock);
+    // Starts after a miner started on launch, so the schedule applies to it too
+    app_options::follow(
+        miner,
+        POLL_INTERVAL,
+        POLL_INTERVAL,
+        move |miner, options| {
+            let running = miner.state().running;
+            let action = scheduler.poll(&options.schedule);
+            async move {
+                match action {
+                    Some(Action::Start) if !running => {
+                        miner
+                            .push_message("***** Starting the miner, as scheduled *****")
+                            .await;
+                        miner.restart();
+                    }
+                    Some(Action::Stop) => {
+                        if running {
+                            miner.push_message("").await;
+                            miner
+                                .push_message("***** Stopping the miner, as scheduled *****")
+                                .await;
+                        }
+                        // Also keeps a paused miner from being resumed
+                        miner.stop();
+                    }
+                    _ => {}
+                }
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Monday 2024-01-01 00:00 UTC
+    const MONDAY: u64 = 1_704_067_200;
+    const HOUR: u64 = 3600;
+    const DAY: u64 = 24 * HOUR;
+
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<u64>>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    /// Mining at night from 22:00 to 06:00 on weekdays, and all weekend
+    fn nights_and_weekends() -> Schedule {
+        let mut schedule = Schedule {
+            enabled: true,
+            timezone: Timezone::UtcOffset { minutes: 0 },
+            ..Default::default()
+        };
+        for day in 0..5 {
+            schedule.set_range(day, 0, 6 * 60, true);
+            schedule.set_range(day, 22 * 60, 24 * 60, true);
+        }
+        schedule.set_range(5, 0, 24 * 60, true);
+        schedule.set_range(6, 0, 24 * 60, true);
+        schedule
+    }
+
+    #[test]
+    fn test_mining_time() {
+        let schedule = nights_and_weekends();
+        assert!(schedule.is_mining_time(MONDAY + 5 * HOUR));
+        assert!(!schedule.is_mining_time(MONDAY + 6 * HOUR));
+        assert!(!schedule.is_mining_time(MONDAY + 21 * HOUR + 59 * 60));
+        assert!(schedule.is_mining_time(MONDAY + 22 * HOUR));
+        assert!(schedule.is_mining_time(MONDAY + 5 * DAY + 12 * HOUR));
+
+        // In a timezone 2 hours ahead of UTC, 22:00 is 20:00 UTC
+        let schedule = Schedule {
+            timezone: Timezone::UtcOffset { minutes: 120 },
+            ..schedule
+        };
+        assert!(schedule.is_mining_time(MONDAY + 20 * HOUR));
+        assert!(!schedule.is_mining_time(MONDAY + 4 * HOUR));
+        // Sunday 23:00 UTC is already Monday 01:00
+        assert!(schedule.is_mining_time(MONDAY - HOUR));
+        assert_eq!(schedule.format_time(MONDAY - HOUR), "Mon 01:00");
+    }
+
+    #[test]
+    fn test_next_transition() {
+        let schedule = nights_and_weekends();
+        assert_eq!(
+            schedule.next_transition(MONDAY + 12 * HOUR + 30),
+            Some(Transition {
+                at: MONDAY + 22 * HOUR,
+                mining: true,
+            })
+        );
+        // Mining through midnight isn't a transition
+        assert_eq!(
+            schedule.next_transition(MONDAY + 23 * HOUR),
+            Some(Transition {
+                at: MONDAY + DAY + 6 * HOUR,
+                mining: false,
+            })
+        );
+        // Friday night goes on through the weekend, until Monday morning
+        let friday_night = MONDAY + 4 * DAY + 22 * HOUR;
+        assert_eq!(
+            schedule.next_transition(friday_night),
+            Some(Transition {
+                at: MONDAY + 7 * DAY + 6 * HOUR,
+                mining: false,
+            })
+        );
+        assert_eq!(
+            schedule.status(friday_night).unwrap(),
+            "Scheduled mining until Mon 06:00"
+        );
+        assert_eq!(
+            schedule.status(MONDAY + 12 * HOUR).unwrap(),
+            "Next scheduled mining at Mon 22:00"
+        );
+
+        let mut all_week = Schedule {
+            enabled: true,
+            ..Default::default()
+        };
+        assert_eq!(all_week.next_transition(MONDAY), None);
+        assert_eq!(
+            all_week.status(MONDAY).unwrap(),
+            "No mining windows scheduled"
+        );
+        for day in 0..7 {
+            all_week.set_range(day, 0, 24 * 60, true);
+        }
+        assert_eq!(all_week.next_transition(MONDAY), None);
+    }
+
+    #[test]
+    fn test_editing_windows() {
+        let mut schedule = Schedule::default();
+        schedule.set_range(2, 60, 120, true);
+        schedule.set_range(2, 120, 180, true);
+        schedule.set_range(2, 22 * 60, 24 * 60, true);
+        assert_eq!(schedule.day_windows(2), "01:00-03:00, 22:00-24:00");
+        assert!(schedule.hour_scheduled(2, 1));
+        assert!(!schedule.hour_scheduled(2, 3));
+
+        // Taking out an hour splits the window
+        schedule.set_range(2, 22 * 60, 23 * 60, false);
+        schedule.set_range(2, 90, 120, false);
+        assert_eq!(
+            schedule.day_windows(2),
+            "01:00-01:30, 02:00-03:00, 23:00-24:00"
+        );
+        assert_eq!(schedule.day_windows(3), "");
+
+        // Windows the options were edited into by hand that don't make sense are dropped
+        let mut schedule = Schedule {
+            windows: vec![
+                TimeWindow {
+                    day: 4,
+                    start: 600,
+                    end: 300,
+                },
+                TimeWindow {
+                    day: 4,
+                    start: 1500,
+                    end: 1600,
+                },
+                TimeWindow {
+                    day: 4,
+                    start: 1380,
+                    end: 2000,
+                },
+            ],
+            ..Default::default()
+        };
+        schedule.set_range(4, 60, 120, true);
+        assert_eq!(schedule.day_windows(4), "01:00-02:00, 23:00-24:00");
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let time = Rc::new(Cell::new(MONDAY + 12 * HOUR));
+        let mut scheduler = Scheduler::new(FakeClock(time.clone()));
+        let mut schedule = nights_and_weekends();
+
+        // The schedule applies from the start
+        assert_eq!(scheduler.poll(&schedule), Some(Action::Stop));
+        assert_eq!(scheduler.poll(&schedule), None);
+        time.set(MONDAY + 22 * HOUR);
+        assert_eq!(scheduler.poll(&schedule), Some(Action::Start));
+        time.set(MONDAY + DAY + 2 * HOUR);
+        assert_eq!(scheduler.poll(&schedule), None);
+        time.set(MONDAY + DAY + 6 * HOUR);
+        assert_eq!(scheduler.poll(&schedule), Some(Action::Stop));
+
+        // Mining by hand goes on into the next window, and stops at its end
+        time.set(MONDAY + DAY + 15 * HOUR);
+        assert!(schedule.can_mine_now(time.get()));
+        schedule.mine_now(time.get());
+        assert!(!schedule.can_mine_now(time.get()));
+        assert_eq!(
+            schedule.status(time.get()).unwrap(),
+            "Mining by hand until Tue 22:00"
+        );
+        assert_eq!(scheduler.poll(&schedule), Some(Action::Start));
+        time.set(MONDAY + DAY + 22 * HOUR);
+        assert_eq!(scheduler.poll(&schedule), None);
+        time.set(MONDAY + 2 * DAY + 6 * HOUR);
+        assert_eq!(scheduler.poll(&schedule), Some(Action::Stop));
+
+        // Disabling the schedule leaves the miner alone, enabling it applies it again
+        schedule.enabled = false;
+        assert_eq!(scheduler.poll(&schedule), None);
+        schedule.enabled = true;
+        assert_eq!(scheduler.poll(&schedule), Some(Action::Stop));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let schedule = Schedule {
+            enabled: true,
+            timezone: Timezone::UtcOffset { minutes: -300 },
+            windows: vec![TimeWindow {
+                day: 5,
+                start: 0,
+                end: 1440,
+            }],
+            mine_until: None,
+        };
+        let json = serde_json::to_string(&schedule).unwrap();
+        assert_eq!(
+            json,
+            "{\"enabled\":true,\"timezone\":{\"kind\":\"utc_offset\",\"minutes\":-300},\
+             \"windows\":[{\"day\":5,\"start\":0,\"end\":1440}],\"mine_until\":null}"
+        );
+        assert_eq!(serde_json::from_str::<Schedule>(&json).unwrap(), schedule);
+        assert_eq!(
+            serde_json::from_str::<Schedule>("{\"timezone\":{\"kind\":\"local\"}}").unwrap(),
+            Schedule::default()
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use crate::ethash;
+use crate::output_parser::{parse_event, GpuStatus, OutputEvent};
+
+use std::time::{Duration, Instant};
+
+/// Statistics of the current run of the app
+#[derive(Default)]
+pub struct SessionStats {
+    /// Time spent mining in earlier runs of the miner this session
+    mining_time: Duration,
+    /// Set while the miner is running
+    running_since: Option<Instant>,
+    /// Latest total hashrate in hashes per second
+    pub current_hashrate: f64,
+    pub peak_hashrate: f64,
+    hashrate_sum: f64,
+    hashrate_samples: u64,
+    /// Latest readings of each GPU
+    pub gpus: Vec<GpuStatus>,
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Stale shares, these are also counted as accepted or rejected
+    pub stale: u64,
+    pub last_accepted: Option<Instant>,
+    /// When the miner last printed a line, and when the pool last sent a job
+    pub last_output: Option<Instant>,
+    pub last_job: Option<Instant>,
+    /// The epoch being mined, and the block of the last job if the pool gives it
+    pub epoch: Option<u64>,
+    pub block: Option<u64>,
+    pub reconnects: u64,
+    pub crashes: u64,
+    pub restarts: u64,
+    /// Restarts of a miner that was running but not mining, see Watchdog
+    pub stall_restarts: u64,
+    /// Set after losing the pool connection, until it is established again
+    pub disconnected: bool,
+    /// GPUs that stopped mining at the --tstop temperature, until they cooled down
+    pub thermal_stopped: Vec<usize>,
+}
+
+impl SessionStats {
+    /// Total time the miner has been running this session
+    pub fn uptime(&self) -> Duration {
+        self.mining_time + self.running_since.map_or(Duration::ZERO, |s| s.elapsed())
+    }
+
+    /// Mean of the reported total hashrates
+    pub fn average_hashrate(&self) -> f64 {
+        if self.hashrate_samples == 0 {
+            0.0
+        } else {
+            self.hashrate_sum / self.hashrate_samples as f64
+        }
+    }
+}
+
+/// Totals over every session, persisted between runs of the app
+#[derive(Default, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LifetimeStats {
+    pub mining_secs: u64,
+    pub peak_hashrate: f64,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub stale: u64,
+    pub reconnects: u64,
+    pub restarts: u64,
+    pub stall_restarts: u64,
+}
+
+/// Mining statistics, built from the parsed miner output and the controller's restarts
+#[derive(Default)]
+pub struct MinerStats {
+    pub session: SessionStats,
+    /// Lifetime totals, not including the time the miner has been running since it was last started
+    lifetime: LifetimeStats,
+}
+
+impl MinerStats {
+    /// Updates the statistics from a line of miner output
+    pub fn handle_line(&mut self, line: &str) {
+        self.session.last_output = Some(Instant::now());
+        if let Some(event) = parse_event(line) {
+            self.handle_event(event);
+        }
+    }
+
+    fn handle_event(&mut self, event: OutputEvent) {
+        let (session, lifetime) = (&mut self.session, &mut self.lifetime);
+        match event {
+            OutputEvent::Hashrate { total, gpus } => {
+                session.current_hashrate = total;
+                session.peak_hashrate = session.peak_hashrate.max(total);
+                lifetime.peak_hashrate = lifetime.peak_hashrate.max(total);
+                session.hashrate_sum += total;
+                session.hashrate_samples += 1;
+                session.gpus = gpus;
+            }
+            OutputEvent::Accepted { stale } => {
+                session.accepted += 1;
+                lifetime.accepted += 1;
+                session.last_accepted = Some(Instant::now());
+                if stale {
+                    session.stale += 1;
+                    lifetime.stale += 1;
+                }
+            }
+            OutputEvent::Rejected { stale } => {
+                session.rejected += 1;
+                lifetime.rejected += 1;
+                if stale {
+                    session.stale += 1;
+                    lifetime.stale += 1;
+                }
+            }
+            OutputEvent::Connected => {
+                if session.disconnected {
+                    session.reconnects += 1;
+                    lifetime.reconnects += 1;
+                }
+                session.disconnected = false;
+            }
+            OutputEvent::Disconnected => session.disconnected = true,
+            OutputEvent::NewJob { block } => {
+                session.last_job = Some(Instant::now());
+                if let Some(block) = block {
+                    session.block = Some(block);
+                    session.epoch = Some(ethash::epoch_of_block(block));
+                }
+            }
+            OutputEvent::Epoch(epoch) => session.epoch = Some(epoch),
+            OutputEvent::ThermalStop { gpu, .. } => {
+                if !session.thermal_stopped.contains(&gpu) {
+                    session.thermal_stopped.push(gpu);
+                    session.thermal_stopped.sort_unstable();
+                }
+            }
+            OutputEvent::ThermalResume { gpu } => session.thermal_stopped.retain(|&g| g != gpu),
+        }
+    }
+
+    /// Called when the miner process has been spawned
+    pub fn miner_started(&mut self) {
+        if self.session.running_since.is_none() {
+            self.session.running_since = Some(Instant::now());
+        }
+        self.session.disconnected = false;
+    }
+
+    /// Called when the miner process has exited or was killed
+    pub fn miner_stopped(&mut self) {
+        if let Some(since) = self.session.running_since.take() {
+            let elapsed = since.elapsed();
+            self.session.mining_time += elapsed;
+            self.lifetime.mining_secs += elapsed.as_secs();
+        }
+        self.session.current_hashrate = 0.0;
+        self.session.gpus.clear();
+        self.session.thermal_stopped.clear();
+    }
+
+    /// Called when the miner exited without having been stopped
+    pub fn miner_crashed(&mut self) {
+        self.miner_stopped();
+        self.session.crashes += 1;
+    }
+
+    /// Called when the controller restarts a crashed miner
+    pub fn miner_restarted(&mut self) {
+        self.session.restarts += 1;
+        self.lifetime.restarts += 1;
+    }
+
+    /// Called when the watchdog restarts a miner that stopped mining
+    pub fn miner_stalled(&mut self) {
+        self.session.stall_restarts += 1;
+        self.lifetime.stall_restarts += 1;
+    }
+
+    /// The lifetime totals, including the time the miner has been running since it was last started
+    pub fn lifetime(&self) -> LifetimeStats {
+        let mut lifetime = self.lifetime.clone();
+        if let Some(since) = self.session.running_since {
+            lifetime.mining_secs += since.elapsed().as_secs();
+        }
+        lifetime
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let session = &self.session;
+        StatsSnapshot {
+            uptime_secs: session.uptime().as_secs(),
+            current_hashrate: session.current_hashrate,
+            average_hashrate: session.average_hashrate(),
+            peak_hashrate: session.peak_hashrate,
+            gpus: session.gpus.clone(),
+            accepted: session.accepted,
+            rejected: session.rejected,
+            stale: session.stale,
+            last_accepted_secs: session.last_accepted.map(|t| t.elapsed().as_secs()),
+            epoch: session.epoch,
+            block: session.block,
+            reconnects: session.reconnects,
+            crashes: session.crashes,
+            restarts: session.restarts,
+            stall_restarts: session.stall_restarts,
+            thermal_stopped: session.thermal_stopped.clone(),
+            lifetime: self.lifetime(),
+        }
+    }
+
+    /// Restores the lifetime totals saved by an earlier run of the app
+    pub fn set_lifetime(&mut self, lifetime: LifetimeStats) {
+        self.lifetime = lifetime;
+    }
+
+    /// Clears both the session statistics and the lifetime totals
+    pub fn reset(&mut self) {
+        let running = self.session.running_since.is_some();
+        self.session = SessionStats::default();
+        self.lifetime = LifetimeStats::default();
+        if running {
+            self.session.running_since = Some(Instant::now());
+        }
+    }
+}
+
+/// A copy of the statistics at one point in time, sent to control socket clients
+#[derive(Default, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct StatsSnapshot {
+    pub uptime_secs: u64,
+    /// Hashrates in hashes per second
+    pub current_hashrate: f64,
+    pub average_hashrate: f64,
+    pub peak_hashrate: f64,
+    pub gpus: Vec<GpuStatus>,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub stale: u64,
+    /// Seconds since the last accepted share
+    pub last_accepted_secs: Option<u64>,
+    pub epoch: Option<u64>,
+    pub block: Option<u64>,
+    pub reconnects: u64,
+    pub crashes: u64,
+    pub restarts: u64,
+    pub stall_restarts: u64,
+    /// GPUs that stopped mining at the --tstop temperature
+    pub thermal_stopped: Vec<usize>,
+    pub lifetime: LifetimeStats,
+}
+
+impl StatsSnapshot {
+    pub fn uptime(&self) -> Duration {
+        Duration::from_secs(self.uptime_secs)
+    }
+}
+
+/// Fraction of the shares that were accepted, None if there are no shares yet
+pub fn accepted_ratio(accepted: u64, rejected: u64) -> Option<f64> {
+    let total = accepted + rejected;
+    if total == 0 {
+        None
+    } else {
+        Some(accepted as f64 / total as f64)
+    }
+}
+
+/// Accepted shares per hour of mining
+pub fn shares_per_hour(accepted: u64, mining_time: Duration) -> f64 {
+    let hours = mining_time.as_secs_f64() / 3600.0;
+    if hours < 1.0 / 60.0 {
+        0.0
+    } else {
+        accepted as f64 / hours
+    }
+}
+
+/// Formats a hashrate in hashes per second with a unit, e.g. "29.47 MH/s"
+pub fn format_hashrate(hashrate: f64) -> String {
+    let units = ["H/s", "kH/s", "MH/s", "GH/s", "TH/s"];
+    let mut value = hashrate;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < units.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, units[unit])
+}
+
+/// Formats a duration as "1d 02:03:04"
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, minutes, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{days}d {hours:02}:{minutes:02}:{secs:02}")
+    } else {
+        format!("{hours:02}:{minutes:02}:{secs:02}")
+    }
+}
+
+/// Formats a unix time as its date in UTC, e.g. "2023-03-14"
+pub fn format_date(unix_secs: u64) -> String {
+    // Converts days since 1970-01-01 to a date of the proleptic Gregorian calendar,
+    // counting in eras of 400 years that start on the 1st of March
+    let days = unix_secs / 86400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!("{year}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_from_output() {
+        let mut stats = MinerStats::default();
+        stats.miner_started();
+        for line in [
+            " i 17:29:36 ethminer Established connection to eu1.ethermine.org [1.2.3.4:4444]",
+            " i 17:29:37 ethminer Job: 8a4e7c3b… block 14300312 eu1.ethermine.org:4444",
+            " m 17:29:50 ethminer 0:00 A0 20.00 Mh - cu0 20.00",
+            " m 17:29:55 ethminer 0:00 A0 40.00 Mh - cu0 40.00",
+            " i 17:30:06 ethminer **Accepted  48 ms. eu1.ethermine.org:4444",
+            " i 17:30:07 ethminer **Accepted stale 210 ms. eu1.ethermine.org:4444",
+            " X 17:30:08 ethminer **Rejected  48 ms. eu1.ethermine.org:4444",
+            " X 17:31:11 ethminer Pause mining on gpu0 : temperature 86 is equal/above --tstop 85",
+            " X 17:31:12 ethminer Pause mining on gpu1 : temperature 85 is equal/above --tstop 85",
+            " i 17:35:41 ethminer Resume mining on gpu0 : temperature 55 is equal/below --tstart 55",
+            " X 17:40:02 ethminer No connection. Suspend mining ...",
+            " i 17:40:09 ethminer Established connection to eu1.ethermine.org [1.2.3.4:4444]",
+            " m 17:40:10 ethminer 0:10 A2:R1 30.00 Mh - cu0 30.00",
+        ] {
+            stats.handle_line(line);
+        }
+        let session = &stats.session;
+        assert_eq!(session.current_hashrate, 30e6);
+        assert_eq!(session.peak_hashrate, 40e6);
+        assert_eq!(session.average_hashrate(), 30e6);
+        assert_eq!(
+            (session.accepted, session.rejected, session.stale),
+            (2, 1, 1)
+        );
+        assert!(session.last_accepted.is_some());
+        assert_eq!(session.reconnects, 1);
+        assert_eq!(session.thermal_stopped, [1]);
+        assert_eq!(stats.snapshot().thermal_stopped, [1]);
+
+        stats.miner_stopped();
+        stats.miner_restarted();
+        stats.miner_started();
+        assert_eq!(stats.session.restarts, 1);
+        assert_eq!(stats.session.current_hashrate, 0.0);
+        assert!(stats.session.thermal_stopped.is_empty());
+
+        let lifetime = stats.lifetime();
+        assert_eq!(
+            (lifetime.accepted, lifetime.rejected, lifetime.stale),
+            (2, 1, 1)
+        );
+        assert_eq!(lifetime.peak_hashrate, 40e6);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.accepted, 2);
+        assert_eq!(snapshot.average_hashrate, 30e6);
+        assert_eq!(snapshot.last_accepted_secs, Some(0));
+        assert_eq!(
+            (snapshot.epoch, snapshot.block),
+            (Some(476), Some(14300312))
+        );
+        assert_eq!(snapshot.lifetime, stats.lifetime());
+
+        stats.reset();
+        assert_eq!(stats.lifetime(), LifetimeStats::default());
+        assert_eq!(stats.session.accepted, 0);
+    }
+
+    #[test]
+    fn test_lifetime_totals_carry_over() {
+        let mut stats = MinerStats::default();
+        stats.set_lifetime(LifetimeStats {
+            accepted: 100,
+            restarts: 3,
+            ..Default::default()
+        });
+        stats.handle_line(" i 17:30:06 ethminer **Accepted  48 ms. eu1.ethermine.org:4444");
+        stats.miner_restarted();
+        let lifetime = stats.lifetime();
+        assert_eq!(lifetime.accepted, 101);
+        assert_eq!(lifetime.restarts, 4);
+        assert_eq!(stats.session.accepted, 1);
+
+        // Older saves without some of the fields still load
+        let loaded: LifetimeStats = serde_json::from_str(r#"{"accepted": 5}"#).unwrap();
+        assert_eq!(loaded.accepted, 5);
+    }
+
+    #[test]
+    fn test_ratios_and_formatting() {
+        assert_eq!(accepted_ratio(0, 0), None);
+        assert_eq!(accepted_ratio(3, 1), Some(0.75));
+        assert_eq!(shares_per_hour(6, Duration::from_secs(1800)), 12.0);
+        assert_eq!(format_hashrate(29_470_000.0), "29.47 MH/s");
+        assert_eq!(format_hashrate(512.0), "512.00 H/s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "01:02:03");
+        assert_eq!(format_duration(Duration::from_secs(90061)), "1d 01:01:01");
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+        assert_eq!(format_date(1_678_838_399), "2023-03-14");
+        assert_eq!(format_date(4_107_542_400), "2100-03-01");
+    }
+}
@@ -0,0 +1,258 @@
+use crate::app_options;
+use crate::miner_handle::MinerHandle;
+use crate::schedule::{Clock, SystemClock, Timezone, DAY_MINUTES};
+
+use tokio::time::Duration;
+
+/// Name of the pause held while mining costs too much, see MinerController::pause
+const POLICY: &str = "tariff";
+
+/// Time between checks of the price of electricity
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The price of electricity at some hours of some days of the week
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct TariffRate {
+    /// Days it applies on, Monday first
+    pub days: [bool; 7],
+    /// In minutes since midnight. A start after the end goes past midnight into the next day.
+    pub start: u16,
+    pub end: u16,
+    /// Per kWh
+    pub price: f64,
+}
+
+impl Default for TariffRate {
+    fn default() -> Self {
+        // Peak hours on weekdays
+        TariffRate {
+            days: [true, true, true, true, true, false, false],
+            start: 17 * 60,
+            end: 21 * 60,
+            price: 0.3,
+        }
+    }
+}
+
+impl TariffRate {
+    /// Whether the rate applies at the minute of the week
+    fn applies(&self, week_minute: u32) -> bool {
+        let day = (week_minute / DAY_MINUTES) as usize;
+        let minute = week_minute % DAY_MINUTES;
+        let (start, end) = (self.start as u32, self.end as u32);
+        if start <= end {
+            self.days[day] && (start..end).contains(&minute)
+        } else {
+            (self.days[day] && minute >= start) || (self.days[(day + 6) % 7] && minute < end)
+        }
+    }
+}
+
+/// What electricity costs, and what mining earns to pay for it
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Tariff {
+    /// Power drawn by the rig while mining, 0 if unknown
+    pub watts: f64,
+    /// Price per kWh at the times no rate applies to
+    pub base_price: f64,
+    /// The first rate that applies at a time sets the price
+    pub rates: Vec<TariffRate>,
+    pub timezone: Timezone,
+    pub currency: String,
+    /// Estimated earnings of 1 MH/s over a day, as given by a mining calculator, 0 if unknown
+    pub revenue_per_mhs_day: f64,
+    /// Pauses mining while the price is above max_price
+    pub pause_above_price: bool,
+    pub max_price: f64,
+    /// Pauses mining while it earns less than the electricity costs
+    pub pause_unprofitable: bool,
+}
+
+impl Default for Tariff {
+    fn default() -> Self {
+        Tariff {
+            watts: 0.0,
+            base_price: 0.15,
+            rates: Vec::new(),
+            timezone: Timezone::Local,
+            currency: "EUR".to_string(),
+            revenue_per_mhs_day: 0.0,
+            pause_above_price: false,
+            max_price: 0.25,
+            pause_unprofitable: false,
+        }
+    }
+}
+
+impl Tariff {
+    /// Price per kWh at the unix time
+    pub fn price_at(&self, unix_secs: u64) -> f64 {
+        let (minute, _) = self.timezone.week_minute(unix_secs);
+        self.rates
+            .iter()
+            .find(|rate| rate.applies(minute))
+            .map_or(self.base_price, |rate| rate.price)
+    }
+
+    /// What the electricity of mining costs per hour at the unix time
+    pub fn cost_per_hour(&self, unix_secs: u64) -> f64 {
+        self.watts / 1000.0 * self.price_at(unix_secs)
+    }
+
+    /// What mining at the hashrate earns per hour
+    pub fn revenue_per_hour(&self, hashrate: f64) -> f64 {
+        hashrate / 1e6 * self.revenue_per_mhs_day / 24.0
+    }
+
+    /// Formats an amount of money, e.g. "0.42 EUR"
+    pub fn format_money(&self, amount: f64) -> String {
+        format!("{amount:.2} {}", self.currency)
+    }
+
+    /// Why mining at the hashrate should be paused at the unix time, if it should
+    pub fn pause_reason(&self, unix_secs: u64, hashrate: f64) -> Option<String> {
+        let price = self.price_at(unix_secs);
+        if self.pause_above_price && price > self.max_price {
+            return Some(format!(
+                "electricity costs {}/kWh, more than {}",
+                self.format_money(price),
+                self.format_money(self.max_price)
+            ));
+        }
+        // Without an estimate of the earnings, mining can't be told to be unprofitable
+        if !self.pause_unprofitable || self.watts <= 0.0 || self.revenue_per_mhs_day <= 0.0 {
+            return None;
+        }
+        let (revenue, cost) = (
+            self.revenue_per_hour(hashrate),
+            self.cost_per_hour(unix_secs),
+        );
+        (hashrate > 0.0 && revenue < cost).then(|| {
+            format!(
+                "mining earns {}/h, less than the {}/h of electricity",
+                self.format_money(revenue),
+                self.format_money(cost)
+            )
+        })
+    }
+}
+
+/// Pauses the miner while the tariff of the options makes mining too expensive, see
+/// app_options::follow
+pub fn start(miner: &MinerHandle) {
+    // The hashrate of the last run, to tell whether mining pays while it is paused
+    let mut hashrate = 0.0;
+    app_options::follow(
+        miner,
+        Duration::ZERO,
+        POLL_INTERVAL,
+        move |miner, options| {
+            let stats = miner.stats();
+            if miner.state().running && stats.average_hashrate > 0.0 {
+                hashrate = stats.average_hashrate;
+            }
+            let reason = options.tariff.pause_reason(SystemClock.now(), hashrate);
+            async move {
+                match reason {
+                    Some(reason) => miner.pause(POLICY, reason).await,
+                    None => {
+                        miner.unpause(POLICY).await;
+                    }
+                }
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monday 2024-01-01 00:00 UTC
+    const MONDAY: u64 = 1_704_067_200;
+    const HOUR: u64 = 3600;
+    const DAY: u64 = 24 * HOUR;
+
+    /// Peak hours on weekday evenings, and cheap nights every day
+    fn tariff() -> Tariff {
+        Tariff {
+            watts: 800.0,
+            base_price: 0.2,
+            rates: vec![
+                TariffRate::default(),
+                TariffRate {
+                    days: [true; 7],
+                    start: 23 * 60,
+                    end: 6 * 60,
+                    price: 0.1,
+                },
+            ],
+            timezone: Timezone::UtcOffset { minutes: 0 },
+            revenue_per_mhs_day: 0.03,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_price() {
+        let tariff = tariff();
+        assert_eq!(tariff.price_at(MONDAY + 12 * HOUR), 0.2);
+        assert_eq!(tariff.price_at(MONDAY + 17 * HOUR), 0.3);
+        assert_eq!(tariff.price_at(MONDAY + 21 * HOUR), 0.2);
+        // No peak on weekends
+        assert_eq!(tariff.price_at(MONDAY + 5 * DAY + 18 * HOUR), 0.2);
+        // The night rate goes on past midnight, also into Monday from Sunday
+        assert_eq!(tariff.price_at(MONDAY + 23 * HOUR), 0.1);
+        assert_eq!(tariff.price_at(MONDAY + DAY + 5 * HOUR), 0.1);
+        assert_eq!(tariff.price_at(MONDAY + 2 * HOUR), 0.1);
+        assert_eq!(tariff.price_at(MONDAY + 6 * HOUR), 0.2);
+
+        // In a timezone 2 hours ahead of UTC, the peak starts at 15:00 UTC
+        let tariff = Tariff {
+            timezone: Timezone::UtcOffset { minutes: 120 },
+            ..tariff
+        };
+        assert_eq!(tariff.price_at(MONDAY + 15 * HOUR), 0.3);
+    }
+
+    #[test]
+    fn test_cost_and_revenue() {
+        let tariff = tariff();
+        assert!((tariff.cost_per_hour(MONDAY + 18 * HOUR) - 0.24).abs() < 1e-9);
+        // 200 MH/s earn 6 a day
+        assert!((tariff.revenue_per_hour(200e6) - 0.25).abs() < 1e-9);
+        assert_eq!(tariff.format_money(1.5), "1.50 EUR");
+    }
+
+    #[test]
+    fn test_pause_above_price() {
+        let mut tariff = tariff();
+        assert_eq!(tariff.pause_reason(MONDAY + 18 * HOUR, 200e6), None);
+        tariff.pause_above_price = true;
+        assert_eq!(
+            tariff.pause_reason(MONDAY + 18 * HOUR, 200e6).unwrap(),
+            "electricity costs 0.30 EUR/kWh, more than 0.25 EUR"
+        );
+        assert_eq!(tariff.pause_reason(MONDAY + 12 * HOUR, 200e6), None);
+    }
+
+    #[test]
+    fn test_pause_unprofitable() {
+        let mut tariff = Tariff {
+            pause_unprofitable: true,
+            ..tariff()
+        };
+        // 0.25/h earned, 0.24/h for electricity at peak
+        assert_eq!(tariff.pause_reason(MONDAY + 18 * HOUR, 200e6), None);
+        assert_eq!(
+            tariff.pause_reason(MONDAY + 18 * HOUR, 150e6).unwrap(),
+            "mining earns 0.19 EUR/h, less than the 0.24 EUR/h of electricity"
+        );
+        assert_eq!(tariff.pause_reason(MONDAY + 12 * HOUR, 150e6), None);
+        // Nothing to compare without a hashrate or an estimate of the earnings
+        assert_eq!(tariff.pause_reason(MONDAY + 18 * HOUR, 0.0), None);
+        tariff.revenue_per_mhs_day = 0.0;
+        assert_eq!(tariff.pause_reason(MONDAY + 18 * HOUR, 150e6), None);
+    }
+}
@@ -1,18 +1,167 @@
-use crate::icon_data::get_icon_argb;
-use crate::miner_controller::MinerController;
+#[cfg(target_os = "linux")]
+use crate::icon_data::{HEIGHT, WIDTH};
+use crate::launch::{self, AutoStart};
+use crate::miner_handle::MinerHandle;
 use crate::miner_settings::*;
+use crate::profiles::active_profile_name;
+#[cfg(target_os = "linux")]
+use crate::{
+    alerts::Alert,
+    app_options::AppOptions,
+    headless::Signals,
+    miner_controller::{MinerEvent, MinerState},
+    miner_handle, notifications,
+    profiles::{self, Profiles},
+    schedule::{Clock, Schedule, SystemClock},
+    stats::{self, StatsSnapshot},
+    tray_icon::{self, IconStatus, IconTracker},
+};
 
 use ksni;
 use ksni::menu::*;
 use std::sync::{Arc, RwLock};
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant};
 use tokio::runtime::Handle;
-use tokio::sync::Mutex;
+
+/// Time between refreshes of the status shown by the tray, while nothing else changes
+#[cfg(target_os = "linux")]
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
 
 #[cfg(target_os = "linux")]
 struct MinerTrayLinux {
     miner_settings: Arc<RwLock<MinerSettings>>,
-    miner_controller: Arc<Mutex<MinerController>>,
+    miner: MinerHandle,
     tokio_handle: Handle,
+    /// Starting the miner on launch, which can be cancelled from the tray
+    auto_start: AutoStart,
+    /// Names of the profiles and the index of the active one, as last loaded
+    profiles: Vec<String>,
+    active_profile: usize,
+    /// The pools of the active profile, in the order ethminer tries them
+    pools: Vec<String>,
+    /// The icon, regenerated when what it shows changes
+    icon_tracker: IconTracker,
+    icon_status: IconStatus,
+    icon: Vec<u8>,
+    /// The last alert raised by the alert rules, until it is dismissed from the menu
+    alert: Option<Alert>,
+    /// The mining schedule, as last loaded
+    schedule: Schedule,
+}
+
+#[cfg(target_os = "linux")]
+impl MinerTrayLinux {
+    fn new(
+        miner_settings: Arc<RwLock<MinerSettings>>,
+        miner: MinerHandle,
+        tokio_handle: Handle,
+        auto_start: AutoStart,
+    ) -> MinerTrayLinux {
+        let mut tray = MinerTrayLinux {
+            miner_settings,
+            tokio_handle,
+            auto_start,
+            profiles: Vec::new(),
+            active_profile: 0,
+            pools: Vec::new(),
+            icon_tracker: IconTracker::new(&miner.stats()),
+            icon_status: IconStatus::default(),
+            icon: tray_icon::compose(&IconStatus::default()),
+            alert: None,
+            schedule: Schedule::default(),
+            miner,
+        };
+        tray.refresh();
+        tray
+    }
+
+    fn refresh(&mut self) {
+        self.update_icon();
+        self.reload_profiles();
+        self.schedule = AppOptions::load().unwrap_or_default().schedule;
+    }
+
+    fn update_icon(&mut self) {
+        let status =
+            self.icon_tracker
+                .update(&self.miner.state(), &self.miner.stats(), Instant::now());
+        if status != self.icon_status {
+            self.icon_status = status;
+            self.icon = tray_icon::compose(&status);
+        }
+    }
+
+    /// Reloads the profiles, which may have been changed by another process
+    fn reload_profiles(&mut self) {
+        let profiles = match Profiles::load() {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                println!("Could not load the profiles for the tray: {e}");
+                return;
+            }
+        };
+        self.profiles = profiles.profiles.keys().cloned().collect();
+        self.active_profile = self
+            .profiles
+            .iter()
+            .position(|name| *name == profiles.active)
+            .unwrap_or(0);
+        self.pools = match profiles.get(None) {
+            Ok((_, settings)) => settings.url.iter().map(Url::label).collect(),
+            Err(_) => Vec::new(),
+        };
+    }
+
+    /// Shows the window attached to the miner, or opens one in a new process
+    fn show_window(&self) {
+        // Sending fails if there is no window to receive it
+        if self.miner.show_window_tx.send(()).is_ok() {
+            return;
+        }
+        let spawned =
+            std::env::current_exe().and_then(|exe| std::process::Command::new(exe).spawn());
+        if let Err(e) = spawned {
+            println!("Could not open the window: {e}");
+        }
+    }
+
+    /// Starts the miner outside of the mining schedule, until its next window
+    fn mine_now(&mut self) {
+        let saved = AppOptions::load().and_then(|mut options| {
+            options.schedule.mine_now(SystemClock.now());
+            options.save()
+        });
+        match saved {
+            Ok(()) => {
+                let _guard = self.tokio_handle.enter();
+                if let Err(e) = self.miner.start_active_profile() {
+                    println!("Could not start the miner: {e}");
+                }
+            }
+            Err(e) => println!("Could not save the mining schedule: {e}"),
+        }
+        self.refresh();
+    }
+
+    /// Makes the pool the first one ethminer tries, restarting the miner if it is running
+    fn use_pool(&mut self, index: usize) {
+        let saved = Profiles::load().and_then(|mut profiles| {
+            let mut settings = profiles.get(None)?.1.clone();
+            settings.move_pool_first(index);
+            profiles.set_active_settings(settings);
+            profiles.save()
+        });
+        match saved {
+            Ok(()) if self.miner.state().running => {
+                let _guard = self.tokio_handle.enter();
+                self.miner.restart();
+            }
+            Ok(()) => {}
+            Err(e) => println!("Could not switch the pool: {e}"),
+        }
+        self.refresh();
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -22,77 +171,443 @@ impl ksni::Tray for MinerTrayLinux {
     }
 
     fn title(&self) -> String {
-        "Mine ether using a gui application".into()
+        status_title(&self.miner.state(), &self.miner.stats())
+    }
+
+    fn tool_tip(&self) -> ksni::ToolTip {
+        let state = self.miner.state();
+        let stats = self.miner.stats();
+        let mut description = status_description(&state, &stats);
+        if let Some(status) = self.schedule.status(SystemClock.now()) {
+            description = format!("{description}\n{status}");
+        }
+        if let Some(alert) = &self.alert {
+            description = format!("{}\n{description}", alert_text(alert));
+        }
+        ksni::ToolTip {
+            title: status_title(&state, &stats),
+            description,
+            ..Default::default()
+        }
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        self.show_window();
     }
 
     fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
-        vec![
+        let running = self.miner.state().running;
+        let mut menu = Vec::new();
+        if let Some(alert) = &self.alert {
+            menu.push(
+                StandardItem {
+                    label: format!("{} (Dismiss)", alert_text(alert)),
+                    activate: Box::new(|this: &mut Self| this.alert = None),
+                    icon_name: "dialog-warning".into(),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        if self.auto_start.pending().is_some() {
+            menu.push(
+                StandardItem {
+                    label: "Cancel Starting on Launch".into(),
+                    activate: Box::new(|this: &mut Self| this.auto_start.cancel()),
+                    icon_name: "process-stop".into(),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        if let Some(status) = self.schedule.status(SystemClock.now()) {
+            menu.push(
+                StandardItem {
+                    label: status,
+                    enabled: false,
+                    icon_name: "appointment-soon".into(),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        menu.push(
             StandardItem {
-                label: "Exit".into(),
-                icon_name: "application-exit".into(),
-                activate: Box::new(|_| std::process::exit(0)),
+                label: "Show Window".into(),
+                activate: Box::new(|this: &mut Self| this.show_window()),
+                icon_name: "window-new".into(),
                 ..Default::default()
             }
             .into(),
+        );
+        menu.push(MenuItem::Separator);
+        menu.push(
             StandardItem {
                 label: "Start Miner".into(),
+                enabled: !running,
                 activate: Box::new(|this: &mut Self| {
-                    // Sends to miner_controller spawn_tx
-                    let mc = this.miner_settings.read().unwrap().clone();
-                    this.miner_controller
-                        .blocking_lock()
-                        .spawn_tx
-                        .blocking_send(mc)
-                        .unwrap();
+                    // The tray runs on its own thread, outside of the runtime
+                    let _guard = this.tokio_handle.enter();
+                    // The profile may have been changed by a window in another process
+                    if this.miner.start_active_profile().is_err() {
+                        let settings = this.miner_settings.read().unwrap().clone();
+                        this.miner.start(active_profile_name(), settings);
+                    }
                 }),
                 icon_name: "media-playback-start".into(),
                 ..Default::default()
             }
             .into(),
+        );
+        if self.schedule.can_mine_now(SystemClock.now()) {
+            menu.push(
+                StandardItem {
+                    label: "Mine Now Until Next Window".into(),
+                    activate: Box::new(|this: &mut Self| this.mine_now()),
+                    icon_name: "media-playback-start".into(),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        menu.push(
             StandardItem {
                 label: "Stop Miner".into(),
+                enabled: running,
                 activate: Box::new(|this: &mut Self| {
-                    // Sends to miner_controller kill_tx
-                    this.miner_controller
-                        .blocking_lock()
-                        .kill_tx
-                        .blocking_send(())
-                        .unwrap();
+                    let _guard = this.tokio_handle.enter();
+                    this.miner.stop();
                 }),
                 icon_name: "process-stop".into(),
                 ..Default::default()
             }
             .into(),
-        ]
+        );
+        menu.push(
+            SubMenu {
+                label: "Profile".into(),
+                enabled: !self.profiles.is_empty(),
+                submenu: vec![RadioGroup {
+                    selected: self.active_profile,
+                    select: Box::new(|this: &mut Self, index| {
+                        if let Some(name) = this.profiles.get(index).cloned() {
+                            let _guard = this.tokio_handle.enter();
+                            this.miner.switch_profile(name);
+                        }
+                        this.refresh();
+                    }),
+                    options: self
+                        .profiles
+                        .iter()
+                        .map(|name| RadioItem {
+                            label: name.clone(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                }
+                .into()],
+                ..Default::default()
+            }
+            .into(),
+        );
+        menu.push(
+            SubMenu {
+                label: "Pool".into(),
+                enabled: !self.pools.is_empty(),
+                submenu: vec![RadioGroup {
+                    selected: 0,
+                    select: Box::new(|this: &mut Self, index| this.use_pool(index)),
+                    options: self
+                        .pools
+                        .iter()
+                        .map(|pool| RadioItem {
+                            label: pool.clone(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                }
+                .into()],
+                ..Default::default()
+            }
+            .into(),
+        );
+        menu.push(MenuItem::Separator);
+        menu.push(
+            StandardItem {
+                label: "Open Log Folder".into(),
+                activate: Box::new(|_: &mut Self| open_log_folder()),
+                icon_name: "folder-open".into(),
+                ..Default::default()
+            }
+            .into(),
+        );
+        menu.push(
+            StandardItem {
+                label: "Exit".into(),
+                icon_name: "application-exit".into(),
+                activate: Box::new(|this: &mut Self| {
+                    // Stops a miner running in this process, so it isn't left behind
+                    this.tokio_handle.block_on(this.miner.shutdown());
+                    if !this.miner.is_remote() {
+                        launch::record_clean_exit();
+                    }
+                    std::process::exit(0)
+                }),
+                ..Default::default()
+            }
+            .into(),
+        );
+        menu
     }
 
     fn icon_pixmap(&self) -> Vec<ksni::Icon> {
-        let icon_data = get_icon_argb().to_vec();
         let icon: ksni::Icon = ksni::Icon {
-            width: 64,
-            height: 64,
-            data: icon_data,
+            width: WIDTH as i32,
+            height: HEIGHT as i32,
+            data: self.icon.clone(),
         };
         vec![icon]
     }
 }
 
+/// Summary of the miner, e.g. "Mining at 20.00 MH/s"
+#[cfg(target_os = "linux")]
+fn status_title(state: &MinerState, stats: &StatsSnapshot) -> String {
+    if state.running {
+        format!(
+            "Mining at {}",
+            stats::format_hashrate(stats.current_hashrate)
+        )
+    } else if !state.paused.is_empty() {
+        "Paused".to_string()
+    } else {
+        "Not mining".to_string()
+    }
+}
+
+/// The profile and the shares of the miner
+#[cfg(target_os = "linux")]
+fn status_description(state: &MinerState, stats: &StatsSnapshot) -> String {
+    let profile = match &state.profile {
+        Some(profile) if state.running => format!("Profile \"{profile}\"\n"),
+        _ => String::new(),
+    };
+    let paused: String = state
+        .paused
+        .iter()
+        .map(|reason| format!("Paused, as {reason}\n"))
+        .collect();
+    format!(
+        "{profile}{paused}{} accepted, {} rejected",
+        stats.accepted, stats.rejected
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn alert_text(alert: &Alert) -> String {
+    format!("{}: {}", alert.severity.label(), alert.message)
+}
+
+/// Opens the directory of the logs and state files in the file manager
+#[cfg(target_os = "linux")]
+pub fn open_log_folder() {
+    let dir = profiles::state_dir();
+    let opened = std::fs::create_dir_all(&dir)
+        .and_then(|_| std::process::Command::new("xdg-open").arg(&dir).spawn());
+    if let Err(e) = opened {
+        println!("Could not open {}: {e}", dir.display());
+    }
+}
+
 #[cfg(target_os = "linux")]
 pub fn start_tray_linux(
     ms: Arc<RwLock<MinerSettings>>,
-    mc: Arc<Mutex<MinerController>>,
+    miner: MinerHandle,
+    auto_start: AutoStart,
     tokio_handle: Handle,
 ) {
-    let service = ksni::TrayService::new(MinerTrayLinux {
-        miner_controller: mc,
-        miner_settings: ms,
-        tokio_handle,
+    let service = ksni::TrayService::new(MinerTrayLinux::new(
+        ms,
+        miner.clone(),
+        tokio_handle.clone(),
+        auto_start.clone(),
+    ));
+    refresh_tray(service.handle(), miner, auto_start, &tokio_handle);
+    service.spawn();
+}
+
+/// Refreshes the tray right away when the miner starts or stops, or starting on launch
+/// is scheduled or cancelled, and every REFRESH_INTERVAL for the hashrate and profiles
+#[cfg(target_os = "linux")]
+fn refresh_tray(
+    handle: ksni::Handle<MinerTrayLinux>,
+    miner: MinerHandle,
+    auto_start: AutoStart,
+    tokio_handle: &Handle,
+) {
+    let mut updated = miner.updated_tx.subscribe();
+    tokio_handle.spawn(async move {
+        // Alerts of a miner in another instance are shown by that instance's tray
+        let mut events = miner.subscribe_events().await;
+        let status = || (miner.state().running, auto_start.pending().is_some());
+        let mut last = status();
+        let mut refreshed = Instant::now();
+        loop {
+            tokio::select! {
+                received = updated.recv() => {
+                    if let Err(tokio::sync::broadcast::error::RecvError::Closed) = received {
+                        return;
+                    }
+                }
+                Ok(MinerEvent::Alert(alert)) = next_event(&mut events) => {
+                    handle.update(move |tray| tray.alert = Some(alert.clone()));
+                }
+                _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+            }
+            if status() != last || refreshed.elapsed() >= REFRESH_INTERVAL {
+                last = status();
+                refreshed = Instant::now();
+                handle.update(MinerTrayLinux::refresh);
+            }
+        }
     });
-    let handle = service.handle();
+}
+
+/// The next event of the miner, or never if there are no events to receive
+#[cfg(target_os = "linux")]
+async fn next_event(
+    events: &mut Option<tokio::sync::broadcast::Receiver<MinerEvent>>,
+) -> Result<MinerEvent, tokio::sync::broadcast::error::RecvError> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Attaches to the instance running the miner, or starts one minimized to the tray and
+/// attaches to it, so closing the window leaves the miner running in the tray.
+/// Falls back to running the miner in this process if the minimized instance doesn't start.
+#[cfg(target_os = "linux")]
+pub async fn attach_or_spawn_minimized() -> Result<MinerHandle, String> {
+    let path = crate::ipc::socket_path();
+    if let Ok(miner) = MinerHandle::attach(&path).await {
+        println!("Attached to the miner controller at {}", path.display());
+        return Ok(miner);
+    }
+    let spawned = std::env::current_exe()
+        .and_then(|exe| std::process::Command::new(exe).arg("--minimized").spawn());
+    match spawned {
+        Ok(_) => match miner_handle::wait_and_attach(&path).await {
+            Ok(miner) => {
+                println!("Attached to the minimized instance");
+                return Ok(miner);
+            }
+            Err(e) => println!("The minimized instance did not start: {e}"),
+        },
+        Err(e) => println!("Could not start the minimized instance: {e}"),
+    }
+    miner_handle::attach_or_start().await
+}
+
+/// Runs the miner with only the tray, until it is exited from the tray or a signal is received.
+/// Returns the exit code of the process.
+#[cfg(target_os = "linux")]
+pub async fn run_minimized(profile: Option<&str>, start_mining: bool) -> i32 {
+    let miner = match miner_handle::attach_or_start().await {
+        Ok(miner) => miner,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+    let crash_loop = miner_handle::start_tasks(&miner).await;
+    notifications::start(&miner).await;
+    let auto_start = AutoStart::default();
+    if let Err(e) = miner.launch(profile, start_mining, false).await {
+        println!("{e}");
+    }
+    if !start_mining {
+        if let Some(message) = auto_start.schedule(&miner, crash_loop) {
+            println!("{message}");
+        }
+    }
+    // The instance that is already running has its own tray
+    if miner.is_remote() {
+        println!("ethminer-gui is already running");
+        return 0;
+    }
+
+    let settings = profiles::load_profile(&profiles::Profiles::path(), None)
+        .map(|(_, settings)| settings)
+        .unwrap_or_default();
+    let service = ksni::TrayService::new(MinerTrayLinux::new(
+        Arc::new(RwLock::new(settings)),
+        miner.clone(),
+        Handle::current(),
+        auto_start.clone(),
+    ));
+    refresh_tray(
+        service.handle(),
+        miner.clone(),
+        auto_start,
+        &Handle::current(),
+    );
     service.spawn();
+
+    match Signals::new() {
+        Ok(mut signals) => println!("Received {}, stopping", signals.recv().await),
+        Err(e) => {
+            println!("Could not listen for signals: {e}");
+            return 1;
+        }
+    }
+    miner.shutdown().await;
+    launch::record_clean_exit();
+    let _ = std::fs::remove_file(crate::ipc::socket_path());
+    0
 }
 
 #[cfg(target_os = "windows")]
 pub fn start_tray_windows() {
     !todo();
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_text() {
+        let mut state = MinerState {
+            running: true,
+            profile: Some("default".to_string()),
+            pid: Some(42),
+            paused: Vec::new(),
+        };
+        let stats = StatsSnapshot {
+            current_hashrate: 20e6,
+            accepted: 12,
+            rejected: 1,
+            ..Default::default()
+        };
+        assert_eq!(status_title(&state, &stats), "Mining at 20.00 MH/s");
+        assert_eq!(
+            status_description(&state, &stats),
+            "Profile \"default\"\n12 accepted, 1 rejected"
+        );
+
+        state.running = false;
+        assert_eq!(status_title(&state, &stats), "Not mining");
+        assert_eq!(
+            status_description(&state, &stats),
+            "12 accepted, 1 rejected"
+        );
+
+        state.paused = vec!["electricity costs 0.30 EUR/kWh, more than 0.25 EUR".to_string()];
+        assert_eq!(status_title(&state, &stats), "Paused");
+        assert_eq!(
+            status_description(&state, &stats),
+            "Paused, as electricity costs 0.30 EUR/kWh, more than 0.25 EUR\n12 accepted, 1 rejected"
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use crate::icon_data::{get_icon_argb, CHANNELS, HEIGHT, WIDTH};
+use crate::miner_controller::MinerState;
+use crate::stats::StatsSnapshot;
+
+use std::time::{Duration, Instant};
+
+/// How long the miner shows as restarting after it was restarted because it crashed
+const RECOVERY: Duration = Duration::from_secs(60);
+/// Number of levels of the hashrate bar, so the icon isn't regenerated for every small change
+const BAR_LEVELS: u8 = 8;
+
+/// Center and radius of the state badge in the bottom right corner of the icon
+const BADGE_CENTER: (f32, f32) = (51.5, 51.5);
+const BADGE_RADIUS: f32 = 11.0;
+/// The hashrate bar along the bottom left of the icon, left of the badge
+const BAR_X: std::ops::Range<usize> = 2..38;
+const BAR_Y: std::ops::Range<usize> = 57..63;
+
+/// Colors as alpha, red, green and blue, the byte order of the tray icon pixmap
+const OUTLINE: [u8; 4] = [255, 32, 32, 32];
+const BAR_BACKGROUND: [u8; 4] = [160, 0, 0, 0];
+
+/// The state of the miner as shown by the color of the tray icon's badge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IconState {
+    Stopped,
+    Running,
+    /// Restarted after a crash, and hasn't been running for RECOVERY yet
+    Restarting,
+    /// Crashed, and isn't running again
+    Crashed,
+    /// Running, but a GPU stopped mining at the --tstop temperature
+    Overheated,
+    /// Stopped by a policy, such as the electricity price, and resumed once it allows.
+    /// Shown apart from Stopped, as the miner starts again by itself
+    Paused,
+}
+
+impl IconState {
+    pub fn color(self) -> [u8; 4] {
+        match self {
+            IconState::Stopped => [255, 128, 128, 128],
+            IconState::Running => [255, 46, 204, 64],
+            IconState::Restarting => [255, 255, 176, 0],
+            IconState::Crashed | IconState::Overheated => [255, 220, 40, 40],
+            IconState::Paused => [255, 64, 128, 224],
+        }
+    }
+}
+
+/// What the tray icon shows. The icon only needs to be regenerated when this changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IconStatus {
+    pub state: IconState,
+    /// Current hashrate relative to the peak of the session, in 0..=BAR_LEVELS,
+    /// or None when there is nothing to show
+    pub bar: Option<u8>,
+}
+
+impl Default for IconStatus {
+    fn default() -> Self {
+        IconStatus {
+            state: IconState::Stopped,
+            bar: None,
+        }
+    }
+}
+
+/// Follows the miner between refreshes of the tray, to tell crashes from intentional stops.
+/// Works the same for a miner in this process and one in another instance, as it only
+/// uses the state and the crash count of the statistics.
+#[derive(Default)]
+pub struct IconTracker {
+    running: bool,
+    crashes: u64,
+    crashed_at: Option<Instant>,
+}
+
+impl IconTracker {
+    pub fn new(stats: &StatsSnapshot) -> IconTracker {
+        // Crashes from before the tray was started don't count
+        IconTracker {
+            crashes: stats.crashes,
+            ..Default::default()
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        state: &MinerState,
+        stats: &StatsSnapshot,
+        now: Instant,
+    ) -> IconStatus {
+        if stats.crashes > self.crashes {
+            self.crashed_at = Some(now);
+        } else if self.running && !state.running {
+            // Stopped without a crash being counted, so it was stopped intentionally
+            self.crashed_at = None;
+        }
+        self.crashes = stats.crashes;
+        self.running = state.running;
+
+        let recovering = self
+            .crashed_at
+            .is_some_and(|crashed_at| now.duration_since(crashed_at) < RECOVERY);
+        let state = match (state.running, self.crashed_at) {
+            (true, _) if !stats.thermal_stopped.is_empty() => IconState::Overheated,
+            (true, Some(_)) if recovering => IconState::Restarting,
+            (true, _) => IconState::Running,
+            (false, Some(_)) => IconState::Crashed,
+            (false, None) if !state.paused.is_empty() => IconState::Paused,
+            (false, None) => IconState::Stopped,
+        };
+        IconStatus {
+            state,
+            bar: hashrate_level(stats).filter(|_| self.running),
+        }
+    }
+}
+
+fn hashrate_level(stats: &StatsSnapshot) -> Option<u8> {
+    if stats.peak_hashrate <= 0.0 {
+        return None;
+    }
+    let fraction = (stats.current_hashrate / stats.peak_hashrate).clamp(0.0, 1.0);
+    Some((fraction * BAR_LEVELS as f64).round() as u8)
+}
+
+/// Draws the state badge and hashrate bar over the app icon, as a 64x64 ARGB pixmap
+pub fn compose(status: &IconStatus) -> Vec<u8> {
+    let mut pixmap = get_icon_argb().to_vec();
+    let color = status.state.color();
+
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let dx = x as f32 + 0.5 - BADGE_CENTER.0;
+            let dy = y as f32 + 0.5 - BADGE_CENTER.1;
+            let distance = (dx * dx + dy * dy).sqrt();
+            if distance <= BADGE_RADIUS - 2.0 {
+                set_pixel(&mut pixmap, x, y, color);
+            } else if distance <= BADGE_RADIUS {
+                // Keeps the badge visible on light and dark panels
+                set_pixel(&mut pixmap, x, y, OUTLINE);
+            }
+        }
+    }
+
+    if let Some(level) = status.bar {
+        let filled = BAR_X.len() * level as usize / BAR_LEVELS as usize;
+        for y in BAR_Y {
+            for (i, x) in BAR_X.enumerate() {
+                let pixel = if i < filled { color } else { BAR_BACKGROUND };
+                set_pixel(&mut pixmap, x, y, pixel);
+            }
+        }
+    }
+    pixmap
+}
+
+fn set_pixel(pixmap: &mut [u8], x: usize, y: usize, argb: [u8; 4]) {
+    let i = (y * WIDTH + x) * CHANNELS;
+    pixmap[i..i + CHANNELS].copy_from_slice(&argb);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(pixmap: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let i = (y * WIDTH + x) * CHANNELS;
+        pixmap[i..i + CHANNELS].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_compose_states() {
+        let base = get_icon_argb();
+        for state in [
+            IconState::Stopped,
+            IconState::Running,
+            IconState::Restarting,
+            IconState::Crashed,
+            IconState::Overheated,
+            IconState::Paused,
+        ] {
+            let pixmap = compose(&IconStatus { state, bar: None });
+            assert_eq!(pixmap.len(), WIDTH * HEIGHT * CHANNELS);
+            assert_eq!(pixel(&pixmap, 51, 51), state.color(), "{state:?}");
+            assert_eq!(pixel(&pixmap, 51, 41), OUTLINE, "{state:?}");
+            // Only the corner of the badge is drawn over
+            assert_eq!(pixel(&pixmap, 10, 10), pixel(&base, 10, 10));
+            assert_eq!(pixel(&pixmap, 20, 60), pixel(&base, 20, 60));
+        }
+        assert_ne!(IconState::Running.color(), IconState::Stopped.color());
+        assert_ne!(IconState::Restarting.color(), IconState::Crashed.color());
+        assert_eq!(IconState::Overheated.color(), IconState::Crashed.color());
+        assert_ne!(IconState::Paused.color(), IconState::Stopped.color());
+    }
+
+    #[test]
+    fn test_compose_hashrate_bar() {
+        let state = IconState::Running;
+        let pixmap = compose(&IconStatus {
+            state,
+            bar: Some(BAR_LEVELS / 2),
+        });
+        assert_eq!(pixel(&pixmap, BAR_X.start, BAR_Y.start), state.color());
+        assert_eq!(pixel(&pixmap, 19, BAR_Y.end - 1), state.color());
+        assert_eq!(pixel(&pixmap, 20, BAR_Y.start), BAR_BACKGROUND);
+        assert_eq!(pixel(&pixmap, BAR_X.end - 1, BAR_Y.start), BAR_BACKGROUND);
+
+        let full = compose(&IconStatus {
+            state,
+            bar: Some(BAR_LEVELS),
+        });
+        assert_eq!(pixel(&full, BAR_X.end - 1, BAR_Y.start), state.color());
+    }
+
+    #[test]
+    fn test_tracker_states() {
+        let start = Instant::now();
+        let running = MinerState {
+            running: true,
+            ..Default::default()
+        };
+        let stopped = MinerState::default();
+        let mut stats = StatsSnapshot {
+            crashes: 2,
+            ..Default::default()
+        };
+        let mut tracker = IconTracker::new(&stats);
+        assert_eq!(
+            tracker.update(&stopped, &stats, start).state,
+            IconState::Stopped
+        );
+        assert_eq!(
+            tracker.update(&running, &stats, start).state,
+            IconState::Running
+        );
+
+        // The crashed miner is seen again before it was restarted
+        stats.crashes = 3;
+        assert_eq!(
+            tracker.update(&stopped, &stats, start).state,
+            IconState::Crashed
+        );
+        let later = start + Duration::from_secs(5);
+        assert_eq!(
+            tracker.update(&running, &stats, later).state,
+            IconState::Restarting
+        );
+        let recovered = start + RECOVERY;
+        assert_eq!(
+            tracker.update(&running, &stats, recovered).state,
+            IconState::Running
+        );
+
+        // A crash and restart between two refreshes
+        stats.crashes = 4;
+        assert_eq!(
+            tracker.update(&running, &stats, recovered).state,
+            IconState::Restarting
+        );
+        // Stopping the miner clears the crash
+        assert_eq!(
+            tracker.update(&stopped, &stats, recovered).state,
+            IconState::Stopped
+        );
+
+        // A GPU stopped at the --tstop temperature
+        stats.thermal_stopped = vec![1];
+        assert_eq!(
+            tracker.update(&running, &stats, recovered).state,
+            IconState::Overheated
+        );
+        stats.thermal_stopped.clear();
+
+        // Held by a policy until it allows mining again
+        let paused = MinerState {
+            paused: vec!["electricity costs too much".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            tracker.update(&paused, &stats, recovered).state,
+            IconState::Paused
+        );
+    }
+
+    #[test]
+    fn test_tracker_hashrate_bar() {
+        let running = MinerState {
+            running: true,
+            ..Default::default()
+        };
+        let mut stats = StatsSnapshot::default();
+        let mut tracker = IconTracker::new(&stats);
+        let now = Instant::now();
+        assert_eq!(tracker.update(&running, &stats, now).bar, None);
+
+        stats.peak_hashrate = 40e6;
+        stats.current_hashrate = 30e6;
+        assert_eq!(tracker.update(&running, &stats, now).bar, Some(6));
+        // Small changes don't change the icon
+        stats.current_hashrate = 30.5e6;
+        assert_eq!(tracker.update(&running, &stats, now).bar, Some(6));
+        assert_eq!(
+            tracker.update(&MinerState::default(), &stats, now).bar,
+            None
+        );
+    }
+}
@@ -0,0 +1,597 @@
+use crate::ansi::{AnsiColor, AnsiStyle, StyledLine};
+use crate::launch;
+use crate::miner_controller::MinerState;
+use crate::miner_handle::{attach_or_start, start_tasks, MinerHandle};
+use crate::output_buffer::OutputSnapshot;
+use crate::profiles::Profiles;
+use crate::stats::{self, StatsSnapshot};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans};
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Sparkline};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration};
+
+/// Number of hashrate samples kept for the sparkline, one per second
+const HASHRATE_SAMPLES: usize = 600;
+
+const HELP: &str =
+    "s Start  x Stop  r Restart  p Profiles  ↑↓ PgUp PgDn Scroll  End Follow  q Quit";
+
+/// Runs the terminal interface until it is quit, returning the exit code of the process.
+/// Like the GUI, it attaches to a running instance if there is one.
+pub async fn run_tui(profile: Option<&str>, start_mining: bool) -> i32 {
+    let miner = match attach_or_start().await {
+        Ok(miner) => miner,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+    // Asked to mine or not, the terminal doesn't start on launch like the GUI
+    start_tasks(&miner).await;
+    let launched = miner.launch(profile, start_mining, false).await;
+    let mut terminal = match TerminalGuard::enter() {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            eprintln!("Could not set up the terminal: {e}");
+            return 1;
+        }
+    };
+    let mut tui = Tui::new(miner.clone());
+    tui.message = launched.err();
+    let result = tui.run(&mut terminal.terminal).await;
+    drop(terminal);
+    // Like closing the GUI, this stops a miner running in this process
+    miner.shutdown().await;
+    if !miner.is_remote() {
+        launch::record_clean_exit();
+    }
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Terminal error: {e}");
+            1
+        }
+    }
+}
+
+/// State of the terminal interface
+struct Tui {
+    miner: MinerHandle,
+    snapshot: OutputSnapshot,
+    /// Hashrate once a second, in hashes per second
+    hashrates: VecDeque<u64>,
+    /// Sequence number of the bottom line of the output while scrolled back,
+    /// None while following the new lines
+    anchor: Option<u64>,
+    /// Height of the output the last time it was drawn, scrolled by page up and down
+    page: usize,
+    /// An error or confirmation shown instead of the key bindings until the next key press
+    message: Option<String>,
+    picker: Option<ProfilePicker>,
+}
+
+/// Popup listing the profiles to switch to
+struct ProfilePicker {
+    names: Vec<String>,
+    active: String,
+    state: ListState,
+}
+
+impl Tui {
+    fn new(miner: MinerHandle) -> Tui {
+        Tui {
+            miner,
+            snapshot: OutputSnapshot::default(),
+            hashrates: VecDeque::with_capacity(HASHRATE_SAMPLES),
+            anchor: None,
+            page: 10,
+            message: None,
+            picker: None,
+        }
+    }
+
+    async fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
+        // Reading the terminal blocks, so it is done on its own thread
+        let (input_tx, mut input_rx) = mpsc::channel(16);
+        std::thread::spawn(move || loop {
+            let input = event::read();
+            let failed = input.is_err();
+            if input_tx.blocking_send(input).is_err() || failed {
+                return;
+            }
+        });
+        let mut updated_rx = self.miner.updated_tx.subscribe();
+        let mut error_rx = self.miner.error_tx.subscribe();
+        let mut ticks = interval(Duration::from_secs(1));
+
+        loop {
+            terminal.draw(|f| self.draw(f))?;
+            tokio::select! {
+                input = input_rx.recv() => match input {
+                    Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                        if !self.handle_key(key) {
+                            return Ok(());
+                        }
+                    }
+                    // Other events, e.g. resizing, only need a redraw
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e),
+                    None => return Ok(()),
+                },
+                _ = updated_rx.recv() => {}
+                error = error_rx.recv() => {
+                    if let Ok(error) = error {
+                        self.message = Some(error);
+                    }
+                }
+                _ = ticks.tick() => self.sample(),
+            }
+        }
+    }
+
+    /// Records the current hashrate for the sparkline
+    fn sample(&mut self) {
+        if self.hashrates.len() == HASHRATE_SAMPLES {
+            self.hashrates.pop_front();
+        }
+        self.hashrates
+            .push_back(self.miner.stats().current_hashrate as u64);
+    }
+
+    /// Returns false if the interface should quit
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if let Some(picker) = self.picker.as_mut() {
+            let selected = picker.state.selected().unwrap_or(0);
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => {
+                    picker.state.select(Some(selected.saturating_sub(1)))
+                }
+                KeyCode::Down | KeyCode::Char('j') => picker
+                    .state
+                    .select(Some((selected + 1).min(picker.names.len() - 1))),
+                KeyCode::Enter => {
+                    let name = picker.names[selected].clone();
+                    self.picker = None;
+                    self.message = Some(format!("Switched to profile \"{name}\""));
+                    self.miner.switch_profile(name);
+                }
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('p') => self.picker = None,
+                _ => {}
+            }
+            return true;
+        }
+
+        self.message = None;
+        match key.code {
+            KeyCode::Char('q') => return false,
+            // Raw mode turns ctrl-c into a key press instead of SIGINT
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char('s') => {
+                if let Err(e) = self.miner.start_active_profile() {
+                    self.message = Some(e);
+                }
+            }
+            KeyCode::Char('x') => self.miner.stop(),
+            KeyCode::Char('r') => self.miner.restart(),
+            KeyCode::Char('p') => self.open_picker(),
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_up(1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_down(1),
+            KeyCode::PageUp => self.scroll_up(self.page),
+            KeyCode::PageDown => self.scroll_down(self.page),
+            KeyCode::Home | KeyCode::Char('g') => self.anchor = Some(self.snapshot.first_seq()),
+            KeyCode::End | KeyCode::Char('G') => self.anchor = None,
+            _ => {}
+        }
+        true
+    }
+
+    fn open_picker(&mut self) {
+        match Profiles::load() {
+            Ok(profiles) if profiles.profiles.is_empty() => {
+                self.message =
+                    Some("There are no profiles, apply settings in the GUI to create one".into())
+            }
+            Ok(profiles) => {
+                let names: Vec<String> = profiles.profiles.keys().cloned().collect();
+                let mut state = ListState::default();
+                state.select(Some(
+                    names
+                        .iter()
+                        .position(|n| *n == profiles.active)
+                        .unwrap_or(0),
+                ));
+                self.picker = Some(ProfilePicker {
+                    names,
+                    active: profiles.active,
+                    state,
+                });
+            }
+            Err(e) => self.message = Some(e),
+        }
+    }
+
+    /// Sequence number of the newest line, None if there is no output
+    fn last_seq(&self) -> Option<u64> {
+        (self.snapshot.first_seq() + self.snapshot.lines.len() as u64).checked_sub(1)
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        if let Some(last) = self.last_seq() {
+            let bottom = self.anchor.unwrap_or(last);
+            self.anchor = Some(
+                bottom
+                    .saturating_sub(lines as u64)
+                    .max(self.snapshot.first_seq()),
+            );
+        }
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        if let (Some(anchor), Some(last)) = (self.anchor, self.last_seq()) {
+            let bottom = anchor + lines as u64;
+            self.anchor = if bottom >= last { None } else { Some(bottom) };
+        }
+    }
+
+    fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        self.miner
+            .buffer
+            .lock()
+            .unwrap()
+            .update_snapshot(&mut self.snapshot);
+        let state = self.miner.state();
+        let stats = self.miner.stats();
+
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Length(6),
+                Constraint::Min(3),
+                Constraint::Length(1),
+            ])
+            .split(f.size());
+        f.render_widget(self.header(&state, &stats), rows[0]);
+
+        let charts = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(20), Constraint::Length(22)])
+            .split(rows[1]);
+        // The sparkline draws the first samples that fit, so only the newest are passed
+        let width = charts[0].width.saturating_sub(2) as usize;
+        let samples = self.hashrates.make_contiguous();
+        let sparkline = Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "Hashrate {}",
+                stats::format_hashrate(stats.current_hashrate)
+            )))
+            .data(&samples[samples.len().saturating_sub(width)..])
+            .style(Style::default().fg(Color::Green));
+        f.render_widget(sparkline, charts[0]);
+        f.render_widget(shares(&stats), charts[1]);
+
+        self.draw_output(f, rows[2]);
+
+        let footer = match &self.message {
+            Some(message) => {
+                Paragraph::new(message.as_str()).style(Style::default().fg(Color::Red))
+            }
+            None => Paragraph::new(HELP).style(Style::default().fg(Color::DarkGray)),
+        };
+        f.render_widget(footer, rows[3]);
+
+        if let Some(picker) = self.picker.as_mut() {
+            let height = picker.names.len() as u16 + 2;
+            let area = centered(f.size(), 40, height);
+            let items: Vec<ListItem> = picker
+                .names
+                .iter()
+                .map(|name| {
+                    let active = if *name == picker.active { "*" } else { " " };
+                    ListItem::new(format!("{active} {name}"))
+                })
+                .collect();
+            let list = List::new(items)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Switch profile"),
+                )
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            f.render_widget(Clear, area);
+            f.render_stateful_widget(list, area, &mut picker.state);
+        }
+    }
+
+    fn header(&self, state: &MinerState, stats: &StatsSnapshot) -> Paragraph<'static> {
+        let mut spans = match state.pid {
+            Some(pid) if state.running => vec![
+                Span::styled(
+                    "● Running",
+                    Style::default()
+                        .fg(Color::Green)
+                        .add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(format!("  pid {pid}")),
+            ],
+            _ => vec![Span::styled(
+                "■ Stopped",
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            )],
+        };
+        let profile = state.profile.as_deref().unwrap_or("-");
+        spans.push(Span::raw(format!(
+            "  Profile {profile}  Uptime {}",
+            stats::format_duration(stats.uptime())
+        )));
+        if self.miner.is_remote() {
+            spans.push(Span::styled(
+                "  Attached to a running miner controller",
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        Paragraph::new(Spans::from(spans))
+            .block(Block::default().borders(Borders::ALL).title("ethminer-gui"))
+    }
+
+    fn draw_output<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        self.page = height.max(1);
+        let len = self.snapshot.lines.len();
+        // Index after the bottom line, the anchor may have been dropped from the buffer since
+        let end = match self.anchor {
+            Some(anchor) => {
+                (anchor.saturating_sub(self.snapshot.first_seq()) as usize + 1).min(len)
+            }
+            None => len,
+        };
+        let lines: Vec<Spans> = self
+            .snapshot
+            .lines
+            .range(end.saturating_sub(height)..end)
+            .map(|line| styled_spans(line))
+            .collect();
+        let title = match self.anchor {
+            Some(_) => "Output (scrolled back, End to follow)",
+            None => "Output",
+        };
+        f.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+            area,
+        );
+    }
+}
+
+fn shares(stats: &StatsSnapshot) -> Paragraph<'static> {
+    let ratio = match stats::accepted_ratio(stats.accepted, stats.rejected) {
+        Some(r) => format!("{:.1}%", r * 100.0),
+        None => "-".to_string(),
+    };
+    let row = |label: &str, value: String, color: Color| {
+        Spans::from(vec![
+            Span::raw(format!("{label:<10}")),
+            Span::styled(value, Style::default().fg(color)),
+        ])
+    };
+    Paragraph::new(vec![
+        row("Accepted", stats.accepted.to_string(), Color::Green),
+        row("Rejected", stats.rejected.to_string(), Color::Red),
+        row("Stale", stats.stale.to_string(), Color::Yellow),
+        row("Ratio", ratio, Color::Reset),
+    ])
+    .block(Block::default().borders(Borders::ALL).title("Shares"))
+}
+
+/// Converts a line of output to spans, keeping the colours of the escape sequences
+fn styled_spans(line: &StyledLine) -> Spans<'static> {
+    line.boundaries()
+        .windows(2)
+        .map(|range| {
+            let text = line.text[range[0]..range[1]].to_string();
+            Span::styled(text, style(line.style_at(range[0])))
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Converts an ANSI style to a terminal style. Indexed colours are passed on
+/// as indexes, so they are drawn with the palette of the user's terminal.
+fn style(ansi: AnsiStyle) -> Style {
+    let color = |c| match c {
+        AnsiColor::Indexed(i) => Color::Indexed(i),
+        AnsiColor::Rgb(r, g, b) => Color::Rgb(r, g, b),
+    };
+    let mut style = Style::default();
+    if let Some(fg) = ansi.fg {
+        style = style.fg(color(fg));
+    }
+    if let Some(bg) = ansi.bg {
+        style = style.bg(color(bg));
+    }
+    if ansi.bold {
+        style = style.add_modifier(Modifier::BOLD);
+    }
+    if ansi.underline {
+        style = style.add_modifier(Modifier::UNDERLINED);
+    }
+    style
+}
+
+/// A rect of at most the size, centered in the area
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let (width, height) = (width.min(area.width), height.min(area.height));
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+#[cfg(unix)]
+type Output = std::fs::File;
+#[cfg(not(unix))]
+type Output = io::Stdout;
+
+/// Puts the terminal in raw mode on the alternate screen, and restores it when dropped
+struct TerminalGuard {
+    terminal: Terminal<CrosstermBackend<Output>>,
+}
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        let mut output = take_stdout()?;
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(output, EnterAlternateScreen)?;
+        // Restores the terminal before the panic message is printed, so it can be read
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            default_hook(info);
+        }));
+        let mut terminal = Terminal::new(CrosstermBackend::new(output))?;
+        terminal.hide_cursor()?;
+        Ok(TerminalGuard { terminal })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = self.terminal.show_cursor();
+        restore_terminal();
+    }
+}
+
+/// Leaves raw mode and the alternate screen, and points stdout at the terminal again
+fn restore_terminal() {
+    let _ = terminal::disable_raw_mode();
+    #[cfg(unix)]
+    unsafe {
+        if let Some(fd) = *TERMINAL_FD.lock().unwrap() {
+            libc::dup2(fd, libc::STDOUT_FILENO);
+        }
+    }
+    let _ = crossterm::execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+/// Copy of the file descriptor stdout had before take_stdout redirected it
+#[cfg(unix)]
+static TERMINAL_FD: std::sync::Mutex<Option<libc::c_int>> = std::sync::Mutex::new(None);
+
+/// The controller logs with println, which would draw over the interface.
+/// So stdout is pointed at /dev/null, and the interface is drawn through a copy of it.
+#[cfg(unix)]
+fn take_stdout() -> io::Result<Output> {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    io::stdout().flush()?;
+    let null = std::fs::OpenOptions::new().write(true).open("/dev/null")?;
+    // Safe, as the descriptors are valid for the duration of the calls,
+    // and the copy is owned by the returned file
+    unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 || libc::dup2(null.as_raw_fd(), libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        *TERMINAL_FD.lock().unwrap() = Some(fd);
+        Ok(std::fs::File::from_raw_fd(libc::dup(fd)))
+    }
+}
+
+#[cfg(not(unix))]
+fn take_stdout() -> io::Result<Output> {
+    Ok(io::stdout())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner_controller::MinerController;
+    use ratatui::backend::TestBackend;
+
+    fn screen(terminal: &Terminal<TestBackend>) -> String {
+        let buffer = terminal.backend().buffer();
+        let mut out = String::new();
+        for y in 0..buffer.area.height {
+            for x in 0..buffer.area.width {
+                out += &buffer.get(x, y).symbol;
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_styled_spans() {
+        let line = StyledLine::parse("\x1b[32;1m m\x1b[0m 17:29:50 \x1b[38;2;1;2;3methminer");
+        let spans = styled_spans(&line).0;
+        let texts: Vec<&str> = spans.iter().map(|s| s.content.as_ref()).collect();
+        assert_eq!(texts, [" m", " 17:29:50 ", "ethminer"]);
+        assert_eq!(spans[0].style.fg, Some(Color::Indexed(2)));
+        assert!(spans[0].style.add_modifier.contains(Modifier::BOLD));
+        assert_eq!(spans[1].style, Style::default());
+        assert_eq!(spans[2].style.fg, Some(Color::Rgb(1, 2, 3)));
+    }
+
+    #[tokio::test]
+    async fn test_draw_and_scroll() {
+        let miner = MinerHandle::local(MinerController::new()).await;
+        for i in 0..100 {
+            let line = format!("\x1b[32m m\x1b[0m line {i}");
+            miner.buffer.lock().unwrap().push(StyledLine::parse(&line));
+        }
+        let mut tui = Tui::new(miner);
+        let mut terminal = Terminal::new(TestBackend::new(90, 24)).unwrap();
+
+        terminal.draw(|f| tui.draw(f)).unwrap();
+        let text = screen(&terminal);
+        assert!(text.contains("■ Stopped"));
+        assert!(text.contains("Accepted  0"));
+        assert!(text.contains("line 99"));
+        assert!(!text.contains("line 80"));
+
+        // Scrolling back keeps the view in place as new lines arrive
+        tui.handle_key(key(KeyCode::PageUp));
+        tui.miner
+            .buffer
+            .lock()
+            .unwrap()
+            .push(StyledLine::plain("new line"));
+        terminal.draw(|f| tui.draw(f)).unwrap();
+        let text = screen(&terminal);
+        assert!(text.contains("scrolled back"));
+        assert!(!text.contains("line 99"));
+        assert!(!text.contains("new line"));
+
+        tui.handle_key(key(KeyCode::End));
+        terminal.draw(|f| tui.draw(f)).unwrap();
+        assert!(screen(&terminal).contains("new line"));
+
+        tui.picker = Some(ProfilePicker {
+            names: vec!["backup".to_string(), "default".to_string()],
+            active: "default".to_string(),
+            state: ListState::default(),
+        });
+        tui.handle_key(key(KeyCode::Down));
+        terminal.draw(|f| tui.draw(f)).unwrap();
+        assert!(screen(&terminal).contains("* default"));
+        assert!(tui.handle_key(key(KeyCode::Esc)));
+        assert!(tui.picker.is_none());
+        assert!(!tui.handle_key(key(KeyCode::Char('q'))));
+    }
+}
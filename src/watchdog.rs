@@ -0,0 +1,221 @@
+use crate::miner_settings::WatchdogSettings;
+use crate::stats::SessionStats;
+
+use std::time::{Duration, Instant};
+
+/// Why the watchdog considers the miner stalled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stall {
+    NoOutput(Duration),
+    ZeroHashrate(Duration),
+    NoJob(Duration),
+}
+
+impl std::fmt::Display for Stall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (what, duration) = match self {
+            Stall::NoOutput(duration) => ("no output", duration),
+            Stall::ZeroHashrate(duration) => ("zero hashrate", duration),
+            Stall::NoJob(duration) => ("no new job", duration),
+        };
+        match duration.as_secs() / 60 {
+            1 => write!(f, "{what} for 1 minute"),
+            minutes => write!(f, "{what} for {minutes} minutes"),
+        }
+    }
+}
+
+/// Notices a miner that is running but not mining, which the controller can't tell from
+/// the process alone. Made for one run of the miner, as it times the checks from its start.
+pub struct Watchdog {
+    settings: WatchdogSettings,
+    started: Instant,
+    /// Since when the hashrate has been zero
+    zero_since: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn new(settings: &WatchdogSettings, started: Instant) -> Watchdog {
+        Watchdog {
+            settings: settings.clone(),
+            started,
+            zero_since: Some(started),
+        }
+    }
+
+    /// Returns why the miner should be restarted, if it stalled
+    pub fn check(&mut self, session: &SessionStats, now: Instant) -> Option<Stall> {
+        // The statistics outlive the run, so anything from before it started doesn't count
+        let since = |time: Option<Instant>| time.map_or(self.started, |t| t.max(self.started));
+        let exceeds = |since: Instant, mins: u32| {
+            let limit = Duration::from_secs(mins as u64 * 60);
+            mins > 0 && now.duration_since(since) >= limit
+        };
+
+        // GPUs stopped at the --tstop temperature hash nothing until they cooled down, and
+        // a restart would only heat them up again
+        if session.current_hashrate > 0.0 || !session.thermal_stopped.is_empty() {
+            self.zero_since = None;
+        } else if self.zero_since.is_none() {
+            self.zero_since = Some(now);
+        }
+
+        if exceeds(since(session.last_output), self.settings.no_output_mins) {
+            return Some(Stall::NoOutput(
+                now.duration_since(since(session.last_output)),
+            ));
+        }
+        // Ethminer reconnects by itself, a restart wouldn't bring the pool back
+        if session.disconnected {
+            return None;
+        }
+        if let Some(zero_since) = self.zero_since {
+            if exceeds(zero_since, self.settings.zero_hashrate_mins) {
+                return Some(Stall::ZeroHashrate(now.duration_since(zero_since)));
+            }
+        }
+        if exceeds(since(session.last_job), self.settings.no_job_mins) {
+            return Some(Stall::NoJob(now.duration_since(since(session.last_job))));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(start: Instant, minutes: u64) -> Instant {
+        start + Duration::from_secs(minutes * 60)
+    }
+
+    /// A miner that prints its hashrate and gets jobs, at the given time
+    fn mining(session: &mut SessionStats, now: Instant) {
+        session.current_hashrate = 30e6;
+        session.last_output = Some(now);
+        session.last_job = Some(now);
+    }
+
+    #[test]
+    fn test_no_output() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(&WatchdogSettings::default(), start);
+        let mut session = SessionStats::default();
+        mining(&mut session, minutes(start, 1));
+        assert_eq!(watchdog.check(&session, minutes(start, 5)), None);
+        assert_eq!(
+            watchdog.check(&session, minutes(start, 6)),
+            Some(Stall::NoOutput(Duration::from_secs(5 * 60)))
+        );
+        assert_eq!(
+            Stall::NoOutput(Duration::from_secs(5 * 60)).to_string(),
+            "no output for 5 minutes"
+        );
+        assert_eq!(
+            Stall::NoJob(Duration::from_secs(60)).to_string(),
+            "no new job for 1 minute"
+        );
+
+        // Output from an earlier run doesn't count
+        let restarted = minutes(start, 7);
+        let mut watchdog = Watchdog::new(&WatchdogSettings::default(), restarted);
+        assert_eq!(watchdog.check(&session, minutes(restarted, 4)), None);
+        assert!(watchdog.check(&session, minutes(restarted, 5)).is_some());
+    }
+
+    #[test]
+    fn test_zero_hashrate() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(&WatchdogSettings::default(), start);
+        let mut session = SessionStats::default();
+        // Generating the DAG
+        for minute in 0..10 {
+            session.last_output = Some(minutes(start, minute));
+            session.last_job = Some(minutes(start, minute));
+            assert_eq!(watchdog.check(&session, minutes(start, minute)), None);
+        }
+        mining(&mut session, minutes(start, 10));
+        assert_eq!(watchdog.check(&session, minutes(start, 10)), None);
+
+        // Hashing stops, while the miner keeps printing
+        session.current_hashrate = 0.0;
+        for minute in 11..21 {
+            session.last_output = Some(minutes(start, minute));
+            session.last_job = Some(minutes(start, minute));
+            assert_eq!(watchdog.check(&session, minutes(start, minute)), None);
+        }
+        session.last_output = Some(minutes(start, 21));
+        session.last_job = Some(minutes(start, 21));
+        assert_eq!(
+            watchdog.check(&session, minutes(start, 21)),
+            Some(Stall::ZeroHashrate(Duration::from_secs(10 * 60)))
+        );
+    }
+
+    #[test]
+    fn test_thermal_stop() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(&WatchdogSettings::default(), start);
+        let mut session = SessionStats::default();
+        mining(&mut session, minutes(start, 1));
+        assert_eq!(watchdog.check(&session, minutes(start, 1)), None);
+
+        // The only GPU is too hot, while the miner keeps printing 0.00 Mh
+        session.current_hashrate = 0.0;
+        session.thermal_stopped = vec![0];
+        for minute in 2..30 {
+            session.last_output = Some(minutes(start, minute));
+            session.last_job = Some(minutes(start, minute));
+            assert_eq!(watchdog.check(&session, minutes(start, minute)), None);
+        }
+
+        // Counted from when it resumed
+        session.thermal_stopped.clear();
+        for minute in 30..40 {
+            session.last_output = Some(minutes(start, minute));
+            session.last_job = Some(minutes(start, minute));
+            assert_eq!(watchdog.check(&session, minutes(start, minute)), None);
+        }
+        session.last_output = Some(minutes(start, 40));
+        session.last_job = Some(minutes(start, 40));
+        assert_eq!(
+            watchdog.check(&session, minutes(start, 40)),
+            Some(Stall::ZeroHashrate(Duration::from_secs(10 * 60)))
+        );
+    }
+
+    #[test]
+    fn test_no_job() {
+        let start = Instant::now();
+        let settings = WatchdogSettings {
+            no_output_mins: 0,
+            zero_hashrate_mins: 0,
+            no_job_mins: 10,
+        };
+        let mut watchdog = Watchdog::new(&settings, start);
+        let mut session = SessionStats::default();
+        mining(&mut session, minutes(start, 2));
+        assert_eq!(watchdog.check(&session, minutes(start, 11)), None);
+        assert_eq!(
+            watchdog.check(&session, minutes(start, 12)),
+            Some(Stall::NoJob(Duration::from_secs(10 * 60)))
+        );
+
+        // Without a pool there are no jobs, and ethminer reconnects by itself
+        session.disconnected = true;
+        assert_eq!(watchdog.check(&session, minutes(start, 12)), None);
+    }
+
+    #[test]
+    fn test_disabled_checks() {
+        let start = Instant::now();
+        let settings = WatchdogSettings {
+            no_output_mins: 0,
+            zero_hashrate_mins: 0,
+            no_job_mins: 0,
+        };
+        let mut watchdog = Watchdog::new(&settings, start);
+        let session = SessionStats::default();
+        assert_eq!(watchdog.check(&session, minutes(start, 24 * 60)), None);
+    }
+}
@@ -0,0 +1,642 @@
+use crate::ansi::StyledLine;
+use crate::app_options::{self, AppOptions};
+use crate::miner_controller::MinerEvent;
+use crate::miner_handle::MinerHandle;
+use crate::output_parser::connected_pool;
+use crate::output_reader::LineEvent;
+use crate::stats::{self, StatsSnapshot};
+
+use serde_json::json;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Time between checks of the statistics for hashrate drops and rejected shares
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// The hashrate has dropped below this fraction of the session average,
+/// and recovered once it is above RECOVERED_FRACTION again
+const DROP_FRACTION: f64 = 0.5;
+const RECOVERED_FRACTION: f64 = 0.8;
+/// Number of rejected shares within SPIKE_WINDOW that make a spike
+const SPIKE_REJECTED: u64 = 5;
+const SPIKE_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Longest time a request may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The events that webhooks can be sent for
+#[derive(
+    serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    Crash,
+    Restart,
+    GaveUp,
+    HashrateDrop,
+    RejectedSpike,
+    PoolSwitch,
+    DailySummary,
+    /// An alert rule fired
+    Alert,
+    /// Sent from the options, to check that a webhook works
+    Test,
+}
+
+impl WebhookEventKind {
+    /// The events that can be chosen for a webhook
+    pub const ALL: [WebhookEventKind; 8] = [
+        WebhookEventKind::Crash,
+        WebhookEventKind::Restart,
+        WebhookEventKind::GaveUp,
+        WebhookEventKind::HashrateDrop,
+        WebhookEventKind::RejectedSpike,
+        WebhookEventKind::PoolSwitch,
+        WebhookEventKind::DailySummary,
+        WebhookEventKind::Alert,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            WebhookEventKind::Crash => "Crash",
+            WebhookEventKind::Restart => "Restart",
+            WebhookEventKind::GaveUp => "Gave up restarting",
+            WebhookEventKind::HashrateDrop => "Hashrate drop",
+            WebhookEventKind::RejectedSpike => "Rejected shares",
+            WebhookEventKind::PoolSwitch => "Pool switch",
+            WebhookEventKind::DailySummary => "Daily summary",
+            WebhookEventKind::Alert => "Alert rules",
+            WebhookEventKind::Test => "Test",
+        }
+    }
+}
+
+/// The shape of the request body
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    /// An object with the event, the rig, the time, a message and the details of the event
+    #[default]
+    Json,
+    /// A Slack incoming webhook message
+    Slack,
+    /// A Discord webhook message
+    Discord,
+}
+
+/// A URL that events of the miner are posted to
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Webhook {
+    pub url: String,
+    pub format: PayloadFormat,
+    pub events: BTreeSet<WebhookEventKind>,
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Webhook {
+            url: String::new(),
+            format: PayloadFormat::Json,
+            events: [
+                WebhookEventKind::Crash,
+                WebhookEventKind::GaveUp,
+                WebhookEventKind::HashrateDrop,
+            ]
+            .into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebhookEvent {
+    pub kind: WebhookEventKind,
+    pub message: String,
+    /// Fields of the event for the json format
+    pub details: serde_json::Value,
+}
+
+impl WebhookEvent {
+    fn new(kind: WebhookEventKind, message: String) -> WebhookEvent {
+        WebhookEvent {
+            kind,
+            message,
+            details: json!({}),
+        }
+    }
+}
+
+/// The request body of the event in the format
+pub fn payload(format: PayloadFormat, event: &WebhookEvent, rig: &str, time: u64) -> String {
+    let payload = match format {
+        PayloadFormat::Json => json!({
+            "event": event.kind,
+            "rig": rig,
+            "time": time,
+            "message": event.message,
+            "details": event.details,
+        }),
+        PayloadFormat::Slack => json!({ "text": format!("*{rig}*: {}", event.message) }),
+        PayloadFormat::Discord => json!({ "content": format!("**{rig}**: {}", event.message) }),
+    };
+    payload.to_string()
+}
+
+/// How often and when failed requests are tried again
+pub struct Retry {
+    pub attempts: u32,
+    /// The delay before the second attempt, which doubles for each following attempt
+    pub delay: Duration,
+}
+
+const RETRY: Retry = Retry {
+    attempts: 4,
+    delay: Duration::from_secs(2),
+};
+
+/// Posts the body to the URL, trying again after failures
+pub async fn deliver(url: &str, body: &str, retry: &Retry) -> Result<(), String> {
+    let mut delay = retry.delay;
+    let mut attempt = 1;
+    loop {
+        match post(url, body).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= retry.attempts => return Err(e),
+            Err(e) => println!("Webhook request to {url} failed, trying again in {delay:?}: {e}"),
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Posts the body, through the proxy of the environment if one is set, e.g. HTTPS_PROXY
+async fn post(url: &str, body: &str) -> Result<(), String> {
+    let (url, body) = (url.to_string(), body.to_string());
+    tokio::task::spawn_blocking(move || {
+        ureq::AgentBuilder::new()
+            .timeout(REQUEST_TIMEOUT)
+            .try_proxy_from_env(true)
+            .build()
+            .post(&url)
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()))
+}
+
+/// Sends a test event to the webhook, without trying again
+pub async fn send_test(webhook: &Webhook) -> Result<(), String> {
+    let event = WebhookEvent::new(
+        WebhookEventKind::Test,
+        "Test message from ethminer-gui".to_string(),
+    );
+    let body = payload(webhook.format, &event, &rig_name(), unix_secs());
+    let once = Retry {
+        attempts: 1,
+        delay: Duration::ZERO,
+    };
+    deliver(&webhook.url, &body, &once).await
+}
+
+/// Finds the events that aren't sent by the controller in the statistics and output
+#[derive(Default)]
+struct Detector {
+    hashrate_dropped: bool,
+    /// Rejected share counts of the last SPIKE_WINDOW
+    rejected: VecDeque<(Instant, u64)>,
+    rejected_spike: bool,
+    pool: Option<String>,
+    /// The statistics at the last daily summary
+    summarized: Option<StatsSnapshot>,
+}
+
+impl Detector {
+    fn check_stats(
+        &mut self,
+        stats: &StatsSnapshot,
+        running: bool,
+        now: Instant,
+    ) -> Vec<WebhookEvent> {
+        let mut events = Vec::new();
+
+        let average = stats.average_hashrate;
+        if !running || average <= 0.0 {
+            self.hashrate_dropped = false;
+        } else if !self.hashrate_dropped && stats.current_hashrate < average * DROP_FRACTION {
+            self.hashrate_dropped = true;
+            let mut event = WebhookEvent::new(
+                WebhookEventKind::HashrateDrop,
+                format!(
+                    "Hashrate dropped to {}, the average is {}",
+                    stats::format_hashrate(stats.current_hashrate),
+                    stats::format_hashrate(average)
+                ),
+            );
+            event.details = json!({
+                "current_hashrate": stats.current_hashrate,
+                "average_hashrate": average,
+            });
+            events.push(event);
+        } else if stats.current_hashrate >= average * RECOVERED_FRACTION {
+            self.hashrate_dropped = false;
+        }
+
+        // The statistics were reset
+        if self
+            .rejected
+            .back()
+            .is_some_and(|(_, n)| *n > stats.rejected)
+        {
+            self.rejected.clear();
+        }
+        self.rejected.push_back((now, stats.rejected));
+        while self
+            .rejected
+            .front()
+            .is_some_and(|(t, _)| now.duration_since(*t) > SPIKE_WINDOW)
+        {
+            self.rejected.pop_front();
+        }
+        let rejected = stats.rejected - self.rejected.front().map_or(0, |(_, n)| *n);
+        if rejected < SPIKE_REJECTED {
+            self.rejected_spike = false;
+        } else if !self.rejected_spike {
+            self.rejected_spike = true;
+            let mut event = WebhookEvent::new(
+                WebhookEventKind::RejectedSpike,
+                format!(
+                    "{rejected} shares were rejected in the last {} minutes",
+                    SPIKE_WINDOW.as_secs() / 60
+                ),
+            );
+            event.details = json!({ "rejected": rejected });
+            events.push(event);
+        }
+        events
+    }
+
+    fn check_line(&mut self, line: &str) -> Option<WebhookEvent> {
+        let pool = connected_pool(line)?.to_string();
+        let previous = self.pool.replace(pool.clone())?;
+        if previous == pool {
+            return None;
+        }
+        let mut event = WebhookEvent::new(
+            WebhookEventKind::PoolSwitch,
+            format!("Switched from pool {previous} to {pool}"),
+        );
+        event.details = json!({ "from": previous, "to": pool });
+        Some(event)
+    }
+
+    /// Summarizes the shares and crashes since the last summary
+    fn summary(&mut self, stats: &StatsSnapshot) -> WebhookEvent {
+        let last = self.summarized.replace(stats.clone()).unwrap_or_default();
+        // Counts from before a reset of the statistics are gone
+        let since = |now: u64, then: u64| now.checked_sub(then).unwrap_or(now);
+        let accepted = since(stats.accepted, last.accepted);
+        let rejected = since(stats.rejected, last.rejected);
+        let crashes = since(stats.crashes, last.crashes);
+        let mut event = WebhookEvent::new(
+            WebhookEventKind::DailySummary,
+            format!(
+                "In the last day: {accepted} shares accepted, {rejected} rejected and \
+                 {crashes} crashes. Average hashrate {}, mining for {}",
+                stats::format_hashrate(stats.average_hashrate),
+                stats::format_duration(stats.uptime())
+            ),
+        );
+        event.details = json!({
+            "accepted": accepted,
+            "rejected": rejected,
+            "crashes": crashes,
+            "average_hashrate": stats.average_hashrate,
+            "uptime_secs": stats.uptime_secs,
+        });
+        event
+    }
+}
+
+fn miner_event(event: MinerEvent) -> Option<WebhookEvent> {
+    let event = match event {
+        MinerEvent::Crashed => {
+            WebhookEvent::new(WebhookEventKind::Crash, "The miner crashed".to_string())
+        }
+        MinerEvent::Restarted => WebhookEvent::new(
+            WebhookEventKind::Restart,
+            "The miner was restarted after crashing".to_string(),
+        ),
+        MinerEvent::GaveUp => WebhookEvent::new(
+            WebhookEventKind::GaveUp,
+            "The miner keeps crashing, and isn't restarted anymore".to_string(),
+        ),
+        MinerEvent::Stalled(reason) => WebhookEvent::new(
+            WebhookEventKind::Restart,
+            format!("The miner stalled with {reason}, and was restarted"),
+        ),
+        MinerEvent::Alert(alert) => {
+            let mut event = WebhookEvent::new(WebhookEventKind::Alert, alert.message);
+            event.details = json!({ "severity": alert.severity });
+            event
+        }
+        // Reported as an error, the miner never ran
+        MinerEvent::SpawnFailed => return None,
+    };
+    Some(event)
+}
+
+/// Sends the events of the miner to the webhooks in the options, see
+/// app_options::follow_events
+pub async fn start(miner: &MinerHandle) {
+    let (mut events, mut lines) = match (
+        miner.subscribe_events().await,
+        miner.subscribe_lines().await,
+    ) {
+        (Some(events), Some(lines)) => (events, lines),
+        _ => return,
+    };
+    app_options::follow_events(miner, |miner, mut options| async move {
+        let mut detector = Detector::default();
+        let mut check = tokio::time::interval(CHECK_INTERVAL);
+        let start = tokio::time::Instant::now();
+        let mut summary = tokio::time::interval_at(start + SUMMARY_INTERVAL, SUMMARY_INTERVAL);
+        loop {
+            let events = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => miner_event(event).into_iter().collect(),
+                    Err(RecvError::Lagged(_)) => vec![],
+                    Err(RecvError::Closed) => return,
+                },
+                line = lines.recv() => match line {
+                    Ok(LineEvent::Push(line)) => {
+                        detector.check_line(&StyledLine::parse(&line).text).into_iter().collect()
+                    }
+                    Ok(LineEvent::Replace(_)) | Err(RecvError::Lagged(_)) => vec![],
+                    Err(RecvError::Closed) => return,
+                },
+                _ = check.tick() => {
+                    detector.check_stats(&miner.stats(), miner.state().running, Instant::now())
+                }
+                _ = summary.tick() => vec![detector.summary(&miner.stats())],
+            };
+            for event in events {
+                send(&event, options.read());
+            }
+        }
+    });
+}
+
+/// Sends the event to the webhooks of the options that subscribed to it
+fn send(event: &WebhookEvent, options: &AppOptions) {
+    println!("{}", event.message);
+    let (rig, time) = (rig_name(), unix_secs());
+    for webhook in options.webhooks.iter().cloned() {
+        if !webhook.events.contains(&event.kind) {
+            continue;
+        }
+        let body = payload(webhook.format, event, &rig, time);
+        tokio::spawn(async move {
+            if let Err(e) = deliver(&webhook.url, &body, &RETRY).await {
+                println!("Could not send the webhook to {}: {e}", webhook.url);
+            }
+        });
+    }
+}
+
+/// Name of the machine, so a channel can tell the rigs apart
+fn rig_name() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "ethminer-gui".to_string())
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::{Alert, Severity};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn crash() -> WebhookEvent {
+        miner_event(MinerEvent::Crashed).unwrap()
+    }
+
+    #[test]
+    fn test_payload_formats() {
+        let json: serde_json::Value =
+            serde_json::from_str(&payload(PayloadFormat::Json, &crash(), "rig1", 1000)).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "event": "crash",
+                "rig": "rig1",
+                "time": 1000,
+                "message": "The miner crashed",
+                "details": {},
+            })
+        );
+        assert_eq!(
+            payload(PayloadFormat::Slack, &crash(), "rig1", 1000),
+            r#"{"text":"*rig1*: The miner crashed"}"#
+        );
+        assert_eq!(
+            payload(PayloadFormat::Discord, &crash(), "rig1", 1000),
+            r#"{"content":"**rig1**: The miner crashed"}"#
+        );
+
+        let alert = miner_event(MinerEvent::Alert(Alert {
+            severity: Severity::Critical,
+            message: "GPU 0 is at 85°C, above 80°C".to_string(),
+        }))
+        .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&payload(PayloadFormat::Json, &alert, "rig1", 1000)).unwrap();
+        assert_eq!(json["event"], "alert");
+        assert_eq!(json["details"], json!({ "severity": "critical" }));
+    }
+
+    #[test]
+    fn test_detect_hashrate_drop() {
+        let mut detector = Detector::default();
+        let now = Instant::now();
+        let mut stats = StatsSnapshot {
+            current_hashrate: 30e6,
+            average_hashrate: 30e6,
+            ..Default::default()
+        };
+        assert!(detector.check_stats(&stats, true, now).is_empty());
+
+        stats.current_hashrate = 10e6;
+        let events = detector.check_stats(&stats, true, now);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, WebhookEventKind::HashrateDrop);
+        assert_eq!(
+            events[0].message,
+            "Hashrate dropped to 10.00 MH/s, the average is 30.00 MH/s"
+        );
+        // Sent once until the hashrate recovered
+        assert!(detector.check_stats(&stats, true, now).is_empty());
+        stats.current_hashrate = 20e6;
+        assert!(detector.check_stats(&stats, true, now).is_empty());
+        stats.current_hashrate = 29e6;
+        assert!(detector.check_stats(&stats, true, now).is_empty());
+        stats.current_hashrate = 10e6;
+        assert_eq!(detector.check_stats(&stats, true, now).len(), 1);
+
+        // A stopped miner has no hashrate
+        stats.current_hashrate = 0.0;
+        assert!(detector.check_stats(&stats, false, now).is_empty());
+    }
+
+    #[test]
+    fn test_detect_rejected_spike() {
+        let mut detector = Detector::default();
+        let start = Instant::now();
+        let mut stats = StatsSnapshot {
+            rejected: 10,
+            ..Default::default()
+        };
+        assert!(detector.check_stats(&stats, true, start).is_empty());
+
+        // Rejected shares spread over a long time aren't a spike
+        for minute in 1..=30 {
+            stats.rejected += 1;
+            let now = start + Duration::from_secs(minute * 3 * 60);
+            assert!(detector.check_stats(&stats, true, now).is_empty());
+        }
+
+        let start = start + Duration::from_secs(2 * 60 * 60);
+        assert!(detector.check_stats(&stats, true, start).is_empty());
+        stats.rejected += SPIKE_REJECTED;
+        let now = start + Duration::from_secs(60);
+        let events = detector.check_stats(&stats, true, now);
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].message,
+            "5 shares were rejected in the last 10 minutes"
+        );
+        assert!(detector.check_stats(&stats, true, now).is_empty());
+
+        // Resetting the statistics isn't a spike either
+        stats.rejected = 0;
+        assert!(detector.check_stats(&stats, true, now).is_empty());
+    }
+
+    #[test]
+    fn test_detect_pool_switch() {
+        let mut detector = Detector::default();
+        let connected = |pool: &str| {
+            format!(" i 17:29:36 ethminer Established connection to {pool} [1.2.3.4:4444]")
+        };
+        assert_eq!(detector.check_line(&connected("eu1.ethermine.org")), None);
+        assert_eq!(detector.check_line(&connected("eu1.ethermine.org")), None);
+        assert_eq!(detector.check_line(" m 17:29:40 ethminer 30.00 Mh"), None);
+        let event = detector
+            .check_line(&connected("us1.ethermine.org"))
+            .unwrap();
+        assert_eq!(event.kind, WebhookEventKind::PoolSwitch);
+        assert_eq!(
+            event.details,
+            json!({ "from": "eu1.ethermine.org", "to": "us1.ethermine.org" })
+        );
+    }
+
+    #[test]
+    fn test_daily_summary() {
+        let mut detector = Detector::default();
+        let mut stats = StatsSnapshot {
+            accepted: 100,
+            rejected: 2,
+            crashes: 1,
+            ..Default::default()
+        };
+        assert_eq!(detector.summary(&stats).details["accepted"], 100);
+        stats.accepted = 150;
+        let event = detector.summary(&stats);
+        assert_eq!(event.details["accepted"], 50);
+        assert_eq!(event.details["rejected"], 0);
+        assert_eq!(event.details["crashes"], 0);
+    }
+
+    /// Answers each request with the next status, and returns the bodies of the requests
+    async fn serve(statuses: Vec<u16>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    stream.read_line(&mut header).await.unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                let response = format!(
+                    "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                );
+                stream
+                    .get_mut()
+                    .write_all(response.as_bytes())
+                    .await
+                    .unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries() {
+        let retry = Retry {
+            attempts: 3,
+            delay: Duration::from_millis(10),
+        };
+        let body = payload(PayloadFormat::Slack, &crash(), "rig1", 1000);
+        let (url, server) = serve(vec![500, 503, 200]).await;
+        deliver(&url, &body, &retry).await.unwrap();
+        assert_eq!(server.await.unwrap(), vec![body.clone(); 3]);
+
+        let (url, server) = serve(vec![500, 500, 500]).await;
+        let error = deliver(&url, &body, &retry).await.unwrap_err();
+        assert!(error.contains("500"), "{error}");
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_test() {
+        let (url, server) = serve(vec![200]).await;
+        let webhook = Webhook {
+            url,
+            format: PayloadFormat::Discord,
+            ..Default::default()
+        };
+        send_test(&webhook).await.unwrap();
+        let bodies = server.await.unwrap();
+        assert!(bodies[0].contains("Test message from ethminer-gui"));
+    }
+}